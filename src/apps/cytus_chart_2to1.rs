@@ -1,4 +1,9 @@
-use crate::cylheim_tools::CylheimChart;
+use crate::cylheim_tools::{
+    batch_convert::{batch_convert_directory, BATCH_SUMMARY_FILE_NAME},
+    chart_id_map::{get_note_id_map_path, NoteIdMap},
+    ConversionOptions, ConversionReport, CylheimChart, CylheimChartLintReport, Cytus1NoteMapping,
+    LintSeverity, PageSizeStrategy,
+};
 use eframe::egui::{self, CentralPanel, RichText};
 use rfd::FileDialog;
 use std::{
@@ -12,6 +17,28 @@ use std::{
 pub struct C2to1Tool {
     selected_file: Option<PathBuf>,
     message: String,
+    reflow_page_size: bool,
+    substitute_notes: bool,
+    clamp_x: bool,
+}
+
+impl C2to1Tool {
+    fn get_conversion_options(&self) -> ConversionOptions {
+        ConversionOptions {
+            page_size_strategy: if self.reflow_page_size {
+                PageSizeStrategy::Reflow
+            } else {
+                PageSizeStrategy::Strict
+            },
+            note_mapping: if self.substitute_notes {
+                Cytus1NoteMapping::substitute_all()
+            } else {
+                Cytus1NoteMapping::default()
+            },
+            clamp_x: self.clamp_x,
+            ..Default::default()
+        }
+    }
 }
 
 impl eframe::App for C2to1Tool {
@@ -21,6 +48,10 @@ impl eframe::App for C2to1Tool {
                 ui.add_space(15.0);
                 ui.heading("Cytus Chart 2 to 1 Converter");
                 ui.add_space(15.0);
+                ui.checkbox(&mut self.reflow_page_size, "Allow uneven page sizes");
+                ui.checkbox(&mut self.substitute_notes, "Substitute Cytus 2 note types");
+                ui.checkbox(&mut self.clamp_x, "Clamp notes outside the screen");
+                ui.add_space(15.0);
                 if ui.button("Select File").clicked() {
                     if let Some(path) = FileDialog::new()
                         .add_filter("text", &["txt", "json"])
@@ -29,8 +60,10 @@ impl eframe::App for C2to1Tool {
                         if let Ok(f) = fs::read_to_string(&path) {
                             let result: Result<CylheimChart, _> = serde_json::from_str(&f);
                             if let Ok(cylchart) = result {
-                                match cylchart.to_cytus1_chart_with_pageshift(false) {
-                                    Ok(chart) => {
+                                match cylchart
+                                    .to_cytus1_chart_with_options(&self.get_conversion_options())
+                                {
+                                    Ok((chart, report)) => {
                                         self.selected_file = Some(path.clone());
                                         let new_file_path = save_new_file(
                                             &path,
@@ -39,24 +72,81 @@ impl eframe::App for C2to1Tool {
                                             "txt",
                                         )
                                         .unwrap();
-                                        self.message = format!(
-                                            "File processed successfully: {:?}",
-                                            new_file_path.file_name().unwrap()
-                                        );
+                                        let id_map = NoteIdMap::from_conversion(&cylchart, &report);
+                                        let id_map_path = get_note_id_map_path(&new_file_path);
+                                        let id_map_result = serde_json::to_string_pretty(&id_map)
+                                            .map_err(io::Error::from)
+                                            .and_then(|json| fs::write(&id_map_path, json));
+                                        match id_map_result {
+                                            Ok(()) => {
+                                                self.message = format!(
+                                                    "File processed successfully: {:?}",
+                                                    new_file_path.file_name().unwrap()
+                                                );
+                                                if !report.is_lossless() {
+                                                    self.message += &format!(
+                                                        "\n{}",
+                                                        format_conversion_report(&report)
+                                                    );
+                                                }
+                                            }
+                                            Err(err) => {
+                                                self.message = format!(
+                                                    "Error writing note id map {}: {}",
+                                                    id_map_path.display(),
+                                                    err
+                                                );
+                                            }
+                                        }
                                     }
                                     Err(err) => {
                                         self.selected_file = Some(path.clone());
-                                        self.message = format!("Error processing file: {:?}", err);
+                                        self.message = format!("Error processing file: {}", err);
                                     }
                                 }
                             } else {
                                 self.selected_file = Some(path.clone());
                                 self.message =
-                                    format!("File you selected is not a valid Cytus2 chart.");
+                                    "File you selected is not a valid Cytus2 chart.".to_string();
                             }
                         } else {
                             self.selected_file = Some(path.clone());
-                            self.message = format!("File you selected is not a text file.");
+                            self.message = "File you selected is not a text file.".to_string();
+                        }
+                    }
+                }
+                ui.add_space(15.0);
+                if ui.button("Check File").clicked() {
+                    if let Some(path) = FileDialog::new().add_filter("json", &["json"]).pick_file()
+                    {
+                        self.selected_file = Some(path.clone());
+                        self.message = match fs::read_to_string(&path)
+                            .ok()
+                            .and_then(|f| serde_json::from_str::<CylheimChart>(&f).ok())
+                        {
+                            Some(cylchart) => format_lint_report(&cylchart.lint()),
+                            None => "File you selected is not a valid Cytus2 chart.".to_string(),
+                        };
+                    }
+                }
+                ui.add_space(15.0);
+                if ui.button("Select Folder").clicked() {
+                    if let Some(input_dir) = FileDialog::new().pick_folder() {
+                        if let Some(output_dir) = FileDialog::new()
+                            .set_title("Select Output Folder")
+                            .pick_folder()
+                        {
+                            let options = self.get_conversion_options();
+                            self.message =
+                                match batch_convert_directory(&input_dir, &output_dir, &options) {
+                                    Ok(summary) => format!(
+                                        "{} charts converted, {} failed.\nSee {} for details.",
+                                        summary.success_count(),
+                                        summary.failure_count(),
+                                        BATCH_SUMMARY_FILE_NAME
+                                    ),
+                                    Err(err) => format!("Error processing folder: {}", err),
+                                };
                         }
                     }
                 }
@@ -67,6 +157,46 @@ impl eframe::App for C2to1Tool {
     }
 }
 
+/// 最多显示的问题条数，其余只计数
+const MAX_SHOWN_ISSUES: usize = 10;
+fn format_lint_report(report: &CylheimChartLintReport) -> String {
+    let mut lines = vec![format!(
+        "{} errors, {} warnings, {} infos.",
+        report.count(LintSeverity::Error),
+        report.count(LintSeverity::Warning),
+        report.count(LintSeverity::Info)
+    )];
+    lines.extend(
+        report
+            .issues()
+            .iter()
+            .take(MAX_SHOWN_ISSUES)
+            .map(|issue| issue.to_string()),
+    );
+    if report.issues().len() > MAX_SHOWN_ISSUES {
+        lines.push(format!(
+            "... and {} more.",
+            report.issues().len() - MAX_SHOWN_ISSUES
+        ));
+    }
+    lines.join("\n")
+}
+
+fn format_conversion_report(report: &ConversionReport) -> String {
+    let mut messages = report.get_messages();
+    // 位置偏移的汇总行总在最后，截断时保留
+    let summary = messages.pop().unwrap_or_default();
+    let mut lines: Vec<String> = messages.iter().take(MAX_SHOWN_ISSUES).cloned().collect();
+    if messages.len() > MAX_SHOWN_ISSUES {
+        lines.push(format!(
+            "... and {} more.",
+            messages.len() - MAX_SHOWN_ISSUES
+        ));
+    }
+    lines.push(summary);
+    lines.join("\n")
+}
+
 pub fn save_new_file(
     original_path: &Path,
     content: &str,
@@ -75,10 +205,10 @@ pub fn save_new_file(
 ) -> io::Result<PathBuf> {
     let mut new_file_path = original_path.to_path_buf();
     if let Some(stem) = new_file_path.file_stem() {
-        let new_file_name = format!("{}_{}.{}", stem.to_string_lossy(), msg.to_string(), ext);
+        let new_file_name = format!("{}_{}.{}", stem.to_string_lossy(), msg, ext);
         new_file_path.set_file_name(new_file_name);
     }
     let mut file = File::create(&new_file_path)?;
-    file.write(content.as_bytes())?;
+    file.write_all(content.as_bytes())?;
     Ok(new_file_path)
 }
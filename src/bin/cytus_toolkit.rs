@@ -4,17 +4,12 @@
 use cyl_package_tool::C2to1Tool;
 use eframe::egui::{self, Color32, Frame, Margin, Stroke};
 // 创建应用选择枚举
-#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
 enum Anchor {
+    #[default]
     C2to1Tool,
 }
 
-impl Default for Anchor {
-    fn default() -> Self {
-        Self::C2to1Tool
-    }
-}
-
 #[derive(Default)]
 struct MainApp {
     c2to1_tool: C2to1Tool,
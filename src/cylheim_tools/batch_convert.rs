@@ -0,0 +1,260 @@
+use super::{
+    chart_id_map::{get_note_id_map_path, NoteIdMap},
+    utils::{resolve_cyl_path, CylToolError},
+    ConversionOptions, ConversionReport, CylheimChart, CylheimProjectConfig,
+};
+use getset::Getters;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs,
+    path::{Component, Path, PathBuf},
+};
+use walkdir::WalkDir;
+
+pub const BATCH_SUMMARY_FILE_NAME: &str = "batch_summary.json";
+#[derive(Serialize, Deserialize, Debug, Clone, Getters)]
+pub struct BatchConvertEntry {
+    #[getset(get = "pub")]
+    input: PathBuf,
+    /// None means the chart was not converted.
+    #[getset(get = "pub")]
+    output: Option<PathBuf>,
+    #[getset(get = "pub")]
+    position_drift_count: usize,
+    #[getset(get = "pub")]
+    substitution_count: usize,
+    #[getset(get = "pub")]
+    dropped_note_count: usize,
+    #[getset(get = "pub")]
+    clamped_note_count: usize,
+    #[getset(get = "pub")]
+    error: Option<String>,
+}
+#[derive(Serialize, Deserialize, Debug, Clone, Default, Getters)]
+pub struct BatchConvertSummary {
+    #[getset(get = "pub")]
+    entries: Vec<BatchConvertEntry>,
+}
+impl BatchConvertSummary {
+    pub fn success_count(&self) -> usize {
+        self.entries
+            .iter()
+            .filter(|entry| entry.error.is_none())
+            .count()
+    }
+    pub fn failure_count(&self) -> usize {
+        self.entries.len() - self.success_count()
+    }
+}
+
+/// Collect Cylheim charts under `input_dir`, found directly or through `.cyl` projects.
+///
+/// A referenced chart that does not exist is still returned so its error can be reported.
+/// A referenced chart outside `input_dir` is reported as an error instead.
+fn find_cylheim_charts(input_dir: &Path) -> (BTreeSet<PathBuf>, Vec<BatchConvertEntry>) {
+    let mut chart_paths: BTreeSet<PathBuf> = BTreeSet::new();
+    // 目录外的谱面及引用它的项目
+    let mut outside_paths: BTreeMap<PathBuf, PathBuf> = BTreeMap::new();
+    let mut errors: Vec<BatchConvertEntry> = Vec::new();
+    let mut add_error = |input: PathBuf, error: String| {
+        errors.push(BatchConvertEntry {
+            input,
+            output: None,
+            position_drift_count: 0,
+            substitution_count: 0,
+            dropped_note_count: 0,
+            clamped_note_count: 0,
+            error: Some(error),
+        })
+    };
+    for entry in WalkDir::new(input_dir).sort_by_file_name() {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(err) => {
+                let path = err.path().unwrap_or(input_dir).to_path_buf();
+                add_error(path, err.to_string());
+                continue;
+            }
+        };
+        let path = entry.path();
+        let extension = path
+            .extension()
+            .map(|ext| ext.to_string_lossy().to_lowercase());
+        match extension.as_deref() {
+            Some("json") => {
+                // 只收集带有 note_list 的 JSON，跳过 level.json 等其他文件
+                let is_chart = fs::read_to_string(path)
+                    .ok()
+                    .and_then(|f| serde_json::from_str::<serde_json::Value>(&f).ok())
+                    .is_some_and(|value| value.get("note_list").is_some());
+                if is_chart {
+                    chart_paths.insert(path.to_path_buf());
+                }
+            }
+            Some("cyl") => {
+                let config = fs::read_to_string(path)
+                    .map_err(|err| err.to_string())
+                    .and_then(|f| {
+                        serde_json::from_str::<CylheimProjectConfig>(&f)
+                            .map_err(|err| err.to_string())
+                    });
+                let project_dir = path.parent().unwrap_or(input_dir);
+                match config {
+                    Ok(config) => {
+                        for chart_info in &config.chart_infos {
+                            let chart_path = resolve_cyl_path(project_dir, &chart_info.chart_path);
+                            // `..` 也可能离开输入目录，因此逐个检查相对路径的组成部分
+                            let is_inside = chart_path.strip_prefix(input_dir).is_ok_and(|path| {
+                                path.components()
+                                    .all(|component| matches!(component, Component::Normal(_)))
+                            });
+                            if is_inside {
+                                chart_paths.insert(chart_path);
+                            } else {
+                                outside_paths
+                                    .entry(chart_path)
+                                    .or_insert_with(|| path.to_path_buf());
+                            }
+                        }
+                    }
+                    Err(err) => add_error(path.to_path_buf(), err),
+                }
+            }
+            _ => {}
+        }
+    }
+    for (chart_path, cyl_path) in outside_paths {
+        let error = format!(
+            "Chart referenced by {} is outside {}.",
+            cyl_path.display(),
+            input_dir.display()
+        );
+        add_error(chart_path, error);
+    }
+    (chart_paths, errors)
+}
+fn convert_chart_file(
+    input: &Path,
+    output: &Path,
+    options: &ConversionOptions,
+) -> Result<ConversionReport, CylToolError> {
+    let f = fs::read_to_string(input).map_err(|err| CylToolError::from(err).at_path(input))?;
+    let chart: CylheimChart =
+        serde_json::from_str(&f).map_err(|err| CylToolError::from(err).at_path(input))?;
+    let (cytus1_chart, report) = chart.to_cytus1_chart_with_options(options)?;
+    if let Some(parent) = output.parent() {
+        fs::create_dir_all(parent).map_err(|err| CylToolError::from(err).at_path(parent))?;
+    }
+    fs::write(output, cytus1_chart.to_string())
+        .map_err(|err| CylToolError::from(err).at_path(output))?;
+    let id_map_path = get_note_id_map_path(output);
+    let id_map_json = serde_json::to_string_pretty(&NoteIdMap::from_conversion(&chart, &report))?;
+    fs::write(&id_map_path, id_map_json)
+        .map_err(|err| CylToolError::from(err).at_path(&id_map_path))?;
+    Ok(report)
+}
+/// Convert every Cylheim chart under `input_dir` to Cytus 1, mirroring the folder layout
+/// into `output_dir` with a note id map next to each chart, and write the summary there as
+/// `batch_summary.json`.
+pub fn batch_convert_directory(
+    input_dir: &Path,
+    output_dir: &Path,
+    options: &ConversionOptions,
+) -> Result<BatchConvertSummary, CylToolError> {
+    if !input_dir.is_dir() {
+        return Err(CylToolError::NotADirectory {
+            path: input_dir.to_path_buf(),
+        });
+    }
+    let (chart_paths, mut entries) = find_cylheim_charts(input_dir);
+    for input in chart_paths {
+        let relative_path = input.strip_prefix(input_dir).unwrap_or(&input);
+        let output = output_dir.join(relative_path).with_extension("txt");
+        entries.push(match convert_chart_file(&input, &output, options) {
+            Ok(report) => BatchConvertEntry {
+                input,
+                output: Some(output),
+                position_drift_count: report.position_drifts().len(),
+                substitution_count: report.substitutions().len(),
+                dropped_note_count: report.dropped_notes().len(),
+                clamped_note_count: report.clamped_notes().len(),
+                error: None,
+            },
+            Err(err) => BatchConvertEntry {
+                input,
+                output: None,
+                position_drift_count: 0,
+                substitution_count: 0,
+                dropped_note_count: 0,
+                clamped_note_count: 0,
+                error: Some(err.to_string()),
+            },
+        });
+    }
+    entries.sort_by(|a, b| a.input.cmp(&b.input));
+    let summary = BatchConvertSummary { entries };
+    fs::create_dir_all(output_dir).map_err(|err| CylToolError::from(err).at_path(output_dir))?;
+    let summary_json = serde_json::to_string_pretty(&summary)?;
+    let summary_path = output_dir.join(BATCH_SUMMARY_FILE_NAME);
+    fs::write(&summary_path, summary_json)
+        .map_err(|err| CylToolError::from(err).at_path(&summary_path))?;
+    Ok(summary)
+}
+#[cfg(test)]
+mod test {
+    use super::*;
+    const TEST_RESOURCE_ROOT: &str = "./tests/resources/";
+    const TEST_OUTPUT_ROOT: &str = "./tests/output/";
+    #[test]
+    fn test_batch_convert_directory() {
+        let input_dir = PathBuf::from(TEST_OUTPUT_ROOT).join("batch_input");
+        let output_dir = PathBuf::from(TEST_OUTPUT_ROOT).join("batch_output");
+        let _ = fs::remove_dir_all(&input_dir);
+        let _ = fs::remove_dir_all(&output_dir);
+        fs::create_dir_all(input_dir.join("nested/deeper")).unwrap();
+        fs::copy(
+            TEST_RESOURCE_ROOT.to_owned() + "test_valid_c2to1_chart.json",
+            input_dir.join("valid.json"),
+        )
+        .unwrap();
+        fs::copy(
+            TEST_RESOURCE_ROOT.to_owned() + "test_cyl_chart.json",
+            input_dir.join("nested/deeper/uneven.json"),
+        )
+        .unwrap();
+        fs::write(input_dir.join("nested/level.json"), "{\"id\": \"level\"}").unwrap();
+        fs::write(
+            input_dir.join("nested/project.cyl"),
+            r#"{"ChartInfos": [{"DiffName": "HARD", "Diff": "5", "FileName": "charts\\missing.json",
+                "Media": "", "Video": "", "Bg": "", "Icon": "", "SongName": "", "ThemeColor": "",
+                "DiffTextColor": "", "DiffBgColor": "", "Bookmarks": []}, {"DiffName": "EASY",
+                "Diff": "1", "FileName": "..\\..\\outside.json", "Media": "", "Video": "",
+                "Bg": "", "Icon": "", "SongName": "", "ThemeColor": "", "DiffTextColor": "",
+                "DiffBgColor": "", "Bookmarks": []}], "LastEditedTime": 0.0}"#,
+        )
+        .unwrap();
+        let summary =
+            batch_convert_directory(&input_dir, &output_dir, &ConversionOptions::default())
+                .unwrap();
+        assert_eq!(summary.entries().len(), 4);
+        assert_eq!(summary.success_count(), 1);
+        assert!(output_dir.join("valid.txt").exists());
+        assert!(output_dir.join("valid.ids.json").exists());
+        assert!(output_dir.join(BATCH_SUMMARY_FILE_NAME).exists());
+        let failed: Vec<&PathBuf> = summary
+            .entries()
+            .iter()
+            .filter(|entry| entry.error().is_some())
+            .map(|entry| entry.input())
+            .collect();
+        assert_eq!(
+            failed,
+            vec![
+                &input_dir.join("nested/../../outside.json"),
+                &input_dir.join("nested/charts/missing.json"),
+                &input_dir.join("nested/deeper/uneven.json"),
+            ]
+        );
+    }
+}
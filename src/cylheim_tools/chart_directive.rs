@@ -0,0 +1,198 @@
+use super::{cyl_chart::UnsupportedNoteStrategy, CylheimChart};
+use getset::Getters;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+const DEFINE_KEYWORD: &str = "#DEFINE";
+
+/// Format a directive chart is meant to be exported to.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ExportTarget {
+    Cytus1,
+    Cytoid,
+}
+/// One `#DEFINE <NAME> [VALUE]` directive read from a directive event.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum CylheimChartDirective {
+    /// `#DEFINE CYTUS1`: the chart is laid out for Cytus 1 export.
+    Cytus1,
+    /// `#DEFINE PAGE_SHIFT <seconds>`: PAGE_SHIFT written to the Cytus 1 chart.
+    PageShift(f64),
+    /// `#DEFINE UNSUPPORTED_NOTE error|drop`: what to do with notes Cytus 1 cannot show.
+    UnsupportedNote(UnsupportedNoteStrategy),
+    /// `#DEFINE EXPORT cytus1|cytoid`: the format the chart is meant for.
+    Export(ExportTarget),
+}
+impl CylheimChartDirective {
+    /// Whether a directive event starts with `#DEFINE`. Other `#` texts are left to the user.
+    pub fn is_define(text: &str) -> bool {
+        text.split_whitespace()
+            .next()
+            .is_some_and(|keyword| keyword.eq_ignore_ascii_case(DEFINE_KEYWORD))
+    }
+    /// Parse the text of a directive event.
+    pub fn parse(text: &str) -> Result<Self, String> {
+        if !Self::is_define(text) {
+            return Err(format!("expected {} <NAME> [VALUE]", DEFINE_KEYWORD));
+        }
+        let mut tokens = text.split_whitespace().skip(1);
+        let name = tokens
+            .next()
+            .ok_or_else(|| "missing directive name".to_string())?
+            .to_uppercase();
+        let value = tokens.next();
+        if tokens.next().is_some() {
+            return Err(format!("too many values for {}", name));
+        }
+        let require_value = || value.ok_or_else(|| format!("{} needs a value", name));
+        match name.as_str() {
+            "CYTUS1" => match value {
+                None => Ok(CylheimChartDirective::Cytus1),
+                Some(_) => Err("CYTUS1 takes no value".to_string()),
+            },
+            "PAGE_SHIFT" => {
+                let value = require_value()?;
+                match value.parse::<f64>() {
+                    // Cytus 1 也接受负的 PAGE_SHIFT
+                    Ok(page_shift) if page_shift.is_finite() => {
+                        Ok(CylheimChartDirective::PageShift(page_shift))
+                    }
+                    _ => Err(format!("invalid page shift {}", value)),
+                }
+            }
+            "UNSUPPORTED_NOTE" => match require_value()?.to_lowercase().as_str() {
+                "error" => Ok(CylheimChartDirective::UnsupportedNote(
+                    UnsupportedNoteStrategy::Error,
+                )),
+                "drop" => Ok(CylheimChartDirective::UnsupportedNote(
+                    UnsupportedNoteStrategy::Drop,
+                )),
+                value => Err(format!("unknown unsupported note strategy {}", value)),
+            },
+            "EXPORT" => match require_value()?.to_lowercase().as_str() {
+                "cytus1" => Ok(CylheimChartDirective::Export(ExportTarget::Cytus1)),
+                "cytoid" => Ok(CylheimChartDirective::Export(ExportTarget::Cytoid)),
+                value => Err(format!("unknown export target {}", value)),
+            },
+            name => Err(format!("unknown directive {}", name)),
+        }
+    }
+}
+/// A directive event that could not be applied.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Getters)]
+pub struct DirectiveWarning {
+    #[getset(get = "pub")]
+    tick: u32,
+    #[getset(get = "pub")]
+    directive: String,
+    #[getset(get = "pub")]
+    message: String,
+}
+impl fmt::Display for DirectiveWarning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Directive \"{}\" at tick {}: {}.",
+            self.directive, self.tick, self.message
+        )
+    }
+}
+/// Tool options collected from the directive events of a chart.
+///
+/// Later directives override earlier ones. `#DEFINE` directives that cannot be read are
+/// skipped and listed in `warnings`; other `#` texts are ignored.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct CylheimChartDirectives {
+    pub cytus1: bool,
+    pub page_shift: Option<f64>,
+    pub unsupported_note_strategy: Option<UnsupportedNoteStrategy>,
+    pub export_target: Option<ExportTarget>,
+    pub warnings: Vec<DirectiveWarning>,
+}
+impl CylheimChart {
+    /// Read every directive event of the chart.
+    pub fn get_directive_options(&self) -> CylheimChartDirectives {
+        let mut directives = CylheimChartDirectives::default();
+        for (tick, text) in self.get_directives() {
+            if !CylheimChartDirective::is_define(&text) {
+                continue;
+            }
+            let mut warn = |message: String| {
+                directives.warnings.push(DirectiveWarning {
+                    tick,
+                    directive: text.clone(),
+                    message,
+                })
+            };
+            let directive = match CylheimChartDirective::parse(&text) {
+                Ok(directive) => directive,
+                Err(message) => {
+                    warn(message);
+                    continue;
+                }
+            };
+            match directive {
+                CylheimChartDirective::Cytus1 => directives.cytus1 = true,
+                CylheimChartDirective::PageShift(page_shift) => {
+                    if directives.page_shift.replace(page_shift).is_some() {
+                        warn("overrides an earlier PAGE_SHIFT".to_string());
+                    }
+                }
+                CylheimChartDirective::UnsupportedNote(strategy) => {
+                    if directives
+                        .unsupported_note_strategy
+                        .replace(strategy)
+                        .is_some()
+                    {
+                        warn("overrides an earlier UNSUPPORTED_NOTE".to_string());
+                    }
+                }
+                CylheimChartDirective::Export(target) => {
+                    if directives.export_target.replace(target).is_some() {
+                        warn("overrides an earlier EXPORT".to_string());
+                    }
+                }
+            }
+        }
+        directives
+    }
+}
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cylheim_tools::CylheimChartEventKind;
+    #[test]
+    fn test_directive_options() {
+        let mut chart = CylheimChart::default();
+        chart.add_event(0, CylheimChartEventKind::new_directive("#DEFINE CYTUS1"));
+        chart.add_event(0, CylheimChartEventKind::new_text("hello"));
+        chart.add_event(0, CylheimChartEventKind::new_directive("#note to self"));
+        chart.add_event(
+            0,
+            CylheimChartEventKind::new_directive("#define page_shift 0.5"),
+        );
+        chart.add_event(
+            0,
+            CylheimChartEventKind::new_directive("#DEFINE EXPORT cytus1"),
+        );
+        chart.add_event(480, CylheimChartEventKind::new_directive("#DEFINE FOO"));
+        chart.add_event(
+            480,
+            CylheimChartEventKind::new_directive("#DEFINE PAGE_SHIFT x"),
+        );
+        let directives = chart.get_directive_options();
+        assert!(directives.cytus1);
+        assert_eq!(directives.page_shift, Some(0.5));
+        assert_eq!(directives.export_target, Some(ExportTarget::Cytus1));
+        assert_eq!(directives.unsupported_note_strategy, None);
+        assert_eq!(directives.warnings.len(), 2);
+        assert_eq!(*directives.warnings[0].tick(), 480);
+        assert_eq!(directives.warnings[0].message(), "unknown directive FOO");
+        assert_eq!(
+            CylheimChartDirective::parse("#DEFINE PAGE_SHIFT -0.25"),
+            Ok(CylheimChartDirective::PageShift(-0.25))
+        );
+        assert!(CylheimChartDirective::parse("#DEFINE PAGE_SHIFT inf").is_err());
+    }
+}
@@ -0,0 +1,236 @@
+use super::{
+    cyl_chart::{CylheimChartEvent, CylheimChartTickEventList},
+    CylheimChart,
+};
+use serde::{Deserialize, Serialize};
+
+const SPEED_UP_EVENT_TYPE: u32 = 0;
+const SPEED_DOWN_EVENT_TYPE: u32 = 1;
+const TEXT_EVENT_TYPE: u32 = 8;
+/// Text events starting with this prefix are tool directives rather than visible text.
+const DIRECTIVE_PREFIX: char = '#';
+
+/// Parsed form of a `CylheimChartEvent`.
+///
+/// Converting back with `into_event` gives the original type and args, so unknown events
+/// survive untouched.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum CylheimChartEventKind {
+    /// Scanline speed up. `args` is kept as written.
+    SpeedUp {
+        args: String,
+    },
+    /// Scanline speed down. `args` is kept as written.
+    SpeedDown {
+        args: String,
+    },
+    /// Text shown in game, with an optional `#RRGGBB` color.
+    Text {
+        text: String,
+        color: Option<String>,
+    },
+    /// A text event starting with `#`, read by tools and not meant to be shown.
+    Directive {
+        directive: String,
+        color: Option<String>,
+    },
+    Unknown {
+        event_type: u32,
+        args: String,
+    },
+}
+/// Split `text,#color` args. Args without a trailing color are all text.
+fn split_text_args(args: &str) -> (String, Option<String>) {
+    match args.rsplit_once(',') {
+        Some((text, color)) if color.starts_with('#') => {
+            (text.to_string(), Some(color.to_string()))
+        }
+        _ => (args.to_string(), None),
+    }
+}
+fn join_text_args(text: &str, color: &Option<String>) -> String {
+    match color {
+        Some(color) => format!("{},{}", text, color),
+        None => text.to_string(),
+    }
+}
+impl CylheimChartEventKind {
+    pub(crate) fn from_event(event: &CylheimChartEvent) -> Self {
+        let args = event.event_args().clone();
+        match *event.event_type() {
+            SPEED_UP_EVENT_TYPE => CylheimChartEventKind::SpeedUp { args },
+            SPEED_DOWN_EVENT_TYPE => CylheimChartEventKind::SpeedDown { args },
+            TEXT_EVENT_TYPE => {
+                let (text, color) = split_text_args(&args);
+                if text.starts_with(DIRECTIVE_PREFIX) {
+                    CylheimChartEventKind::Directive {
+                        directive: text,
+                        color,
+                    }
+                } else {
+                    CylheimChartEventKind::Text { text, color }
+                }
+            }
+            event_type => CylheimChartEventKind::Unknown { event_type, args },
+        }
+    }
+    pub(crate) fn into_event(self) -> CylheimChartEvent {
+        match self {
+            CylheimChartEventKind::SpeedUp { args } => {
+                CylheimChartEvent::new(SPEED_UP_EVENT_TYPE, &args)
+            }
+            CylheimChartEventKind::SpeedDown { args } => {
+                CylheimChartEvent::new(SPEED_DOWN_EVENT_TYPE, &args)
+            }
+            CylheimChartEventKind::Text { text, color } => {
+                CylheimChartEvent::new(TEXT_EVENT_TYPE, &join_text_args(&text, &color))
+            }
+            CylheimChartEventKind::Directive { directive, color } => {
+                CylheimChartEvent::new(TEXT_EVENT_TYPE, &join_text_args(&directive, &color))
+            }
+            CylheimChartEventKind::Unknown { event_type, args } => {
+                CylheimChartEvent::new(event_type, &args)
+            }
+        }
+    }
+    /// A white text event.
+    pub fn new_text(text: &str) -> Self {
+        CylheimChartEventKind::Text {
+            text: text.to_string(),
+            color: Some("#FFFFFF".to_string()),
+        }
+    }
+    /// A directive event such as `#DEFINE CYTUS1`.
+    pub fn new_directive(directive: &str) -> Self {
+        CylheimChartEventKind::Directive {
+            directive: directive.to_string(),
+            color: Some("#FFFFFF".to_string()),
+        }
+    }
+    pub fn is_speed_change(&self) -> bool {
+        matches!(
+            self,
+            CylheimChartEventKind::SpeedUp { .. } | CylheimChartEventKind::SpeedDown { .. }
+        )
+    }
+}
+impl From<&CylheimChartEvent> for CylheimChartEventKind {
+    fn from(event: &CylheimChartEvent) -> Self {
+        CylheimChartEventKind::from_event(event)
+    }
+}
+impl From<CylheimChartEventKind> for CylheimChartEvent {
+    fn from(kind: CylheimChartEventKind) -> Self {
+        kind.into_event()
+    }
+}
+impl CylheimChartEvent {
+    pub fn get_kind(&self) -> CylheimChartEventKind {
+        CylheimChartEventKind::from_event(self)
+    }
+}
+impl CylheimChart {
+    /// Every event of the chart with its tick, in `event_order_list` order.
+    pub fn iter_events(&self) -> impl Iterator<Item = (u32, CylheimChartEventKind)> + '_ {
+        self.event_order_list().iter().flat_map(|events| {
+            events
+                .event_list()
+                .iter()
+                .map(|event| (*events.tick(), event.get_kind()))
+        })
+    }
+    pub fn get_events_at_tick(&self, tick: u32) -> Vec<CylheimChartEventKind> {
+        self.iter_events()
+            .filter(|(event_tick, _)| *event_tick == tick)
+            .map(|(_, kind)| kind)
+            .collect()
+    }
+    /// Scanline speed up and speed down events with their ticks.
+    pub fn get_speed_changes(&self) -> Vec<(u32, CylheimChartEventKind)> {
+        self.iter_events()
+            .filter(|(_, kind)| kind.is_speed_change())
+            .collect()
+    }
+    /// Directive texts with their ticks.
+    pub fn get_directives(&self) -> Vec<(u32, String)> {
+        self.iter_events()
+            .filter_map(|(tick, kind)| match kind {
+                CylheimChartEventKind::Directive { directive, .. } => Some((tick, directive)),
+                _ => None,
+            })
+            .collect()
+    }
+    /// Append an event at `tick`, keeping `event_order_list` sorted by tick.
+    pub fn add_event(&mut self, tick: u32, kind: CylheimChartEventKind) {
+        let event_order_list = self.event_order_list_mut();
+        match event_order_list.binary_search_by_key(&tick, |events| *events.tick()) {
+            Ok(index) => event_order_list[index]
+                .event_list_mut()
+                .push(kind.into_event()),
+            Err(index) => event_order_list.insert(
+                index,
+                CylheimChartTickEventList::new(tick, vec![kind.into_event()]),
+            ),
+        }
+    }
+}
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::fs;
+    const TEST_RESOURCE_ROOT: &str = "./tests/resources/";
+    #[test]
+    fn test_event_round_trip() {
+        let f = fs::read_to_string(TEST_RESOURCE_ROOT.to_owned() + "test_cyl_chart.json").unwrap();
+        let chart: CylheimChart = serde_json::from_str(&f).unwrap();
+        for events in chart.event_order_list() {
+            for event in events.event_list() {
+                let kind = event.get_kind();
+                let round_trip = kind.into_event();
+                assert_eq!(round_trip.event_type(), event.event_type());
+                assert_eq!(round_trip.event_args(), event.event_args());
+            }
+        }
+        assert!(chart.iter_events().any(|(_, kind)| kind
+            == CylheimChartEventKind::Text {
+                text: "主人的任务".to_string(),
+                color: Some("#FFFFFF".to_string()),
+            }));
+        assert!(chart
+            .iter_events()
+            .any(|(_, kind)| matches!(kind, CylheimChartEventKind::Unknown { event_type: 5, .. })));
+    }
+    #[test]
+    fn test_event_query() {
+        let mut chart = CylheimChart::default();
+        chart.add_event(960, CylheimChartEventKind::new_text("hello"));
+        chart.add_event(
+            480,
+            CylheimChartEventKind::SpeedUp {
+                args: "W".to_string(),
+            },
+        );
+        chart.add_event(0, CylheimChartEventKind::new_directive("#DEFINE CYTUS1"));
+        chart.add_event(
+            960,
+            CylheimChartEventKind::SpeedDown {
+                args: "W".to_string(),
+            },
+        );
+        let ticks: Vec<u32> = chart
+            .event_order_list()
+            .iter()
+            .map(|events| *events.tick())
+            .collect();
+        assert_eq!(ticks, vec![0, 480, 960]);
+        assert_eq!(chart.get_events_at_tick(960).len(), 2);
+        assert_eq!(chart.get_speed_changes().len(), 2);
+        assert_eq!(
+            chart.get_directives(),
+            vec![(0, "#DEFINE CYTUS1".to_string())]
+        );
+        let flag = &chart.event_order_list()[0].event_list()[0];
+        assert_eq!(flag.event_args(), "#DEFINE CYTUS1,#FFFFFF");
+    }
+}
@@ -0,0 +1,235 @@
+use super::{cytus1_chart::Cytus1Chart, utils::CylToolError, ConversionReport, CylheimChart};
+use getset::Getters;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    fmt,
+    path::{Path, PathBuf},
+};
+
+/// Extension of the note id map written next to an exported Cytus 1 chart.
+pub const NOTE_ID_MAP_EXTENSION: &str = "ids.json";
+/// Other Cytus 1 editors save six decimals, so smaller x differences are not edits.
+const X_TOLERANCE: f64 = 5e-7;
+
+/// Sidecar path of an exported chart: `song.txt` maps to `song.ids.json`.
+pub fn get_note_id_map_path(chart_path: &Path) -> PathBuf {
+    chart_path.with_extension(NOTE_ID_MAP_EXTENSION)
+}
+/// One Cylheim note and the Cytus 1 note it was exported as.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Getters)]
+pub struct NoteIdPair {
+    #[getset(get = "pub")]
+    cylheim_id: u32,
+    #[getset(get = "pub")]
+    cytus1_id: u32,
+    /// Cylheim tick of the note when it was exported.
+    #[getset(get = "pub")]
+    tick: u32,
+}
+/// Which Cylheim note every Cytus 1 note of an export came from.
+///
+/// Notes dropped by the export have no pair.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Getters)]
+pub struct NoteIdMap {
+    #[getset(get = "pub")]
+    pairs: Vec<NoteIdPair>,
+}
+/// An x copied from one chart to the other.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Getters)]
+pub struct SyncedX {
+    #[getset(get = "pub")]
+    cylheim_id: u32,
+    #[getset(get = "pub")]
+    cytus1_id: u32,
+    #[getset(get = "pub")]
+    old_x: f64,
+    #[getset(get = "pub")]
+    new_x: f64,
+}
+impl fmt::Display for SyncedX {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Note {} (Cytus 1 note {}) moved from x {} to {}.",
+            self.cylheim_id, self.cytus1_id, self.old_x, self.new_x
+        )
+    }
+}
+impl NoteIdMap {
+    /// Map of the export of `chart` that produced `report`.
+    pub fn from_conversion(chart: &CylheimChart, report: &ConversionReport) -> Self {
+        let renumbered: HashMap<u32, u32> = report
+            .renumbered_notes()
+            .iter()
+            .map(|note| (*note.note_id(), *note.cytus1_id()))
+            .collect();
+        let mut pairs: Vec<NoteIdPair> = chart
+            .note_list()
+            .iter()
+            .filter(|note| {
+                !report
+                    .dropped_notes()
+                    .iter()
+                    .any(|dropped| dropped.note_id() == note.id())
+            })
+            .map(|note| NoteIdPair {
+                cylheim_id: *note.id(),
+                cytus1_id: renumbered.get(note.id()).copied().unwrap_or(*note.id()),
+                tick: *note.tick(),
+            })
+            .collect();
+        pairs.sort_by_key(|pair| pair.cylheim_id);
+        Self { pairs }
+    }
+    pub fn get_cytus1_id(&self, cylheim_id: u32) -> Option<u32> {
+        self.pairs
+            .iter()
+            .find(|pair| pair.cylheim_id == cylheim_id)
+            .map(|pair| pair.cytus1_id)
+    }
+    pub fn get_cylheim_id(&self, cytus1_id: u32) -> Option<u32> {
+        self.pairs
+            .iter()
+            .find(|pair| pair.cytus1_id == cytus1_id)
+            .map(|pair| pair.cylheim_id)
+    }
+    /// Copy the x of every mapped Cytus 1 note onto its Cylheim note and list the changes.
+    ///
+    /// Notes clamped on export come back clamped. Fails without changing `chart` when a
+    /// mapped note is missing from either chart.
+    pub fn sync_x_to_cylheim(
+        &self,
+        cytus1_chart: &Cytus1Chart,
+        chart: &mut CylheimChart,
+    ) -> Result<Vec<SyncedX>, CylToolError> {
+        let cytus1_x: HashMap<u32, f64> = cytus1_chart
+            .notes()
+            .iter()
+            .map(|note| (*note.id(), *note.x()))
+            .collect();
+        let cylheim_index: HashMap<u32, usize> = chart
+            .note_list()
+            .iter()
+            .enumerate()
+            .map(|(index, note)| (*note.id(), index))
+            .collect();
+        let changes = self.get_x_changes(|pair| {
+            let index = cylheim_index.get(&pair.cylheim_id)?;
+            let new_x = *cytus1_x.get(&pair.cytus1_id)?;
+            Some((*chart.note_list()[*index].x(), new_x))
+        })?;
+        for change in &changes {
+            chart.note_list_mut()[cylheim_index[&change.cylheim_id]].set_x(change.new_x);
+        }
+        Ok(changes)
+    }
+    /// Copy the x of every mapped Cylheim note onto its Cytus 1 note and list the changes.
+    ///
+    /// Fails without changing `cytus1_chart` when a mapped note is missing from either chart.
+    pub fn sync_x_to_cytus1(
+        &self,
+        chart: &CylheimChart,
+        cytus1_chart: &mut Cytus1Chart,
+    ) -> Result<Vec<SyncedX>, CylToolError> {
+        let cylheim_x: HashMap<u32, f64> = chart
+            .note_list()
+            .iter()
+            .map(|note| (*note.id(), *note.x()))
+            .collect();
+        let cytus1_index: HashMap<u32, usize> = cytus1_chart
+            .notes()
+            .iter()
+            .enumerate()
+            .map(|(index, note)| (*note.id(), index))
+            .collect();
+        let changes = self.get_x_changes(|pair| {
+            let index = cytus1_index.get(&pair.cytus1_id)?;
+            let new_x = *cylheim_x.get(&pair.cylheim_id)?;
+            Some((*cytus1_chart.notes()[*index].x(), new_x))
+        })?;
+        let mut notes = cytus1_chart.notes().clone();
+        for change in &changes {
+            notes[cytus1_index[&change.cytus1_id]].set_x(change.new_x);
+        }
+        cytus1_chart.set_notes(notes);
+        Ok(changes)
+    }
+    /// Pairs whose x differs, with `get_x` giving (old x, new x) or None when a note is missing.
+    fn get_x_changes(
+        &self,
+        get_x: impl Fn(&NoteIdPair) -> Option<(f64, f64)>,
+    ) -> Result<Vec<SyncedX>, CylToolError> {
+        let mut changes: Vec<SyncedX> = Vec::new();
+        for pair in &self.pairs {
+            let (old_x, new_x) = get_x(pair).ok_or(CylToolError::MissingMappedNote {
+                cylheim_id: pair.cylheim_id,
+                cytus1_id: pair.cytus1_id,
+            })?;
+            if (old_x - new_x).abs() > X_TOLERANCE {
+                changes.push(SyncedX {
+                    cylheim_id: pair.cylheim_id,
+                    cytus1_id: pair.cytus1_id,
+                    old_x,
+                    new_x,
+                });
+            }
+        }
+        Ok(changes)
+    }
+}
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cylheim_tools::{ConversionOptions, CylheimChartNoteType, UnsupportedNoteStrategy};
+    use std::fs;
+    const TEST_RESOURCE_ROOT: &str = "./tests/resources/";
+    #[test]
+    fn test_note_id_map_sync_x() {
+        let f = fs::read_to_string(TEST_RESOURCE_ROOT.to_owned() + "test_valid_c2to1_chart.json")
+            .unwrap();
+        let mut chart: CylheimChart = serde_json::from_str(&f).unwrap();
+        chart.note_list_mut()[0].set_note_type(CylheimChartNoteType::Flick);
+        let (cytus1_chart, report) = chart
+            .to_cytus1_chart_with_options(&ConversionOptions {
+                unsupported_note_strategy: Some(UnsupportedNoteStrategy::Drop),
+                renumber_ids: true,
+                ..Default::default()
+            })
+            .unwrap();
+        let id_map = NoteIdMap::from_conversion(&chart, &report);
+        assert_eq!(id_map.pairs().len(), cytus1_chart.notes().len());
+        assert_eq!(id_map.get_cytus1_id(0), None);
+        assert_eq!(id_map.get_cytus1_id(1), Some(0));
+        assert_eq!(id_map.get_cylheim_id(0), Some(1));
+        // 经过文本往返后未修改的音符不应产生变化
+        let mut cytus1_chart: Cytus1Chart = cytus1_chart.to_string().parse().unwrap();
+        assert!(id_map
+            .sync_x_to_cylheim(&cytus1_chart, &mut chart.clone())
+            .unwrap()
+            .is_empty());
+
+        let mut notes = cytus1_chart.notes().clone();
+        notes[0].set_x(0.25);
+        cytus1_chart.set_notes(notes);
+        let changes = id_map.sync_x_to_cylheim(&cytus1_chart, &mut chart).unwrap();
+        assert_eq!(changes.len(), 1);
+        assert_eq!(*changes[0].cylheim_id(), 1);
+        assert_eq!(*chart.note_list()[1].x(), 0.25);
+
+        chart.note_list_mut()[2].set_x(0.5);
+        let changes = id_map.sync_x_to_cytus1(&chart, &mut cytus1_chart).unwrap();
+        assert_eq!(changes.len(), 1);
+        let cytus1_id = id_map.get_cytus1_id(2).unwrap();
+        assert_eq!(*cytus1_chart.notes()[cytus1_id as usize].x(), 0.5);
+
+        cytus1_chart.set_notes(Vec::new());
+        assert_eq!(
+            id_map
+                .sync_x_to_cylheim(&cytus1_chart, &mut chart)
+                .unwrap_err()
+                .kind(),
+            "missing_mapped_note"
+        );
+    }
+}
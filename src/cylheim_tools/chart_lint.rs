@@ -0,0 +1,435 @@
+use super::{cyl_chart::CylheimChartNoteType, CylheimChart};
+use getset::Getters;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+/// How bad a lint issue is. Errors make Cylheim refuse or misrender the chart.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum LintSeverity {
+    Info,
+    Warning,
+    Error,
+}
+impl fmt::Display for LintSeverity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LintSeverity::Info => write!(f, "info"),
+            LintSeverity::Warning => write!(f, "warning"),
+            LintSeverity::Error => write!(f, "error"),
+        }
+    }
+}
+/// A problem found by `CylheimChart::lint`, with the notes, ticks and pages involved.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum LintIssueKind {
+    /// The note tick lies outside the tick range of its page.
+    NoteOutsidePage {
+        note_id: u32,
+        tick: u32,
+        page_index: u32,
+    },
+    /// The note refers to a page that does not exist.
+    InvalidPageIndex { note_id: u32, page_index: u32 },
+    /// A drag note whose `next_id` points to a missing note.
+    DanglingNextId { note_id: u32, next_id: i32 },
+    /// A drag chain that leads back to one of its own notes.
+    DragChainCycle { note_id: u32 },
+    /// A drag child that no drag note points to.
+    OrphanDragChild { note_id: u32 },
+    /// A hold whose end lies past the end of its page.
+    HoldCrossesPage {
+        note_id: u32,
+        tick: u32,
+        hold_tick: u32,
+        page_index: u32,
+    },
+    /// A tempo not strictly after the previous one.
+    UnsortedTempo { tempo_index: u32, tick: u32 },
+    /// A page starting before the previous page ends.
+    PageOverlap {
+        page_index: u32,
+        start_tick: u32,
+        previous_end_tick: u32,
+    },
+    /// A page starting after the previous page ends.
+    PageGap {
+        page_index: u32,
+        start_tick: u32,
+        previous_end_tick: u32,
+    },
+    /// Several notes share the same id.
+    DuplicateNoteId { note_id: u32, count: u32 },
+    /// `has_sibling` does not match whether another note shares the tick.
+    SiblingMismatch {
+        note_id: u32,
+        tick: u32,
+        has_sibling: bool,
+    },
+    /// A note x outside `[0, 1]`.
+    NoteXOutOfRange { note_id: u32, x: f64 },
+    /// A directive event that cannot be applied.
+    InvalidDirective {
+        tick: u32,
+        directive: String,
+        message: String,
+    },
+}
+impl LintIssueKind {
+    pub fn get_severity(&self) -> LintSeverity {
+        match self {
+            LintIssueKind::PageGap { .. }
+            | LintIssueKind::HoldCrossesPage { .. }
+            | LintIssueKind::OrphanDragChild { .. }
+            | LintIssueKind::InvalidDirective { .. } => LintSeverity::Warning,
+            LintIssueKind::SiblingMismatch { .. } => LintSeverity::Info,
+            _ => LintSeverity::Error,
+        }
+    }
+}
+impl fmt::Display for LintIssueKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LintIssueKind::NoteOutsidePage {
+                note_id,
+                tick,
+                page_index,
+            } => write!(
+                f,
+                "Note {} at tick {} is outside page {}.",
+                note_id, tick, page_index
+            ),
+            LintIssueKind::InvalidPageIndex {
+                note_id,
+                page_index,
+            } => write!(
+                f,
+                "Note {} have an invalid page index {}.",
+                note_id, page_index
+            ),
+            LintIssueKind::DanglingNextId { note_id, next_id } => {
+                write!(f, "Note {} links to a missing note {}.", note_id, next_id)
+            }
+            LintIssueKind::DragChainCycle { note_id } => {
+                write!(f, "Drag chain through note {} forms a cycle.", note_id)
+            }
+            LintIssueKind::OrphanDragChild { note_id } => {
+                write!(f, "Drag child {} has no parent.", note_id)
+            }
+            LintIssueKind::HoldCrossesPage {
+                note_id,
+                tick,
+                hold_tick,
+                page_index,
+            } => write!(
+                f,
+                "Hold {} from tick {} for {} ticks crosses the end of page {}.",
+                note_id, tick, hold_tick, page_index
+            ),
+            LintIssueKind::UnsortedTempo { tempo_index, tick } => write!(
+                f,
+                "Tempo {} at tick {} is not after the previous tempo.",
+                tempo_index, tick
+            ),
+            LintIssueKind::PageOverlap {
+                page_index,
+                start_tick,
+                previous_end_tick,
+            } => write!(
+                f,
+                "Page {} starts at tick {} before the previous page ends at tick {}.",
+                page_index, start_tick, previous_end_tick
+            ),
+            LintIssueKind::PageGap {
+                page_index,
+                start_tick,
+                previous_end_tick,
+            } => write!(
+                f,
+                "Page {} starts at tick {} after the previous page ends at tick {}.",
+                page_index, start_tick, previous_end_tick
+            ),
+            LintIssueKind::DuplicateNoteId { note_id, count } => {
+                write!(f, "Note id {} is used by {} notes.", note_id, count)
+            }
+            LintIssueKind::SiblingMismatch {
+                note_id,
+                tick,
+                has_sibling,
+            } => write!(
+                f,
+                "Note {} at tick {} has has_sibling {} but {}.",
+                note_id,
+                tick,
+                has_sibling,
+                if *has_sibling {
+                    "no other note shares its tick"
+                } else {
+                    "another note shares its tick"
+                }
+            ),
+            LintIssueKind::NoteXOutOfRange { note_id, x } => {
+                write!(f, "Note {} have an invalid x {}.", note_id, x)
+            }
+            LintIssueKind::InvalidDirective {
+                tick,
+                directive,
+                message,
+            } => write!(
+                f,
+                "Directive \"{}\" at tick {}: {}.",
+                directive, tick, message
+            ),
+        }
+    }
+}
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Getters)]
+pub struct LintIssue {
+    #[getset(get = "pub")]
+    severity: LintSeverity,
+    #[getset(get = "pub")]
+    message: String,
+    #[serde(flatten)]
+    #[getset(get = "pub")]
+    kind: LintIssueKind,
+}
+impl LintIssue {
+    fn new(kind: LintIssueKind) -> Self {
+        Self {
+            severity: kind.get_severity(),
+            message: kind.to_string(),
+            kind,
+        }
+    }
+}
+impl fmt::Display for LintIssue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "[{}] {}", self.severity, self.message)
+    }
+}
+/// Every issue found in a chart, most severe first.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, Getters)]
+pub struct CylheimChartLintReport {
+    #[getset(get = "pub")]
+    issues: Vec<LintIssue>,
+}
+impl CylheimChartLintReport {
+    pub fn count(&self, severity: LintSeverity) -> usize {
+        self.issues
+            .iter()
+            .filter(|issue| issue.severity == severity)
+            .count()
+    }
+    pub fn has_errors(&self) -> bool {
+        self.count(LintSeverity::Error) > 0
+    }
+}
+impl CylheimChart {
+    /// Check the chart for structural problems without stopping at the first one.
+    pub fn lint(&self) -> CylheimChartLintReport {
+        let mut issues: Vec<LintIssueKind> = Vec::new();
+        let page_list = self.page_list();
+        let note_list = self.note_list();
+
+        for (index, tempo) in self.tempo_list().iter().enumerate().skip(1) {
+            if *tempo.tick() <= *self.tempo_list()[index - 1].tick() {
+                issues.push(LintIssueKind::UnsortedTempo {
+                    tempo_index: index as u32,
+                    tick: *tempo.tick(),
+                });
+            }
+        }
+        for (index, page) in page_list.iter().enumerate().skip(1) {
+            let previous_end_tick = *page_list[index - 1].end_tick();
+            let start_tick = *page.start_tick();
+            if start_tick < previous_end_tick {
+                issues.push(LintIssueKind::PageOverlap {
+                    page_index: index as u32,
+                    start_tick,
+                    previous_end_tick,
+                });
+            } else if start_tick > previous_end_tick {
+                issues.push(LintIssueKind::PageGap {
+                    page_index: index as u32,
+                    start_tick,
+                    previous_end_tick,
+                });
+            }
+        }
+
+        let mut id_count: HashMap<u32, u32> = HashMap::new();
+        let mut tick_count: HashMap<u32, u32> = HashMap::new();
+        for note in note_list {
+            *id_count.entry(*note.id()).or_default() += 1;
+            *tick_count.entry(*note.tick()).or_default() += 1;
+        }
+        let mut duplicate_ids: Vec<(u32, u32)> = id_count
+            .iter()
+            .filter(|(_, count)| **count > 1)
+            .map(|(note_id, count)| (*note_id, *count))
+            .collect();
+        duplicate_ids.sort();
+        issues.extend(
+            duplicate_ids
+                .into_iter()
+                .map(|(note_id, count)| LintIssueKind::DuplicateNoteId { note_id, count }),
+        );
+
+        let note_map: HashMap<u32, _> = note_list.iter().map(|note| (*note.id(), note)).collect();
+        let mut child_ids: HashSet<u32> = HashSet::new();
+        for note in note_list {
+            let note_id = *note.id();
+            match page_list.get(*note.page_index() as usize) {
+                None => issues.push(LintIssueKind::InvalidPageIndex {
+                    note_id,
+                    page_index: *note.page_index(),
+                }),
+                Some(page) => {
+                    if !page.contains_tick(*note.tick()) {
+                        issues.push(LintIssueKind::NoteOutsidePage {
+                            note_id,
+                            tick: *note.tick(),
+                            page_index: *note.page_index(),
+                        });
+                    }
+                    // 只有短 hold 需要在本页内结束，长 hold 本身就跨页
+                    if *note.note_type() == CylheimChartNoteType::Hold
+                        && note.tick() + note.hold_tick() > *page.end_tick()
+                    {
+                        issues.push(LintIssueKind::HoldCrossesPage {
+                            note_id,
+                            tick: *note.tick(),
+                            hold_tick: *note.hold_tick(),
+                            page_index: *note.page_index(),
+                        });
+                    }
+                }
+            }
+            if *note.x() < 0.0 || *note.x() > 1.0 {
+                issues.push(LintIssueKind::NoteXOutOfRange {
+                    note_id,
+                    x: *note.x(),
+                });
+            }
+            let has_sibling = tick_count[note.tick()] > 1;
+            if *note.has_sibling() != has_sibling {
+                issues.push(LintIssueKind::SiblingMismatch {
+                    note_id,
+                    tick: *note.tick(),
+                    has_sibling: *note.has_sibling(),
+                });
+            }
+            if note.note_type().is_drag_family() && *note.next_id() >= 0 {
+                let next_id = *note.next_id();
+                if note_map.contains_key(&(next_id as u32)) {
+                    child_ids.insert(next_id as u32);
+                } else {
+                    issues.push(LintIssueKind::DanglingNextId { note_id, next_id });
+                }
+            }
+        }
+        for note in note_list {
+            if note.note_type().is_chain_child() && !child_ids.contains(note.id()) {
+                issues.push(LintIssueKind::OrphanDragChild {
+                    note_id: *note.id(),
+                });
+            }
+        }
+
+        // 沿 next_id 前进，回到当前路径上的音符即为环，每个环只报告一次
+        let mut visited: HashSet<u32> = HashSet::new();
+        for note in note_list
+            .iter()
+            .filter(|note| note.note_type().is_drag_family())
+        {
+            let mut path: Vec<u32> = Vec::new();
+            let mut current = Some(note);
+            while let Some(current_note) = current {
+                let current_id = *current_note.id();
+                if let Some(position) = path.iter().position(|id| *id == current_id) {
+                    let cycle_start = path[position..].iter().min().copied().unwrap();
+                    issues.push(LintIssueKind::DragChainCycle {
+                        note_id: cycle_start,
+                    });
+                    break;
+                }
+                if !visited.insert(current_id) {
+                    break;
+                }
+                path.push(current_id);
+                current = (*current_note.next_id() >= 0
+                    && current_note.note_type().is_drag_family())
+                .then(|| note_map.get(&(*current_note.next_id() as u32)).copied())
+                .flatten();
+            }
+        }
+
+        issues.extend(
+            self.get_directive_options()
+                .warnings
+                .into_iter()
+                .map(|warning| LintIssueKind::InvalidDirective {
+                    tick: *warning.tick(),
+                    directive: warning.directive().clone(),
+                    message: warning.message().clone(),
+                }),
+        );
+
+        let mut issues: Vec<LintIssue> = issues.into_iter().map(LintIssue::new).collect();
+        issues.sort_by_key(|issue| std::cmp::Reverse(issue.severity));
+        CylheimChartLintReport { issues }
+    }
+}
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cylheim_tools::{cyl_chart::CylheimChartTempo, CylheimChartEventKind};
+    use std::fs;
+    const TEST_RESOURCE_ROOT: &str = "./tests/resources/";
+    #[test]
+    fn test_lint() {
+        let f = fs::read_to_string(TEST_RESOURCE_ROOT.to_owned() + "test_valid_c2to1_chart.json")
+            .unwrap();
+        let mut chart: CylheimChart = serde_json::from_str(&f).unwrap();
+        assert!(!chart.lint().has_errors());
+
+        let note_list = chart.note_list_mut();
+        note_list[0].set_x(1.5);
+        let duplicate_id = *note_list[2].id();
+        note_list[1].set_id(duplicate_id);
+        let head = note_list.iter().position(|note| *note.id() == 18).unwrap();
+        let child_id = *note_list[head].next_id() as u32;
+        let child = note_list
+            .iter()
+            .position(|note| *note.id() == child_id)
+            .unwrap();
+        note_list[child].set_next_id(18);
+        chart
+            .tempo_list_mut()
+            .push(CylheimChartTempo::new(0, 500000));
+        chart.add_event(0, CylheimChartEventKind::new_directive("#todo check drags"));
+        chart.add_event(0, CylheimChartEventKind::new_directive("#DEFINE FOO"));
+        let report = chart.lint();
+        let kinds: Vec<&LintIssueKind> = report.issues().iter().map(|issue| issue.kind()).collect();
+        assert!(kinds.contains(&&LintIssueKind::NoteXOutOfRange { note_id: 0, x: 1.5 }));
+        assert!(kinds
+            .iter()
+            .any(|kind| matches!(kind, LintIssueKind::DuplicateNoteId { count: 2, .. })));
+        assert!(kinds.contains(&&LintIssueKind::DragChainCycle { note_id: 18 }));
+        assert!(kinds
+            .iter()
+            .any(|kind| matches!(kind, LintIssueKind::UnsortedTempo { .. })));
+        let directives: Vec<&LintIssueKind> = kinds
+            .iter()
+            .copied()
+            .filter(|kind| matches!(kind, LintIssueKind::InvalidDirective { .. }))
+            .collect();
+        assert!(matches!(
+            directives[..],
+            [LintIssueKind::InvalidDirective { directive, .. }] if directive == "#DEFINE FOO"
+        ));
+        assert_eq!(*report.issues()[0].severity(), LintSeverity::Error);
+    }
+}
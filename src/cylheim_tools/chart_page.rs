@@ -0,0 +1,429 @@
+use super::{
+    chart_retime::{dedup_tempo_list, merge_event_order_list},
+    cyl_chart::CylheimChartPage,
+    utils::CylToolError,
+    CylheimChart,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+
+/// How page edits set the scan line direction of pages.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ScanDirectionMode {
+    /// Make every page after the edit run opposite to the one before it.
+    #[default]
+    Alternate,
+    /// Leave existing pages as they are. New pages still run opposite to the page before.
+    Keep,
+}
+impl CylheimChart {
+    fn check_page_index(&self, page_index: u32) -> Result<usize, CylToolError> {
+        let index = page_index as usize;
+        if index >= self.page_list().len() {
+            return Err(CylToolError::PageNotFound { page_index });
+        }
+        Ok(index)
+    }
+    /// Latest tick of any page, tempo, event or note end.
+    fn get_last_tick(&self) -> u32 {
+        let page_ticks = self.page_list().iter().map(|page| *page.end_tick());
+        let tempo_ticks = self.tempo_list().iter().map(|tempo| *tempo.tick());
+        let event_ticks = self.event_order_list().iter().map(|events| *events.tick());
+        let note_ticks = self
+            .note_list()
+            .iter()
+            .map(|note| note.tick().saturating_add(*note.hold_tick()));
+        page_ticks
+            .chain(tempo_ticks)
+            .chain(event_ticks)
+            .chain(note_ticks)
+            .max()
+            .unwrap_or(0)
+    }
+    /// Cut `removed` ticks starting at `at` and insert `inserted` ticks in their place.
+    ///
+    /// Ticks inside the cut collapse onto `at` and later ticks move by the difference.
+    /// Pages from `first_moved_page` on move as a whole; notes on earlier pages stay within
+    /// their page's end. Fails without changing the chart when a tick would overflow.
+    fn splice_ticks(
+        &mut self,
+        at: u32,
+        removed: u32,
+        inserted: u32,
+        first_moved_page: usize,
+    ) -> Result<(), CylToolError> {
+        if inserted > removed {
+            let tick = self.get_last_tick().max(at);
+            let delta = inserted - removed;
+            tick.checked_add(delta)
+                .ok_or(CylToolError::TickOverflow { tick, delta })?;
+        }
+        let map_tick = |tick: u32| {
+            if tick < at {
+                tick
+            } else if tick < at + removed {
+                at
+            } else {
+                tick - removed + inserted
+            }
+        };
+        for page in self.page_list_mut().iter_mut().skip(first_moved_page) {
+            page.set_start_tick(map_tick(*page.start_tick()));
+            page.set_end_tick(map_tick(*page.end_tick()));
+        }
+        for tempo in self.tempo_list_mut() {
+            tempo.set_tick(map_tick(*tempo.tick()));
+        }
+        dedup_tempo_list(self.tempo_list_mut());
+        for events in self.event_order_list_mut() {
+            events.set_tick(map_tick(*events.tick()));
+        }
+        merge_event_order_list(self.event_order_list_mut());
+        let page_end_ticks: Vec<u32> = self
+            .page_list()
+            .iter()
+            .map(|page| *page.end_tick())
+            .collect();
+        for note in self.note_list_mut() {
+            let end_tick = map_tick(note.tick().saturating_add(*note.hold_tick()));
+            let mut tick = map_tick(*note.tick());
+            // 页尾的音符不随插入的 tick 移出所在页
+            if (*note.page_index() as usize) < first_moved_page {
+                if let Some(page_end_tick) = page_end_ticks.get(*note.page_index() as usize) {
+                    tick = tick.min(*page_end_tick);
+                }
+            }
+            note.set_tick(tick);
+            note.set_hold_tick(end_tick.saturating_sub(tick));
+        }
+        Ok(())
+    }
+    /// Shift the page index of every note on `from_page` or later by `delta`.
+    fn shift_page_index(&mut self, from_page: usize, delta: i32) {
+        for note in self.note_list_mut() {
+            if *note.page_index() as usize >= from_page {
+                note.set_page_index((*note.page_index() as i32 + delta) as u32);
+            }
+        }
+    }
+    fn apply_scan_direction(&mut self, from_page: usize, mode: ScanDirectionMode) {
+        if mode == ScanDirectionMode::Keep {
+            return;
+        }
+        let page_list = self.page_list_mut();
+        for index in from_page.max(1)..page_list.len() {
+            let direction = -*page_list[index - 1].scan_line_direction();
+            page_list[index].set_scan_line_direction(direction);
+        }
+    }
+    /// Direction a new page at `page_index` takes: opposite to its neighbour, upward on an
+    /// empty chart.
+    fn get_new_page_direction(&self, page_index: usize) -> i32 {
+        let neighbour = match page_index {
+            0 => self.page_list().first(),
+            _ => self.page_list().get(page_index - 1),
+        };
+        neighbour.map_or(1, |page| -*page.scan_line_direction())
+    }
+    /// Insert an empty page of `page_size` ticks at `tick`, which must be a page boundary.
+    /// Everything from `tick` on moves later by `page_size`.
+    pub fn insert_page(
+        &mut self,
+        tick: u32,
+        page_size: u32,
+        mode: ScanDirectionMode,
+    ) -> Result<(), CylToolError> {
+        if page_size == 0 {
+            return Err(CylToolError::InvalidPageSize { page_size: 0.0 });
+        }
+        let page_list = self.page_list();
+        let page_index = page_list
+            .iter()
+            .position(|page| *page.start_tick() == tick)
+            .or_else(|| match page_list.last() {
+                Some(page) => (*page.end_tick() == tick).then_some(page_list.len()),
+                None => (tick == 0).then_some(0),
+            })
+            .ok_or(CylToolError::NotPageBoundary { tick })?;
+        let direction = self.get_new_page_direction(page_index);
+        self.splice_ticks(tick, 0, page_size, page_index)?;
+        self.shift_page_index(page_index, 1);
+        self.page_list_mut().insert(
+            page_index,
+            CylheimChartPage::new(tick, tick + page_size, direction),
+        );
+        self.apply_scan_direction(page_index + 1, mode);
+        Ok(())
+    }
+    /// Delete a page with its notes. Later pages move back to close the gap, and events and
+    /// tempos inside the page move to its start.
+    ///
+    /// Drag chains through deleted notes are joined around them. A drag head whose chain goes
+    /// on past the page cannot be deleted. Returns the ids of the deleted notes.
+    pub fn delete_page(
+        &mut self,
+        page_index: u32,
+        mode: ScanDirectionMode,
+    ) -> Result<Vec<u32>, CylToolError> {
+        let index = self.check_page_index(page_index)?;
+        let page = self.page_list()[index].clone();
+        let deleted_notes: HashSet<u32> = self
+            .note_list()
+            .iter()
+            .filter(|note| *note.page_index() == page_index)
+            .map(|note| *note.id())
+            .collect();
+        let next_ids: HashMap<u32, i32> = self
+            .note_list()
+            .iter()
+            .map(|note| (*note.id(), *note.next_id()))
+            .collect();
+        // 删除链头会使页外的子音符失去链头
+        for note in self.note_list() {
+            if !note.note_type().is_chain_head() || !deleted_notes.contains(note.id()) {
+                continue;
+            }
+            let mut next_id = *note.next_id();
+            let mut visited: HashSet<u32> = HashSet::new();
+            while next_id >= 0 && visited.insert(next_id as u32) {
+                match next_ids.get(&(next_id as u32)) {
+                    Some(_) if !deleted_notes.contains(&(next_id as u32)) => {
+                        return Err(CylToolError::DragHeadDeletion {
+                            note_id: *note.id(),
+                            child_id: next_id as u32,
+                        });
+                    }
+                    Some(child_next_id) => next_id = *child_next_id,
+                    None => break,
+                }
+            }
+        }
+        for note in self.note_list_mut() {
+            if !note.note_type().is_drag_family() {
+                continue;
+            }
+            // 跳过被删除的音符，连接到链上的下一个保留音符
+            let mut next_id = *note.next_id();
+            let mut visited: HashSet<u32> = HashSet::new();
+            while next_id >= 0 && deleted_notes.contains(&(next_id as u32)) {
+                if !visited.insert(next_id as u32) {
+                    next_id = -1;
+                    break;
+                }
+                next_id = next_ids[&(next_id as u32)];
+            }
+            note.set_next_id(next_id);
+        }
+        self.note_list_mut()
+            .retain(|note| !deleted_notes.contains(note.id()));
+        self.page_list_mut().remove(index);
+        self.shift_page_index(index, -1);
+        self.splice_ticks(
+            *page.start_tick(),
+            page.get_page_size().max(0) as u32,
+            0,
+            index,
+        )?;
+        self.apply_scan_direction(index, mode);
+        let mut deleted_notes: Vec<u32> = deleted_notes.into_iter().collect();
+        deleted_notes.sort();
+        Ok(deleted_notes)
+    }
+    /// Split a page in two at `tick`, strictly inside the page. Notes from `tick` on go to the
+    /// second page. No tick moves.
+    pub fn split_page(
+        &mut self,
+        page_index: u32,
+        tick: u32,
+        mode: ScanDirectionMode,
+    ) -> Result<(), CylToolError> {
+        let index = self.check_page_index(page_index)?;
+        let page = &self.page_list()[index];
+        if tick <= *page.start_tick() || tick >= *page.end_tick() {
+            return Err(CylToolError::TickOutsidePage { tick, page_index });
+        }
+        let second_page =
+            CylheimChartPage::new(tick, *page.end_tick(), -*page.scan_line_direction());
+        self.page_list_mut()[index].set_end_tick(tick);
+        self.page_list_mut().insert(index + 1, second_page);
+        for note in self.note_list_mut() {
+            let in_second_page = *note.page_index() == page_index && *note.tick() >= tick;
+            if *note.page_index() > page_index || in_second_page {
+                note.set_page_index(note.page_index() + 1);
+            }
+        }
+        self.apply_scan_direction(index + 2, mode);
+        Ok(())
+    }
+    /// Merge a page with the page after it, keeping the first page's scan direction.
+    pub fn merge_pages(
+        &mut self,
+        page_index: u32,
+        mode: ScanDirectionMode,
+    ) -> Result<(), CylToolError> {
+        let index = self.check_page_index(page_index)?;
+        let next_page = self
+            .page_list()
+            .get(index + 1)
+            .cloned()
+            .ok_or(CylToolError::LastPage { page_index })?;
+        self.page_list_mut()[index].set_end_tick(*next_page.end_tick());
+        self.page_list_mut().remove(index + 1);
+        self.shift_page_index(index + 1, -1);
+        self.apply_scan_direction(index + 1, mode);
+        Ok(())
+    }
+    /// Change a page to `page_size` ticks, moving everything after it by the difference.
+    ///
+    /// Shrinking is refused while notes lie in the part that is cut off. Events and tempos
+    /// there move to the new page end.
+    pub fn set_page_size(&mut self, page_index: u32, page_size: u32) -> Result<(), CylToolError> {
+        let index = self.check_page_index(page_index)?;
+        if page_size == 0 {
+            return Err(CylToolError::InvalidPageSize { page_size: 0.0 });
+        }
+        let page = &self.page_list()[index];
+        let old_end_tick = *page.end_tick();
+        let new_end_tick =
+            page.start_tick()
+                .checked_add(page_size)
+                .ok_or(CylToolError::TickOverflow {
+                    tick: *page.start_tick(),
+                    delta: page_size,
+                })?;
+        if let Some(note) = self.note_list().iter().find(|note| {
+            *note.page_index() == page_index
+                && *note.tick() > new_end_tick
+                && *note.tick() < old_end_tick
+        }) {
+            return Err(CylToolError::NoteAfterPageEnd {
+                note_id: *note.id(),
+                page_index,
+            });
+        }
+        if new_end_tick < old_end_tick {
+            self.splice_ticks(new_end_tick, old_end_tick - new_end_tick, 0, index + 1)?;
+        } else {
+            self.splice_ticks(old_end_tick, 0, new_end_tick - old_end_tick, index + 1)?;
+        }
+        self.page_list_mut()[index].set_end_tick(new_end_tick);
+        Ok(())
+    }
+}
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::fs;
+    const TEST_RESOURCE_ROOT: &str = "./tests/resources/";
+    fn get_page_ticks(chart: &CylheimChart) -> Vec<(u32, u32, i32)> {
+        chart
+            .page_list()
+            .iter()
+            .map(|page| {
+                (
+                    *page.start_tick(),
+                    *page.end_tick(),
+                    *page.scan_line_direction(),
+                )
+            })
+            .collect()
+    }
+    #[test]
+    fn test_page_edit() {
+        let f = fs::read_to_string(TEST_RESOURCE_ROOT.to_owned() + "test_valid_c2to1_chart.json")
+            .unwrap();
+        let chart: CylheimChart = serde_json::from_str(&f).unwrap();
+        let page_size = chart.page_list()[18].get_page_size() as u32;
+        let boundary = *chart.page_list()[18].start_tick();
+
+        let mut edited = chart.clone();
+        edited
+            .insert_page(boundary, page_size, ScanDirectionMode::Alternate)
+            .unwrap();
+        assert_eq!(edited.page_list().len(), chart.page_list().len() + 1);
+        for (note, edited_note) in chart.note_list().iter().zip(edited.note_list()) {
+            if *note.page_index() >= 18 {
+                assert_eq!(*edited_note.tick(), note.tick() + page_size);
+                assert_eq!(*edited_note.page_index(), note.page_index() + 1);
+            } else {
+                assert_eq!(edited_note.tick(), note.tick());
+            }
+        }
+        assert!(!edited.lint().has_errors());
+        let deleted_notes = edited
+            .delete_page(18, ScanDirectionMode::Alternate)
+            .unwrap();
+        assert!(deleted_notes.is_empty());
+        assert_eq!(get_page_ticks(&edited), get_page_ticks(&chart));
+        assert_eq!(
+            serde_json::to_value(edited.note_list()).unwrap(),
+            serde_json::to_value(chart.note_list()).unwrap()
+        );
+
+        let split_tick = boundary + page_size / 8;
+        edited
+            .split_page(18, split_tick, ScanDirectionMode::Keep)
+            .unwrap();
+        assert_eq!(*edited.page_list()[19].start_tick(), split_tick);
+        assert!(edited
+            .note_list()
+            .iter()
+            .all(|note| *note.page_index() != 19 || *note.tick() >= split_tick));
+        assert!(edited
+            .note_list()
+            .iter()
+            .any(|note| *note.page_index() == 19
+                && *note.tick() < *edited.page_list()[19].end_tick()));
+        assert!(!edited.lint().has_errors());
+        edited.merge_pages(18, ScanDirectionMode::Keep).unwrap();
+        assert_eq!(get_page_ticks(&edited), get_page_ticks(&chart));
+
+        edited.set_page_size(0, page_size * 2).unwrap();
+        assert_eq!(
+            *edited.page_list()[1].start_tick(),
+            chart.page_list()[1].start_tick() + page_size
+        );
+        edited.set_page_size(0, page_size).unwrap();
+        assert_eq!(get_page_ticks(&edited), get_page_ticks(&chart));
+        assert!(edited.set_page_size(18, 1).is_err());
+        assert!(edited
+            .insert_page(boundary + 1, page_size, ScanDirectionMode::Keep)
+            .is_err());
+
+        let deleted_notes = edited
+            .delete_page(18, ScanDirectionMode::Alternate)
+            .unwrap();
+        assert!(!deleted_notes.is_empty());
+        assert!(!edited.lint().has_errors());
+    }
+    #[test]
+    fn test_page_edit_error() {
+        let f = fs::read_to_string(TEST_RESOURCE_ROOT.to_owned() + "test_valid_c2to1_chart.json")
+            .unwrap();
+        let mut chart: CylheimChart = serde_json::from_str(&f).unwrap();
+        // 音符 18 为第 19 页的链头，在子音符 19 处拆页后链跨两页
+        chart
+            .split_page(19, 18300, ScanDirectionMode::Keep)
+            .unwrap();
+        let page_ticks = get_page_ticks(&chart);
+        let note_count = chart.note_list().len();
+        assert!(matches!(
+            chart.delete_page(19, ScanDirectionMode::Keep),
+            Err(CylToolError::DragHeadDeletion {
+                note_id: 18,
+                child_id: 19
+            })
+        ));
+        let last_tick = *chart.page_list().last().unwrap().end_tick();
+        assert!(matches!(
+            chart.insert_page(last_tick, u32::MAX, ScanDirectionMode::Keep),
+            Err(CylToolError::TickOverflow { .. })
+        ));
+        assert!(matches!(
+            chart.set_page_size(1, u32::MAX),
+            Err(CylToolError::TickOverflow { .. })
+        ));
+        assert_eq!(get_page_ticks(&chart), page_ticks);
+        assert_eq!(chart.note_list().len(), note_count);
+    }
+}
@@ -0,0 +1,443 @@
+use super::{utils::CylToolError, CylheimChart};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+
+/// Corrections `CylheimChart::repair` may apply. Every fix is off unless enabled.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct CylheimChartRepairOptions {
+    /// Sort `note_list`, `tempo_list` and `event_order_list` by tick.
+    pub sort_lists: bool,
+    /// Move notes whose tick lies outside their page to the page containing the tick.
+    pub recompute_page_index: bool,
+    /// Set `next_id` to -1 when it points to a missing note.
+    pub drop_dangling_next_id: bool,
+    /// Set `is_forward` on drag children placed on an earlier page than their parent.
+    pub fix_is_forward: bool,
+    /// Clamp note x into `[0, 1]`. An x that is not a number goes to the middle.
+    pub clamp_x: bool,
+    /// Set `has_sibling` from the notes sharing a tick.
+    pub recompute_has_sibling: bool,
+}
+impl CylheimChartRepairOptions {
+    /// Every available fix.
+    pub fn all() -> Self {
+        Self {
+            sort_lists: true,
+            recompute_page_index: true,
+            drop_dangling_next_id: true,
+            fix_is_forward: true,
+            clamp_x: true,
+            recompute_has_sibling: true,
+        }
+    }
+}
+/// One change made by `CylheimChart::repair`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum RepairChange {
+    /// The named list was reordered by tick.
+    SortList {
+        list: String,
+    },
+    PageIndex {
+        note_id: u32,
+        old: u32,
+        new: u32,
+    },
+    NextId {
+        note_id: u32,
+        old: i32,
+    },
+    IsForward {
+        note_id: u32,
+        new: bool,
+    },
+    X {
+        note_id: u32,
+        old: f64,
+        new: f64,
+    },
+    HasSibling {
+        note_id: u32,
+        new: bool,
+    },
+}
+impl fmt::Display for RepairChange {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RepairChange::SortList { list } => write!(f, "Sorted {} by tick.", list),
+            RepairChange::PageIndex { note_id, old, new } => write!(
+                f,
+                "Moved note {} from page {} to page {}.",
+                note_id, old, new
+            ),
+            RepairChange::NextId { note_id, old } => write!(
+                f,
+                "Dropped the link from note {} to missing note {}.",
+                note_id, old
+            ),
+            RepairChange::IsForward { note_id, new } => {
+                write!(f, "Set is_forward of note {} to {}.", note_id, new)
+            }
+            RepairChange::X { note_id, old, new } => {
+                write!(f, "Clamped x of note {} from {} to {}.", note_id, old, new)
+            }
+            RepairChange::HasSibling { note_id, new } => {
+                write!(f, "Set has_sibling of note {} to {}.", note_id, new)
+            }
+        }
+    }
+}
+impl CylheimChart {
+    /// Index of the page holding `tick`. A tick on a page boundary goes to the later page.
+    fn find_page_index(&self, tick: u32) -> Option<u32> {
+        self.page_list()
+            .iter()
+            .rposition(|page| page.contains_tick(tick))
+            .map(|page_index| page_index as u32)
+    }
+    /// Apply the fixes enabled in `options` and list every change made.
+    pub fn repair(&mut self, options: &CylheimChartRepairOptions) -> Vec<RepairChange> {
+        let mut changes: Vec<RepairChange> = Vec::new();
+        if options.sort_lists {
+            let mut sort_list = |list: &str, sorted: bool| {
+                if !sorted {
+                    changes.push(RepairChange::SortList {
+                        list: list.to_string(),
+                    });
+                }
+            };
+            sort_list(
+                "tempo_list",
+                self.tempo_list().is_sorted_by_key(|tempo| *tempo.tick()),
+            );
+            self.tempo_list_mut().sort_by_key(|tempo| *tempo.tick());
+            sort_list(
+                "event_order_list",
+                self.event_order_list()
+                    .is_sorted_by_key(|events| *events.tick()),
+            );
+            self.event_order_list_mut()
+                .sort_by_key(|events| *events.tick());
+            sort_list(
+                "note_list",
+                self.note_list()
+                    .is_sorted_by_key(|note| (*note.tick(), *note.id())),
+            );
+            self.note_list_mut()
+                .sort_by_key(|note| (*note.tick(), *note.id()));
+        }
+        if options.recompute_page_index {
+            for index in 0..self.note_list().len() {
+                let note = &self.note_list()[index];
+                let in_page = self
+                    .page_list()
+                    .get(*note.page_index() as usize)
+                    .is_some_and(|page| page.contains_tick(*note.tick()));
+                if in_page {
+                    continue;
+                }
+                if let Some(page_index) = self.find_page_index(*note.tick()) {
+                    let note = &mut self.note_list_mut()[index];
+                    changes.push(RepairChange::PageIndex {
+                        note_id: *note.id(),
+                        old: *note.page_index(),
+                        new: page_index,
+                    });
+                    note.set_page_index(page_index);
+                }
+            }
+        }
+        if options.drop_dangling_next_id {
+            let note_ids: HashSet<u32> = self.note_list().iter().map(|note| *note.id()).collect();
+            for note in self.note_list_mut() {
+                let next_id = *note.next_id();
+                if note.note_type().is_drag_family()
+                    && next_id >= 0
+                    && !note_ids.contains(&(next_id as u32))
+                {
+                    changes.push(RepairChange::NextId {
+                        note_id: *note.id(),
+                        old: next_id,
+                    });
+                    note.set_next_id(-1);
+                }
+            }
+        }
+        if options.fix_is_forward {
+            let parent_page: HashMap<u32, u32> = self
+                .note_list()
+                .iter()
+                .filter(|note| note.note_type().is_drag_family() && *note.next_id() >= 0)
+                .map(|note| (*note.next_id() as u32, *note.page_index()))
+                .collect();
+            for note in self.note_list_mut() {
+                let Some(parent_page_index) = parent_page.get(note.id()) else {
+                    continue;
+                };
+                // 子节点位于父节点之前的页时需要提前显示
+                let is_forward = note.page_index() < parent_page_index;
+                if note.note_type().is_drag_family() && *note.is_forward() != is_forward {
+                    changes.push(RepairChange::IsForward {
+                        note_id: *note.id(),
+                        new: is_forward,
+                    });
+                    note.set_is_forward(is_forward);
+                }
+            }
+        }
+        if options.clamp_x {
+            for note in self.note_list_mut() {
+                // NaN 无法截取，放到中间
+                let x = match note.x().is_nan() {
+                    true => 0.5,
+                    false => note.x().clamp(0.0, 1.0),
+                };
+                if x != *note.x() {
+                    changes.push(RepairChange::X {
+                        note_id: *note.id(),
+                        old: *note.x(),
+                        new: x,
+                    });
+                    note.set_x(x);
+                }
+            }
+        }
+        if options.recompute_has_sibling {
+            let mut tick_count: HashMap<u32, usize> = HashMap::new();
+            for note in self.note_list() {
+                *tick_count.entry(*note.tick()).or_default() += 1;
+            }
+            for note in self.note_list_mut() {
+                let has_sibling = tick_count[note.tick()] > 1;
+                if *note.has_sibling() != has_sibling {
+                    changes.push(RepairChange::HasSibling {
+                        note_id: *note.id(),
+                        new: has_sibling,
+                    });
+                    note.set_has_sibling(has_sibling);
+                }
+            }
+        }
+        changes
+    }
+    /// Sort `note_list` by (tick, x) and renumber the notes from 0 in that order, remapping
+    /// the `next_id` of every drag note. Returns the old → new id of every note.
+    ///
+    /// Other notes keep their placeholder `next_id`. Fails without changing the chart on a
+    /// duplicate id or a drag note linked to a missing note.
+    pub fn normalize_note_order(&mut self) -> Result<BTreeMap<u32, u32>, CylToolError> {
+        let mut note_list = self.note_list().clone();
+        note_list.sort_by(|a, b| {
+            a.tick()
+                .cmp(b.tick())
+                .then(a.x().total_cmp(b.x()))
+                .then(a.id().cmp(b.id()))
+        });
+        let mut id_map: BTreeMap<u32, u32> = BTreeMap::new();
+        for (new_id, note) in note_list.iter().enumerate() {
+            if id_map.insert(*note.id(), new_id as u32).is_some() {
+                return Err(CylToolError::DuplicateNoteId {
+                    note_id: *note.id(),
+                });
+            }
+        }
+        for note in &mut note_list {
+            if note.note_type().is_drag_family() && *note.next_id() >= 0 {
+                let next_id =
+                    id_map
+                        .get(&(*note.next_id() as u32))
+                        .ok_or(CylToolError::BrokenDragChain {
+                            note_id: *note.id(),
+                            next_id: *note.next_id(),
+                        })?;
+                note.set_next_id(*next_id as i32);
+            }
+            note.set_id(id_map[note.id()]);
+        }
+        self.set_note_list(note_list);
+        Ok(id_map)
+    }
+}
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cylheim_tools::LintIssueKind;
+    use std::fs;
+    const TEST_RESOURCE_ROOT: &str = "./tests/resources/";
+    #[test]
+    fn test_repair() {
+        let f = fs::read_to_string(TEST_RESOURCE_ROOT.to_owned() + "test_valid_c2to1_chart.json")
+            .unwrap();
+        let chart: CylheimChart = serde_json::from_str(&f).unwrap();
+        let mut broken = chart.clone();
+        broken.note_list_mut().reverse();
+        let note_list = broken.note_list_mut();
+        let last = note_list.len() - 1;
+        note_list[last].set_x(-0.5);
+        note_list[last].set_page_index(0);
+        note_list[last].set_has_sibling(true);
+        let head = note_list.iter_mut().find(|note| *note.id() == 18).unwrap();
+        head.set_next_id(100000);
+        assert!(broken.clone().repair(&Default::default()).is_empty());
+
+        let changes = broken.repair(&CylheimChartRepairOptions::all());
+        assert!(changes.contains(&RepairChange::SortList {
+            list: "note_list".to_string()
+        }));
+        assert!(changes.contains(&RepairChange::PageIndex {
+            note_id: 0,
+            old: 0,
+            new: 4
+        }));
+        assert!(changes.contains(&RepairChange::NextId {
+            note_id: 18,
+            old: 100000
+        }));
+        assert!(changes.contains(&RepairChange::X {
+            note_id: 0,
+            old: -0.5,
+            new: 0.0
+        }));
+        assert!(changes.contains(&RepairChange::HasSibling {
+            note_id: 0,
+            new: false
+        }));
+        assert!(!broken.lint().has_errors());
+        // 断开链接后原来的子节点成为孤立节点
+        assert!(broken
+            .lint()
+            .issues()
+            .iter()
+            .any(|issue| *issue.kind() == LintIssueKind::OrphanDragChild { note_id: 19 }));
+        assert!(broken.repair(&CylheimChartRepairOptions::all()).is_empty());
+
+        let fix_is_forward = CylheimChartRepairOptions {
+            fix_is_forward: true,
+            ..Default::default()
+        };
+        let head = chart
+            .note_list()
+            .iter()
+            .find(|note| *note.id() == 18)
+            .unwrap();
+        let (child_id, head_page) = (*head.next_id() as u32, *head.page_index());
+        let find_child = |chart: &CylheimChart| {
+            chart
+                .note_list()
+                .iter()
+                .position(|note| *note.id() == child_id)
+                .unwrap()
+        };
+        // 子节点移到父节点之前的页时需要设置 is_forward
+        let mut forward = chart.clone();
+        let index = find_child(&forward);
+        forward.note_list_mut()[index].set_page_index(head_page - 1);
+        assert_eq!(
+            forward.repair(&fix_is_forward),
+            vec![RepairChange::IsForward {
+                note_id: child_id,
+                new: true
+            }]
+        );
+        assert!(*forward.note_list()[index].is_forward());
+        // 与父节点同页的子节点不应保留 is_forward
+        let mut backward = chart.clone();
+        let index = find_child(&backward);
+        backward.note_list_mut()[index].set_is_forward(true);
+        assert_eq!(
+            backward.repair(&fix_is_forward),
+            vec![RepairChange::IsForward {
+                note_id: child_id,
+                new: false
+            }]
+        );
+        assert!(!*backward.note_list()[index].is_forward());
+
+        // 页边界上的音符属于两侧的页，重新分配时放到后一页
+        let mut boundary = chart.clone();
+        let index = boundary
+            .note_list()
+            .iter()
+            .position(|note| {
+                *note.page_index() > 1
+                    && *note.tick() == *chart.page_list()[*note.page_index() as usize].start_tick()
+            })
+            .unwrap();
+        let page_index = *boundary.note_list()[index].page_index();
+        let note_id = *boundary.note_list()[index].id();
+        let recompute_page_index = CylheimChartRepairOptions {
+            recompute_page_index: true,
+            ..Default::default()
+        };
+        boundary.note_list_mut()[index].set_page_index(page_index - 1);
+        assert!(boundary.repair(&recompute_page_index).is_empty());
+        boundary.note_list_mut()[index].set_page_index(0);
+        assert_eq!(
+            boundary.repair(&recompute_page_index),
+            vec![RepairChange::PageIndex {
+                note_id,
+                old: 0,
+                new: page_index
+            }]
+        );
+
+        let clamp_x = CylheimChartRepairOptions {
+            clamp_x: true,
+            ..Default::default()
+        };
+        let mut nan_x = chart.clone();
+        nan_x.note_list_mut()[0].set_x(f64::NAN);
+        let changes = nan_x.repair(&clamp_x);
+        assert!(matches!(
+            changes[..],
+            [RepairChange::X { note_id: 0, old, new: 0.5 }] if old.is_nan()
+        ));
+        assert!(nan_x.repair(&clamp_x).is_empty());
+    }
+    #[test]
+    fn test_normalize_note_order() {
+        let f = fs::read_to_string(TEST_RESOURCE_ROOT.to_owned() + "test_valid_c2to1_chart.json")
+            .unwrap();
+        let chart: CylheimChart = serde_json::from_str(&f).unwrap();
+        // 打乱顺序并留出空缺编号
+        let mut shuffled = chart.clone();
+        for note in shuffled.note_list_mut() {
+            note.set_id(note.id() * 2 + 10);
+            if note.note_type().is_drag_family() && *note.next_id() >= 0 {
+                note.set_next_id(note.next_id() * 2 + 10);
+            }
+        }
+        shuffled.note_list_mut().reverse();
+        let id_map = shuffled.normalize_note_order().unwrap();
+        assert_eq!(id_map.len(), chart.note_list().len());
+        assert!(shuffled
+            .note_list()
+            .iter()
+            .enumerate()
+            .all(|(i, note)| *note.id() == i as u32));
+        assert!(shuffled
+            .note_list()
+            .windows(2)
+            .all(|pair| (pair[0].tick(), pair[0].x()) <= (pair[1].tick(), pair[1].x())));
+        assert!(!shuffled.lint().has_errors());
+        let head = chart
+            .note_list()
+            .iter()
+            .find(|note| *note.id() == 18)
+            .unwrap();
+        let new_head = &shuffled.note_list()[id_map[&(18 * 2 + 10)] as usize];
+        assert_eq!(
+            *new_head.next_id() as u32,
+            id_map[&(*head.next_id() as u32 * 2 + 10)]
+        );
+
+        shuffled.note_list_mut()[0].set_id(1);
+        assert!(matches!(
+            shuffled.normalize_note_order(),
+            Err(CylToolError::DuplicateNoteId { note_id: 1 })
+        ));
+        assert_eq!(*shuffled.note_list()[0].id(), 1);
+    }
+}
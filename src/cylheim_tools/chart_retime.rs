@@ -0,0 +1,446 @@
+use super::{
+    cyl_chart::{CylheimChartTempo, CylheimChartTickEventList},
+    tempo_map::{Rational, TempoMap},
+    utils::CylToolError,
+    CylheimChart,
+};
+use getset::Getters;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// What `CylheimChart::rescale_time_base` does with a tick the new time base cannot hold.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum InexactTickStrategy {
+    /// Refuse the rescale and leave the chart unchanged.
+    #[default]
+    Refuse,
+    /// Round to the nearest tick of the new time base.
+    Snap,
+}
+/// The tick field a retiming report entry refers to.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum TickLocation {
+    PageStart {
+        page_index: u32,
+    },
+    PageEnd {
+        page_index: u32,
+    },
+    /// Index in the tempo list being retimed.
+    Tempo {
+        tempo_index: u32,
+    },
+    /// The event list at `tick`, given in the old timing.
+    Event {
+        tick: u32,
+    },
+    Note {
+        note_id: u32,
+    },
+    /// The end of a hold, `tick + hold_tick`.
+    HoldEnd {
+        note_id: u32,
+    },
+}
+impl fmt::Display for TickLocation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TickLocation::PageStart { page_index } => write!(f, "start of page {}", page_index),
+            TickLocation::PageEnd { page_index } => write!(f, "end of page {}", page_index),
+            TickLocation::Tempo { tempo_index } => write!(f, "tempo {}", tempo_index),
+            TickLocation::Event { tick } => write!(f, "events at tick {}", tick),
+            TickLocation::Note { note_id } => write!(f, "note {}", note_id),
+            TickLocation::HoldEnd { note_id } => write!(f, "hold end of note {}", note_id),
+        }
+    }
+}
+/// A tick that moved off its exact position while retiming the chart.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Getters)]
+pub struct QuantizedTick {
+    #[serde(flatten)]
+    #[getset(get = "pub")]
+    location: TickLocation,
+    /// Tick before retiming.
+    #[getset(get = "pub")]
+    old_tick: u32,
+    #[getset(get = "pub")]
+    new_tick: u32,
+    /// `new_tick` minus the exact position, in new ticks.
+    #[getset(get = "pub")]
+    tick_error: f64,
+}
+impl fmt::Display for QuantizedTick {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Moved {} from tick {} to {} ({:+.3} ticks off).",
+            self.location, self.old_tick, self.new_tick, self.tick_error
+        )
+    }
+}
+/// Rounds exact ticks into whole ones and records every tick that had to move.
+struct TickQuantizer {
+    quantized_ticks: Vec<QuantizedTick>,
+}
+impl TickQuantizer {
+    fn new() -> Self {
+        Self {
+            quantized_ticks: Vec::new(),
+        }
+    }
+    fn quantize(&mut self, location: TickLocation, old_tick: u32, exact_tick: Rational) -> u32 {
+        let new_tick = exact_tick.round().clamp(0, u32::MAX as i128) as u32;
+        if exact_tick != Rational::from(new_tick) {
+            self.quantized_ticks.push(QuantizedTick {
+                location,
+                old_tick,
+                new_tick,
+                tick_error: (Rational::from(new_tick) - exact_tick).to_f64(),
+            });
+        }
+        new_tick
+    }
+}
+/// Fail when two tempos that had different ticks in `old_ticks` now share a tick.
+/// `tempo_list` is in the order of `old_ticks`.
+fn check_tempo_collision(
+    old_ticks: &[u32],
+    tempo_list: &[CylheimChartTempo],
+) -> Result<(), CylToolError> {
+    let mut ticks: Vec<(u32, u32)> = tempo_list
+        .iter()
+        .zip(old_ticks)
+        .map(|(tempo, old_tick)| (*tempo.tick(), *old_tick))
+        .collect();
+    ticks.sort();
+    ticks.dedup();
+    match ticks.windows(2).find(|pair| pair[0].0 == pair[1].0) {
+        Some(pair) => Err(CylToolError::TempoCollision {
+            tick: pair[0].1,
+            other_tick: pair[1].1,
+        }),
+        None => Ok(()),
+    }
+}
+/// Sort `tempo_list` by tick; of tempos sharing a tick only the last one is kept.
+pub(super) fn dedup_tempo_list(tempo_list: &mut Vec<CylheimChartTempo>) {
+    tempo_list.sort_by_key(|tempo| *tempo.tick());
+    tempo_list.reverse();
+    tempo_list.dedup_by_key(|tempo| *tempo.tick());
+    tempo_list.reverse();
+}
+/// Sort `event_order_list` by tick, joining event lists that share a tick.
+pub(super) fn merge_event_order_list(event_order_list: &mut Vec<CylheimChartTickEventList>) {
+    let mut merged_list: Vec<CylheimChartTickEventList> = Vec::new();
+    for mut events in std::mem::take(event_order_list) {
+        match merged_list
+            .iter_mut()
+            .find(|merged| merged.tick() == events.tick())
+        {
+            Some(merged) => merged.event_list_mut().append(events.event_list_mut()),
+            None => merged_list.push(events),
+        }
+    }
+    merged_list.sort_by_key(|events| *events.tick());
+    *event_order_list = merged_list;
+}
+impl CylheimChart {
+    /// Move every page, event and note tick through `map_tick`, which returns the exact new
+    /// position of an old tick. Event lists landing on one tick are merged.
+    fn retime_ticks(&mut self, quantizer: &mut TickQuantizer, map_tick: impl Fn(u32) -> Rational) {
+        for (page_index, page) in self.page_list_mut().iter_mut().enumerate() {
+            let page_index = page_index as u32;
+            let start_tick = *page.start_tick();
+            let end_tick = *page.end_tick();
+            page.set_start_tick(quantizer.quantize(
+                TickLocation::PageStart { page_index },
+                start_tick,
+                map_tick(start_tick),
+            ));
+            page.set_end_tick(quantizer.quantize(
+                TickLocation::PageEnd { page_index },
+                end_tick,
+                map_tick(end_tick),
+            ));
+        }
+        for events in self.event_order_list_mut() {
+            let tick = *events.tick();
+            events.set_tick(quantizer.quantize(TickLocation::Event { tick }, tick, map_tick(tick)));
+        }
+        merge_event_order_list(self.event_order_list_mut());
+        for note in self.note_list_mut() {
+            let note_id = *note.id();
+            let tick = *note.tick();
+            let end_tick = tick + *note.hold_tick();
+            let new_tick = quantizer.quantize(TickLocation::Note { note_id }, tick, map_tick(tick));
+            note.set_tick(new_tick);
+            if end_tick != tick {
+                // 按终点取整，使长条结束位置不因起点取整而偏移
+                let new_end_tick = quantizer.quantize(
+                    TickLocation::HoldEnd { note_id },
+                    end_tick,
+                    map_tick(end_tick),
+                );
+                note.set_hold_tick(new_end_tick.saturating_sub(new_tick));
+            }
+        }
+    }
+    /// Rescale every tick of the chart to `time_base` ticks per beat.
+    ///
+    /// Returns the ticks that could not be placed exactly. With `InexactTickStrategy::Refuse`
+    /// any such tick fails the rescale with all of them listed, and the chart is left as it
+    /// was. Tempos that would snap onto one tick always fail the rescale.
+    pub fn rescale_time_base(
+        &mut self,
+        time_base: u32,
+        strategy: InexactTickStrategy,
+    ) -> Result<Vec<QuantizedTick>, CylToolError> {
+        if time_base == 0 {
+            return Err(CylToolError::InvalidTimeBase { time_base });
+        }
+        let old_time_base = *self.time_base();
+        let map_tick =
+            |tick: u32| Rational::new(tick as i128 * time_base as i128, old_time_base as i128);
+        let mut rescaled = self.clone();
+        let mut quantizer = TickQuantizer::new();
+        let old_ticks: Vec<u32> = self
+            .tempo_list()
+            .iter()
+            .map(|tempo| *tempo.tick())
+            .collect();
+        for (tempo_index, tempo) in rescaled.tempo_list_mut().iter_mut().enumerate() {
+            let tick = *tempo.tick();
+            tempo.set_tick(quantizer.quantize(
+                TickLocation::Tempo {
+                    tempo_index: tempo_index as u32,
+                },
+                tick,
+                map_tick(tick),
+            ));
+        }
+        check_tempo_collision(&old_ticks, rescaled.tempo_list())?;
+        dedup_tempo_list(rescaled.tempo_list_mut());
+        rescaled.retime_ticks(&mut quantizer, map_tick);
+        let quantized_ticks = quantizer.quantized_ticks;
+        if strategy == InexactTickStrategy::Refuse && !quantized_ticks.is_empty() {
+            return Err(CylToolError::InexactTick {
+                time_base,
+                quantized_ticks,
+            });
+        }
+        rescaled.set_time_base(time_base);
+        *self = rescaled;
+        Ok(quantized_ticks)
+    }
+    /// Switch to `tempo_list`, which differs from the current one after `edit_tick` only, and
+    /// move every later tick so it keeps its time.
+    fn retime_tempo_list(
+        &mut self,
+        edit_tick: u32,
+        mut tempo_list: Vec<CylheimChartTempo>,
+    ) -> Result<Vec<QuantizedTick>, CylToolError> {
+        let time_base = *self.time_base();
+        let start_offset_time = *self.start_offset_time();
+        let old_tempo_map = self.get_tempo_map()?;
+        tempo_list.sort_by_key(|tempo| *tempo.tick());
+        let mut quantizer = TickQuantizer::new();
+        let mut new_tempo_list: Vec<CylheimChartTempo> = Vec::new();
+        let old_ticks: Vec<u32> = tempo_list.iter().map(|tempo| *tempo.tick()).collect();
+        // 逐个放置之后的 tempo：每个 tempo 的位置取决于它之前的 tempo
+        for (tempo_index, mut tempo) in tempo_list.into_iter().enumerate() {
+            let tick = *tempo.tick();
+            if tick > edit_tick {
+                let exact_tick = TempoMap::new(time_base, &new_tempo_list, start_offset_time)?
+                    .get_time_tick(old_tempo_map.get_tick_time(tick))
+                    .ok_or(CylToolError::MissingTempo)?;
+                tempo.set_tick(quantizer.quantize(
+                    TickLocation::Tempo {
+                        tempo_index: tempo_index as u32,
+                    },
+                    tick,
+                    exact_tick,
+                ));
+            }
+            new_tempo_list.push(tempo);
+        }
+        check_tempo_collision(&old_ticks, &new_tempo_list)?;
+        dedup_tempo_list(&mut new_tempo_list);
+        let new_tempo_map = TempoMap::new(time_base, &new_tempo_list, start_offset_time)?;
+        if new_tempo_map.get_time_tick(Rational::ZERO).is_none() {
+            return Err(CylToolError::MissingTempo);
+        }
+        let mut retimed = self.clone();
+        retimed.set_tempo_list(new_tempo_list);
+        retimed.retime_ticks(&mut quantizer, |tick| {
+            if tick <= edit_tick {
+                tick.into()
+            } else {
+                new_tempo_map
+                    .get_time_tick(old_tempo_map.get_tick_time(tick))
+                    .unwrap_or(tick.into())
+            }
+        });
+        *self = retimed;
+        Ok(quantizer.quantized_ticks)
+    }
+    fn find_tempo_index(&self, tick: u32) -> Result<usize, CylToolError> {
+        self.tempo_list()
+            .iter()
+            .position(|tempo| *tempo.tick() == tick)
+            .ok_or(CylToolError::UnknownTempo { tick })
+    }
+    /// Add a tempo of `value` microseconds per beat at `tick`, keeping every note, page and
+    /// event at its time. Returns the ticks that moved off their exact position.
+    pub fn insert_tempo(
+        &mut self,
+        tick: u32,
+        value: u32,
+    ) -> Result<Vec<QuantizedTick>, CylToolError> {
+        if value == 0 {
+            return Err(CylToolError::TempoMismatch { tick, value });
+        }
+        if self.find_tempo_index(tick).is_ok() {
+            return Err(CylToolError::DuplicateTempo { tick });
+        }
+        let mut tempo_list = self.tempo_list().clone();
+        tempo_list.push(CylheimChartTempo::new(tick, value));
+        self.retime_tempo_list(tick, tempo_list)
+    }
+    /// Remove the tempo at `tick`, keeping every note, page and event at its time. The first
+    /// tempo cannot be removed.
+    pub fn remove_tempo(&mut self, tick: u32) -> Result<Vec<QuantizedTick>, CylToolError> {
+        let tempo_index = self.find_tempo_index(tick)?;
+        if self.tempo_list().iter().all(|tempo| *tempo.tick() >= tick) {
+            return Err(CylToolError::FirstTempoRemoval { tick });
+        }
+        let mut tempo_list = self.tempo_list().clone();
+        tempo_list.remove(tempo_index);
+        self.retime_tempo_list(tick, tempo_list)
+    }
+    /// Change the tempo at `tick` to `value` microseconds per beat, keeping every note, page
+    /// and event at its time.
+    pub fn set_tempo_value(
+        &mut self,
+        tick: u32,
+        value: u32,
+    ) -> Result<Vec<QuantizedTick>, CylToolError> {
+        if value == 0 {
+            return Err(CylToolError::TempoMismatch { tick, value });
+        }
+        let tempo_index = self.find_tempo_index(tick)?;
+        let mut tempo_list = self.tempo_list().clone();
+        tempo_list[tempo_index].set_value(value);
+        self.retime_tempo_list(tick, tempo_list)
+    }
+}
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::fs;
+    const TEST_RESOURCE_ROOT: &str = "./tests/resources/";
+    #[test]
+    fn test_rescale_time_base() {
+        let f = fs::read_to_string(TEST_RESOURCE_ROOT.to_owned() + "test_cyl_chart.json").unwrap();
+        let chart: CylheimChart = serde_json::from_str(&f).unwrap();
+        let mut rescaled = chart.clone();
+        let quantized_ticks = rescaled
+            .rescale_time_base(960, InexactTickStrategy::Refuse)
+            .unwrap();
+        assert!(quantized_ticks.is_empty());
+        assert_eq!(*rescaled.time_base(), 960);
+        assert_eq!(
+            *rescaled.note_list()[1].tick(),
+            2 * *chart.note_list()[1].tick()
+        );
+        let tempo_map = chart.get_tempo_map().unwrap();
+        let rescaled_tempo_map = rescaled.get_tempo_map().unwrap();
+        for (note, rescaled_note) in chart.note_list().iter().zip(rescaled.note_list()) {
+            assert_eq!(
+                tempo_map.get_tick_time(*note.tick()),
+                rescaled_tempo_map.get_tick_time(*rescaled_note.tick())
+            );
+        }
+        rescaled
+            .rescale_time_base(480, InexactTickStrategy::Refuse)
+            .unwrap();
+        assert_eq!(
+            serde_json::to_value(&rescaled).unwrap(),
+            serde_json::to_value(&chart).unwrap()
+        );
+
+        let mut coarse = chart.clone();
+        let refused_ticks = match coarse.rescale_time_base(7, InexactTickStrategy::Refuse) {
+            Err(CylToolError::InexactTick {
+                quantized_ticks, ..
+            }) => quantized_ticks,
+            result => panic!("unexpected result {:?}", result),
+        };
+        assert_eq!(*coarse.time_base(), 480);
+        let quantized_ticks = coarse
+            .rescale_time_base(7, InexactTickStrategy::Snap)
+            .unwrap();
+        // 拒绝时列出的应是取整时会移动的全部 tick
+        assert!(quantized_ticks.len() > 1);
+        assert_eq!(refused_ticks, quantized_ticks);
+        assert!(quantized_ticks
+            .iter()
+            .all(|quantized_tick| quantized_tick.tick_error.abs() <= 0.5));
+        assert_eq!(*coarse.time_base(), 7);
+
+        // 取整到同一 tick 的两个 tempo 不能静默合并
+        let mut crowded = chart.clone();
+        let mut tempo_list = crowded.tempo_list().clone();
+        tempo_list.push(CylheimChartTempo::new(1, 500000));
+        crowded.set_tempo_list(tempo_list);
+        let error = crowded
+            .rescale_time_base(7, InexactTickStrategy::Snap)
+            .unwrap_err();
+        assert_eq!(error.kind(), "tempo_collision");
+        assert_eq!(*crowded.time_base(), 480);
+        assert_eq!(crowded.tempo_list().len(), chart.tempo_list().len() + 1);
+    }
+    #[test]
+    fn test_tempo_edit() {
+        let f = fs::read_to_string(TEST_RESOURCE_ROOT.to_owned() + "test_cyl_chart.json").unwrap();
+        let chart: CylheimChart = serde_json::from_str(&f).unwrap();
+        let tempo_map = chart.get_tempo_map().unwrap();
+        let note_time = |chart: &CylheimChart| -> Vec<f64> {
+            let tempo_map = chart.get_tempo_map().unwrap();
+            chart
+                .note_list()
+                .iter()
+                .map(|note| tempo_map.get_tick_time(*note.tick()).to_f64())
+                .collect()
+        };
+        let split_tick = *chart.page_list()[4].start_tick();
+        let value = tempo_map.get_tempo_value_at_tick(split_tick).unwrap();
+
+        // 半速 tempo 使之后的 tick 减半，时间不变
+        let mut edited = chart.clone();
+        let quantized_ticks = edited.insert_tempo(split_tick, value * 2).unwrap();
+        for quantized_tick in &quantized_ticks {
+            assert!(quantized_tick.tick_error().abs() <= 0.5);
+        }
+        let tick_second = value as f64 / *chart.time_base() as f64 / 1_000_000.0;
+        for (time, edited_time) in note_time(&chart).iter().zip(note_time(&edited)) {
+            assert!((time - edited_time).abs() <= tick_second);
+        }
+        let later_page = &edited.page_list()[6];
+        assert_eq!(
+            *later_page.start_tick(),
+            split_tick + (*chart.page_list()[6].start_tick() - split_tick) / 2
+        );
+        assert!(edited.insert_tempo(split_tick, value).is_err());
+
+        let mut restored = edited.clone();
+        restored.set_tempo_value(split_tick, value).unwrap();
+        restored.remove_tempo(split_tick).unwrap();
+        assert_eq!(restored.tempo_list().len(), chart.tempo_list().len());
+        for (time, restored_time) in note_time(&chart).iter().zip(note_time(&restored)) {
+            assert!((time - restored_time).abs() <= tick_second);
+        }
+        assert!(restored.remove_tempo(0).is_err());
+        assert!(restored.set_tempo_value(0, 0).is_err());
+    }
+}
@@ -0,0 +1,131 @@
+use super::{cytus1_chart::Cytus1Chart, utils::CylToolError, CylheimChart};
+use getset::Getters;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+#[derive(Serialize, Deserialize, Debug, Clone, Getters)]
+pub struct CylheimChartStats {
+    #[getset(get = "pub")]
+    time_base: u32,
+    #[getset(get = "pub")]
+    page_count: usize,
+    #[getset(get = "pub")]
+    tempo_count: usize,
+    #[getset(get = "pub")]
+    event_count: usize,
+    #[getset(get = "pub")]
+    note_count: usize,
+    /// Note count keyed by the raw note type id.
+    #[getset(get = "pub")]
+    note_type_count: BTreeMap<u32, usize>,
+    #[getset(get = "pub")]
+    min_bpm: f64,
+    #[getset(get = "pub")]
+    max_bpm: f64,
+    /// Seconds from tick 0 to the end of the last note.
+    #[getset(get = "pub")]
+    duration: f64,
+}
+#[derive(Serialize, Deserialize, Debug, Clone, Getters)]
+pub struct Cytus1ChartStats {
+    #[getset(get = "pub")]
+    bpm: f64,
+    #[getset(get = "pub")]
+    page_size: f64,
+    #[getset(get = "pub")]
+    note_count: usize,
+    #[getset(get = "pub")]
+    hold_count: usize,
+    #[getset(get = "pub")]
+    link_count: usize,
+    /// Seconds to the end of the last note.
+    #[getset(get = "pub")]
+    duration: f64,
+}
+impl CylheimChart {
+    /// Fails when the time base is zero.
+    pub fn get_stats(&self) -> Result<CylheimChartStats, CylToolError> {
+        let mut note_type_count: BTreeMap<u32, usize> = BTreeMap::new();
+        for note in self.note_list() {
+            *note_type_count
+                .entry(note.note_type().get_id())
+                .or_default() += 1;
+        }
+        let bpm_list: Vec<f64> = self
+            .tempo_list()
+            .iter()
+            .filter(|tempo| *tempo.value() > 0)
+            .map(|tempo| 60_000_000.0 / *tempo.value() as f64)
+            .collect();
+        let last_tick = self
+            .note_list()
+            .iter()
+            .map(|note| note.tick().saturating_add(*note.hold_tick()))
+            .max()
+            .unwrap_or(0);
+        Ok(CylheimChartStats {
+            time_base: *self.time_base(),
+            page_count: self.page_list().len(),
+            tempo_count: self.tempo_list().len(),
+            event_count: self
+                .event_order_list()
+                .iter()
+                .map(|events| events.event_list().len())
+                .sum(),
+            note_count: self.note_list().len(),
+            note_type_count,
+            min_bpm: bpm_list.iter().copied().fold(f64::NAN, f64::min),
+            max_bpm: bpm_list.iter().copied().fold(f64::NAN, f64::max),
+            duration: self.get_tempo_map()?.get_tick_time(last_tick).to_f64(),
+        })
+    }
+}
+impl Cytus1Chart {
+    pub fn get_stats(&self) -> Cytus1ChartStats {
+        Cytus1ChartStats {
+            bpm: *self.bpm(),
+            page_size: *self.page_size(),
+            note_count: self.notes().len(),
+            hold_count: self
+                .notes()
+                .iter()
+                .filter(|note| *note.hold_length() > 0.0)
+                .count(),
+            link_count: self.links().len(),
+            duration: self
+                .notes()
+                .iter()
+                .map(|note| note.time() + note.hold_length())
+                .fold(0.0, f64::max),
+        }
+    }
+}
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::fs;
+    #[test]
+    fn test_cylheim_chart_stats() {
+        let path = "./tests/resources/cylchart_with_cytus1_flag.json";
+        let f = fs::read_to_string(path).unwrap();
+        let chart: CylheimChart = serde_json::from_str(&f).unwrap();
+        let stats = chart.get_stats().unwrap();
+        assert_eq!(stats.note_count, 707);
+        assert_eq!(stats.note_type_count[&1], 15);
+        assert_eq!(stats.min_bpm, 160.0);
+        assert_eq!(stats.max_bpm, 160.0);
+        assert_eq!(stats.event_count, 1);
+    }
+    #[test]
+    fn test_cytus1_chart_stats() {
+        let chart: Cytus1Chart = "VERSION 2\nBPM 120\nPAGE_SHIFT 0\nPAGE_SIZE 2\n\
+                                  NOTE 0 1.0 0.5 0\nNOTE 1 2.0 0.5 1.5\n\
+                                  NOTE 2 2.5 0.2 0\nNOTE 3 3.0 0.3 0\nLINK 2 3\n"
+            .parse()
+            .unwrap();
+        let stats = chart.get_stats();
+        assert_eq!(stats.note_count, 4);
+        assert_eq!(stats.hold_count, 1);
+        assert_eq!(stats.link_count, 1);
+        assert_eq!(stats.duration, 3.5);
+    }
+}
@@ -0,0 +1,329 @@
+use super::{
+    chart_retime::{dedup_tempo_list, merge_event_order_list},
+    cyl_chart::CylheimChartTempo,
+    cytus1_chart::Cytus1Chart,
+    utils::CylToolError,
+    CylheimChart, CylheimChartRepairOptions,
+};
+
+fn check_x_range(low: f64, high: f64) -> Result<(), CylToolError> {
+    if (0.0..=1.0).contains(&low) && (low..=1.0).contains(&high) {
+        Ok(())
+    } else {
+        Err(CylToolError::InvalidXRange { low, high })
+    }
+}
+fn check_rate(rate: f64) -> Result<(), CylToolError> {
+    if rate.is_finite() && rate > 0.0 {
+        Ok(())
+    } else {
+        Err(CylToolError::InvalidRate { rate })
+    }
+}
+fn check_page_size(page_size: f64) -> Result<(), CylToolError> {
+    if page_size.is_finite() && page_size > 0.0 {
+        Ok(())
+    } else {
+        Err(CylToolError::InvalidPageSize { page_size })
+    }
+}
+impl CylheimChart {
+    /// Flip every note horizontally, x → 1 − x.
+    pub fn mirror_x(&mut self) {
+        for note in self.note_list_mut() {
+            note.set_x(1.0 - note.x());
+        }
+    }
+    /// Squeeze x from `[0, 1]` into `[low, high]`.
+    pub fn compress_x(&mut self, low: f64, high: f64) -> Result<(), CylToolError> {
+        check_x_range(low, high)?;
+        for note in self.note_list_mut() {
+            note.set_x(low + note.x() * (high - low));
+        }
+        Ok(())
+    }
+    /// Move the whole chart `seconds` later in the song through `start_offset_time`.
+    ///
+    /// Fails without changing the chart when a note would start before 0.
+    pub fn shift_seconds(&mut self, seconds: f64) -> Result<(), CylToolError> {
+        let start_offset_time = self.start_offset_time() + seconds;
+        let tempo_map = self.get_tempo_map()?;
+        for note in self.note_list() {
+            let time = start_offset_time + tempo_map.get_tick_time(*note.tick()).to_f64();
+            if time < 0.0 {
+                return Err(CylToolError::NoteBeforeStart {
+                    note_id: *note.id(),
+                    time,
+                });
+            }
+        }
+        self.set_start_offset_time(start_offset_time);
+        Ok(())
+    }
+    /// Move every note, tempo and event `ticks` later, keeping the pages where they are.
+    ///
+    /// The opening tempo also covers the ticks opened up before it. Page indexes and
+    /// `is_forward` follow the notes. Fails without changing the chart when a note would
+    /// leave the pages.
+    pub fn shift_ticks(&mut self, ticks: i64) -> Result<(), CylToolError> {
+        let page_list = self.page_list().clone();
+        let end_tick = *page_list
+            .last()
+            .ok_or(CylToolError::MissingPage)?
+            .end_tick() as i64;
+        // 位于页尾的音符移动后仍放在以该 tick 结尾的页上
+        let find_page = |tick: i64, at_page_end: bool| {
+            let position = |at_page_end: bool| {
+                page_list.iter().position(|page| match at_page_end {
+                    true => *page.end_tick() as i64 == tick,
+                    false => *page.start_tick() as i64 <= tick && tick < *page.end_tick() as i64,
+                })
+            };
+            position(at_page_end).or_else(|| position(!at_page_end))
+        };
+        let mut page_indexes: Vec<u32> = Vec::new();
+        for note in self.note_list() {
+            let tick = *note.tick() as i64 + ticks;
+            let at_page_end = page_list
+                .get(*note.page_index() as usize)
+                .is_some_and(|page| page.end_tick() == note.tick());
+            match find_page(tick, at_page_end) {
+                Some(page_index) if tick + *note.hold_tick() as i64 <= end_tick => {
+                    page_indexes.push(page_index as u32)
+                }
+                _ => {
+                    return Err(CylToolError::NoteOutsidePages {
+                        note_id: *note.id(),
+                    })
+                }
+            }
+        }
+        let shift = |tick: u32| (tick as i64 + ticks).max(0) as u32;
+        let mut tempo_list = self.tempo_list().clone();
+        tempo_list.sort_by_key(|tempo| *tempo.tick());
+        let opening_tempo = tempo_list.first().cloned();
+        for tempo in &mut tempo_list {
+            tempo.set_tick(shift(*tempo.tick()));
+        }
+        if let Some(opening_tempo) = opening_tempo.filter(|_| ticks > 0) {
+            tempo_list.insert(
+                0,
+                CylheimChartTempo::new(*opening_tempo.tick(), *opening_tempo.value()),
+            );
+        }
+        // 移到 0 之前的 tempo 合并到 0，只保留其中最后一个
+        dedup_tempo_list(&mut tempo_list);
+        self.set_tempo_list(tempo_list);
+        for events in self.event_order_list_mut() {
+            events.set_tick(shift(*events.tick()));
+        }
+        merge_event_order_list(self.event_order_list_mut());
+        for (note, page_index) in self.note_list_mut().iter_mut().zip(page_indexes) {
+            note.set_tick(shift(*note.tick()));
+            note.set_page_index(page_index);
+        }
+        self.repair(&CylheimChartRepairOptions {
+            fix_is_forward: true,
+            ..Default::default()
+        });
+        Ok(())
+    }
+    /// Play the chart `rate` times as fast by scaling every tempo and `start_offset_time`.
+    /// Ticks are unchanged.
+    ///
+    /// Tempo values are rounded to whole microseconds. Fails without changing the chart when
+    /// a tempo cannot be expressed.
+    pub fn change_rate(&mut self, rate: f64) -> Result<(), CylToolError> {
+        check_rate(rate)?;
+        let mut tempo_list = self.tempo_list().clone();
+        for tempo in &mut tempo_list {
+            let value = (*tempo.value() as f64 / rate).round();
+            if value > u32::MAX as f64 || (value == 0.0 && *tempo.value() > 0) {
+                return Err(CylToolError::TempoMismatch {
+                    tick: *tempo.tick(),
+                    value: *tempo.value(),
+                });
+            }
+            tempo.set_value(value as u32);
+        }
+        self.set_tempo_list(tempo_list);
+        self.set_start_offset_time(self.start_offset_time() / rate);
+        Ok(())
+    }
+}
+impl Cytus1Chart {
+    /// Flip every note horizontally, x → 1 − x.
+    pub fn mirror_x(&mut self) {
+        let mut notes = self.notes().clone();
+        for note in &mut notes {
+            note.set_x(1.0 - note.x());
+        }
+        self.set_notes(notes);
+    }
+    /// Squeeze x from `[0, 1]` into `[low, high]`.
+    pub fn compress_x(&mut self, low: f64, high: f64) -> Result<(), CylToolError> {
+        check_x_range(low, high)?;
+        let mut notes = self.notes().clone();
+        for note in &mut notes {
+            note.set_x(low + note.x() * (high - low));
+        }
+        self.set_notes(notes);
+        Ok(())
+    }
+    /// Move every note `seconds` later, moving `page_shift` with them so notes stay where
+    /// they were on screen.
+    ///
+    /// A `page_shift` that was not negative is brought back to `[0, 2 * page_size)` when the
+    /// shift would make it negative; a negative one is left as shifted. Fails without changing
+    /// the chart when the page size is not positive or a note would start before 0.
+    pub fn shift_seconds(&mut self, seconds: f64) -> Result<(), CylToolError> {
+        check_page_size(*self.page_size())?;
+        let mut notes = self.notes().clone();
+        for note in &mut notes {
+            let time = note.time() + seconds;
+            if time < 0.0 {
+                return Err(CylToolError::NoteBeforeStart {
+                    note_id: *note.id(),
+                    time,
+                });
+            }
+            note.set_time(time);
+        }
+        // 音符位置为 (time + page_shift) / page_size，两页为一个周期
+        let mut page_shift = self.page_shift() - seconds;
+        if page_shift < 0.0 && *self.page_shift() >= 0.0 {
+            page_shift = page_shift.rem_euclid(2.0 * self.page_size());
+        }
+        self.set_notes(notes);
+        self.set_page_shift(page_shift);
+        Ok(())
+    }
+    /// Play the chart `rate` times as fast, scaling every time, length and the BPM.
+    pub fn change_rate(&mut self, rate: f64) -> Result<(), CylToolError> {
+        check_rate(rate)?;
+        let mut notes = self.notes().clone();
+        for note in &mut notes {
+            note.set_time(note.time() / rate);
+            note.set_hold_length(note.hold_length() / rate);
+        }
+        self.set_notes(notes);
+        self.set_bpm(self.bpm() * rate);
+        self.set_page_size(self.page_size() / rate);
+        self.set_page_shift(self.page_shift() / rate);
+        Ok(())
+    }
+}
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::fs;
+    const TEST_RESOURCE_ROOT: &str = "./tests/resources/";
+    fn get_position(chart: &Cytus1Chart, index: usize) -> f64 {
+        let note = &chart.notes()[index];
+        ((note.time() + chart.page_shift()) / chart.page_size()).rem_euclid(2.0)
+    }
+    #[test]
+    fn test_chart_transform() {
+        let f = fs::read_to_string(TEST_RESOURCE_ROOT.to_owned() + "test_valid_c2to1_chart.json")
+            .unwrap();
+        let chart: CylheimChart = serde_json::from_str(&f).unwrap();
+        let cytus1_chart = chart.to_cytus1_chart_with_pageshift(false).unwrap();
+
+        let mut transformed = chart.clone();
+        transformed.mirror_x();
+        transformed.compress_x(0.25, 0.75).unwrap();
+        let x = *chart.note_list()[0].x();
+        assert_eq!(*transformed.note_list()[0].x(), 0.25 + (1.0 - x) * 0.5);
+        assert!(transformed.compress_x(0.5, 0.25).is_err());
+
+        // 同一转换作用于两种谱面应得到一致的结果
+        let mut transformed = chart.clone();
+        transformed.change_rate(2.0).unwrap();
+        transformed.shift_seconds(1.0).unwrap();
+        let mut c1_transformed = cytus1_chart.clone();
+        c1_transformed.change_rate(2.0).unwrap();
+        c1_transformed.shift_seconds(1.0).unwrap();
+        let converted = transformed.to_cytus1_chart_with_pageshift(false).unwrap();
+        assert_eq!(converted.bpm(), c1_transformed.bpm());
+        assert_eq!(converted.page_size(), c1_transformed.page_size());
+        for index in [0, 100, 500] {
+            let (note, c1_note) = (&converted.notes()[index], &c1_transformed.notes()[index]);
+            assert!((note.time() - c1_note.time()).abs() < 1e-9);
+            assert!(
+                (get_position(&converted, index) - get_position(&c1_transformed, index)).abs()
+                    < 1e-9
+            );
+            assert!(
+                (get_position(&cytus1_chart, index) - get_position(&c1_transformed, index)).abs()
+                    < 1e-9
+            );
+        }
+        assert!(c1_transformed.shift_seconds(-1000.0).is_err());
+        let start_offset_time = *transformed.start_offset_time();
+        assert!(matches!(
+            transformed.shift_seconds(-1000.0),
+            Err(CylToolError::NoteBeforeStart { .. })
+        ));
+        assert_eq!(*transformed.start_offset_time(), start_offset_time);
+        assert!(transformed.change_rate(0.0).is_err());
+
+        // 大幅平移时 page_shift 一步折回 [0, 2 * page_size)
+        let mut c1_transformed = cytus1_chart.clone();
+        c1_transformed.shift_seconds(1e9).unwrap();
+        let page_shift = *c1_transformed.page_shift();
+        assert!((0.0..2.0 * cytus1_chart.page_size()).contains(&page_shift));
+        for index in [0, 100, 500] {
+            assert!(
+                (get_position(&cytus1_chart, index) - get_position(&c1_transformed, index)).abs()
+                    < 1e-6
+            );
+        }
+        for page_size in [0.0, -1.0, f64::NAN] {
+            let mut c1_transformed = cytus1_chart.clone();
+            c1_transformed.set_page_size(page_size);
+            assert!(c1_transformed.shift_seconds(1.0).is_err());
+            assert_eq!(
+                c1_transformed.notes()[0].time(),
+                cytus1_chart.notes()[0].time()
+            );
+        }
+    }
+    /// Tick and page index of every note, then tick and value of every tempo.
+    fn get_ticks(chart: &CylheimChart) -> Vec<(u32, u32)> {
+        let notes = chart
+            .note_list()
+            .iter()
+            .map(|note| (*note.tick(), *note.page_index()));
+        let tempos = chart
+            .tempo_list()
+            .iter()
+            .map(|tempo| (*tempo.tick(), *tempo.value()));
+        notes.chain(tempos).collect()
+    }
+    #[test]
+    fn test_shift_ticks() {
+        let f = fs::read_to_string(TEST_RESOURCE_ROOT.to_owned() + "test_valid_c2to1_chart.json")
+            .unwrap();
+        let chart: CylheimChart = serde_json::from_str(&f).unwrap();
+        let tempo_map = chart.get_tempo_map().unwrap();
+        let mut shifted = chart.clone();
+        shifted.shift_ticks(960).unwrap();
+        let shifted_map = shifted.get_tempo_map().unwrap();
+        for (note, shifted_note) in chart.note_list().iter().zip(shifted.note_list()) {
+            assert_eq!(*shifted_note.tick(), note.tick() + 960);
+            assert_eq!(*shifted_note.page_index(), note.page_index() + 1);
+            assert_eq!(
+                shifted_map.get_tick_time(*shifted_note.tick()),
+                tempo_map.get_tick_time(*note.tick()) + tempo_map.get_tick_time(960)
+            );
+        }
+        assert!(!shifted.lint().has_errors());
+        shifted.shift_ticks(-960).unwrap();
+        assert_eq!(get_ticks(&shifted), get_ticks(&chart));
+
+        let last_end = *chart.page_list().last().unwrap().end_tick() as i64;
+        let mut shifted = chart.clone();
+        assert!(shifted.shift_ticks(last_end).is_err());
+        assert_eq!(get_ticks(&shifted), get_ticks(&chart));
+    }
+}
@@ -1,7 +1,7 @@
 use super::{
     cyl_chart::{
-        CylheimChart, CylheimChartNote, CylheimChartPage, CylheimChartPagePositionFunction,
-        CylheimChartTempo,
+        CylheimChart, CylheimChartNote, CylheimChartNoteType, CylheimChartPage,
+        CylheimChartPagePositionFunction, CylheimChartTempo,
     },
     utils::CylToolError,
 };
@@ -40,7 +40,7 @@ pub(crate) struct CylheimChartPageViewerNotes {
     page_index: u32,
     #[serde(rename = "type")]
     #[getset(get = "pub", set = "pub")]
-    note_type: u32,
+    note_type: CylheimChartNoteType,
     #[getset(get = "pub", set = "pub")]
     id: u32,
     #[getset(get = "pub", set = "pub")]
@@ -97,82 +97,273 @@ pub(crate) struct CylheimChartPageViewerNotePostion {
     y: f64,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
-#[allow(unused)]
-pub enum CylheimChartPageViewerNoteType {
-    Click,
-    Hold,
-    LongHold,
-    Drag,
-    DragChild,
-    Flick,
-    ClickDrag,
-    ClickDragChild,
-    DropClick,
-    DropDrag,
-    CustomNoteType(i32),
+/// Vertical position of `tick` on a page, normalized so that 0 is the bottom and 1 the top.
+///
+/// Ticks outside the page extrapolate beyond [0, 1], which is what drag lines to notes on
+/// other pages need.
+pub(crate) fn get_page_y(page: &CylheimChartPage, tick: u32) -> f64 {
+    let progress = (tick as f64 - *page.start_tick() as f64) / page.get_page_size() as f64;
+    if *page.scan_line_direction() >= 0 {
+        progress
+    } else {
+        1.0 - progress
+    }
 }
-impl CylheimChartPageViewerNoteType {
-    #[allow(unused)]
-
-    pub fn get_id(&self) -> i32 {
-        match self {
-            CylheimChartPageViewerNoteType::Click => 0,
-            CylheimChartPageViewerNoteType::Hold => 1,
-            CylheimChartPageViewerNoteType::LongHold => 2,
-            CylheimChartPageViewerNoteType::Drag => 3,
-            CylheimChartPageViewerNoteType::DragChild => 4,
-            CylheimChartPageViewerNoteType::Flick => 5,
-            CylheimChartPageViewerNoteType::ClickDrag => 6,
-            CylheimChartPageViewerNoteType::ClickDragChild => 7,
-            CylheimChartPageViewerNoteType::DropClick => 8,
-            CylheimChartPageViewerNoteType::DropDrag => 9,
-            CylheimChartPageViewerNoteType::CustomNoteType(r#type) => *r#type,
-        }
+fn get_note_position(
+    page: &CylheimChartPage,
+    note: &CylheimChartNote,
+) -> CylheimChartPageViewerNotePostion {
+    CylheimChartPageViewerNotePostion {
+        x: *note.x(),
+        y: get_page_y(page, *note.tick()),
     }
-    #[allow(unused)]
-    pub fn from_id(id: i32) -> Option<Self> {
-        match id {
-            0 => Some(CylheimChartPageViewerNoteType::Click),
-            1 => Some(CylheimChartPageViewerNoteType::Hold),
-            2 => Some(CylheimChartPageViewerNoteType::LongHold),
-            3 => Some(CylheimChartPageViewerNoteType::Drag),
-            4 => Some(CylheimChartPageViewerNoteType::DragChild),
-            5 => Some(CylheimChartPageViewerNoteType::Flick),
-            6 => Some(CylheimChartPageViewerNoteType::ClickDrag),
-            7 => Some(CylheimChartPageViewerNoteType::ClickDragChild),
-            8 => Some(CylheimChartPageViewerNoteType::DropClick),
-            9 => Some(CylheimChartPageViewerNoteType::DropDrag),
-            id => Some(CylheimChartPageViewerNoteType::CustomNoteType(id)),
-            _ => None, // 无效ID返回 None
+}
+fn build_viewer_note(
+    chart: &CylheimChart,
+    page_index: u32,
+    note: &CylheimChartNote,
+    parent_map: &HashMap<u32, &CylheimChartNote>,
+    note_map: &HashMap<u32, &CylheimChartNote>,
+) -> CylheimChartPageViewerNotes {
+    let page = &chart.page_list()[page_index as usize];
+    let mut position = get_note_position(page, note);
+    let long_hold_handler = match note.note_type() {
+        CylheimChartNoteType::LongHold => {
+            let head_tick = *note.tick();
+            let tail_tick = head_tick + note.hold_tick();
+            let start_tick = head_tick.max(*page.start_tick());
+            let end_tick = tail_tick.min(*page.end_tick());
+            let with_head = head_tick >= *page.start_tick() && head_tick < *page.end_tick();
+            let (start_y, end_y) = (get_page_y(page, start_tick), get_page_y(page, end_tick));
+            if !with_head {
+                position.y = start_y;
+            }
+            Some(CylheimChartPageViewerLongHoldHandler {
+                with_head,
+                start_y: start_y.min(end_y),
+                end_y: start_y.max(end_y),
+            })
         }
+        _ => None,
+    };
+    let (drag_parent_pos, drag_child_pos) = if note.note_type().is_drag_family() {
+        let parent = parent_map.get(note.id());
+        let child = match *note.next_id() {
+            next_id if next_id >= 0 => note_map.get(&(next_id as u32)),
+            _ => None,
+        };
+        (
+            parent.map(|parent| get_note_position(page, parent)),
+            child.map(|child| get_note_position(page, child)),
+        )
+    } else {
+        (None, None)
+    };
+    CylheimChartPageViewerNotes {
+        page_index,
+        note_type: *note.note_type(),
+        id: *note.id(),
+        tick: *note.tick(),
+        position,
+        has_sibling: *note.has_sibling(),
+        long_hold_handler,
+        drag_parent_pos,
+        drag_child_pos,
+        hold_tick: *note.hold_tick(),
+        next_id: *note.next_id(),
+        is_forward: *note.is_forward(),
+        falling_note_direction: *note.falling_note_direction(),
+        approach_rate: *note.approach_rate(),
     }
 }
-
-pub(crate) fn build_cylheim_page_viewer(
-    chart: CylheimChart,
+/// Build the per-page geometry of `chart`, keyed by page index.
+///
+/// Every page lists its own notes, a clipped handler for each long hold passing through it,
+/// and the notes of the following page as ghost notes.
+pub fn build_cylheim_page_viewer(
+    chart: &CylheimChart,
 ) -> Result<HashMap<u32, CylheimChartPageViewer>, CylToolError> {
-    todo!()
+    let page_list = chart.page_list();
+    for (page_index, page) in page_list.iter().enumerate() {
+        if page.get_page_size() <= 0 {
+            return Err(CylToolError::PageSizeMismatch {
+                page_index: page_index as u32,
+                page_size: page.get_page_size(),
+                expected_size: page.get_page_size().max(1),
+            });
+        }
+    }
+    let note_map: HashMap<u32, &CylheimChartNote> = chart
+        .note_list()
+        .iter()
+        .map(|note| (*note.id(), note))
+        .collect();
+    let parent_map: HashMap<u32, &CylheimChartNote> = chart
+        .note_list()
+        .iter()
+        .filter(|note| note.note_type().is_drag_family() && *note.next_id() >= 0)
+        .filter_map(|note| {
+            let child = note_map.get(&(*note.next_id() as u32))?;
+            child
+                .note_type()
+                .is_drag_family()
+                .then_some((*child.id(), note))
+        })
+        .collect();
+
+    let mut page_notes: Vec<Vec<CylheimChartPageViewerNotes>> = vec![Vec::new(); page_list.len()];
+    for note in chart.note_list() {
+        let page_index = *note.page_index();
+        if page_index as usize >= page_list.len() {
+            return Err(CylToolError::InvalidPageIndex {
+                note_id: *note.id(),
+                tick: *note.tick(),
+                page_index,
+            });
+        }
+        page_notes[page_index as usize].push(build_viewer_note(
+            chart,
+            page_index,
+            note,
+            &parent_map,
+            &note_map,
+        ));
+        if *note.note_type() == CylheimChartNoteType::LongHold {
+            // 长条的尾部会延伸到后续页面
+            let tail_tick = note.tick() + note.hold_tick();
+            for (body_index, body_page) in
+                page_list.iter().enumerate().skip(page_index as usize + 1)
+            {
+                if *body_page.start_tick() >= tail_tick {
+                    break;
+                }
+                page_notes[body_index].push(build_viewer_note(
+                    chart,
+                    body_index as u32,
+                    note,
+                    &parent_map,
+                    &note_map,
+                ));
+            }
+        }
+    }
+    for notes in &mut page_notes {
+        notes.sort_by_key(|note| (note.tick, note.id));
+    }
+
+    let mut viewer_map: HashMap<u32, CylheimChartPageViewer> = HashMap::new();
+    for (page_index, page) in page_list.iter().enumerate() {
+        let ghost_page = page_list.get(page_index + 1);
+        let mut tempo_list: Vec<CylheimChartTempo> = Vec::new();
+        for tempo in chart.tempo_list() {
+            if *tempo.tick() <= *page.start_tick() {
+                tempo_list.clear();
+                tempo_list.push(tempo.clone());
+            } else if *tempo.tick() < *page.end_tick() {
+                tempo_list.push(tempo.clone());
+            }
+        }
+        viewer_map.insert(
+            page_index as u32,
+            CylheimChartPageViewer {
+                time_base: *chart.time_base(),
+                page_index: page_index as u32,
+                start_tick: *page.start_tick(),
+                end_tick: *page.end_tick(),
+                scan_line_direction: *page.scan_line_direction(),
+                ghost_scan_line_direction: ghost_page
+                    .map_or(-*page.scan_line_direction(), |ghost| {
+                        *ghost.scan_line_direction()
+                    }),
+                tempo_list,
+                note_list: page_notes[page_index].clone(),
+                ghost_note_list: page_notes
+                    .get(page_index + 1)
+                    .map(|notes| {
+                        notes
+                            .iter()
+                            .filter(|note| {
+                                note.long_hold_handler
+                                    .as_ref()
+                                    .is_none_or(|handler| handler.with_head)
+                            })
+                            .cloned()
+                            .collect()
+                    })
+                    .unwrap_or_default(),
+                position_function: page.position_function().clone(),
+            },
+        );
+    }
+    Ok(viewer_map)
 }
 #[cfg(test)]
-#[allow(unused)]
 mod test {
     use super::*;
     use std::fs;
     const TEST_RESOURCE_ROOT: &str = "./tests/resources/";
-    const TEST_OUTPUT_ROOT: &str = "./tests/output/";
     fn get_resource_path(filename: &str) -> String {
         TEST_RESOURCE_ROOT.to_owned() + filename
     }
-    fn get_output_path(filename: &str) -> String {
-        TEST_OUTPUT_ROOT.to_owned() + filename
+    #[test]
+    fn test_build_cylheim_page_viewer() {
+        let path = get_resource_path("cylchart_with_cytus1_flag.json");
+        let f = fs::read_to_string(path).unwrap();
+        let chart: CylheimChart = serde_json::from_str(&f).unwrap();
+        let viewer_map = build_cylheim_page_viewer(&chart).unwrap();
+        assert_eq!(viewer_map.len(), chart.page_list().len());
+        let note_count: usize = viewer_map.values().map(|page| page.note_list.len()).sum();
+        assert_eq!(note_count, chart.note_list().len());
+        for page in viewer_map.values() {
+            for note in &page.note_list {
+                assert!((0.0..=1.0).contains(note.position.y()));
+            }
+            if let Some(next_page) = viewer_map.get(&(page.page_index + 1)) {
+                assert_eq!(page.ghost_note_list.len(), next_page.note_list.len());
+            }
+        }
+        // 第 4 页向上扫描，第 5 页向下扫描
+        let page = &viewer_map[&4];
+        let note = &page.note_list[0];
+        assert_eq!(note.id, 0);
+        assert_eq!(*note.position.y(), 60.0 / 960.0);
+        let page = &viewer_map[&5];
+        assert_eq!(*page.note_list[0].position.y(), 1.0 - 60.0 / 960.0);
+        // 拖拽链的子节点记录父节点位置
+        let child = viewer_map
+            .values()
+            .flat_map(|page| page.note_list.iter())
+            .find(|note| note.note_type == CylheimChartNoteType::DragChild)
+            .unwrap();
+        assert!(child.drag_parent_pos.is_some());
     }
     #[test]
-    #[allow(unused)]
-    fn test() {
-        panic!();
-        // let a = CylheimChartPageViewer {
-        //     todo!()
-        // };
+    fn test_build_cylheim_page_viewer_long_hold() {
+        let mut chart = CylheimChart::default();
+        chart.set_page_list(vec![
+            CylheimChartPage::new(0, 960, 1),
+            CylheimChartPage::new(960, 1920, -1),
+            CylheimChartPage::new(1920, 2880, 1),
+        ]);
+        chart.set_tempo_list(vec![CylheimChartTempo::new(0, 500000)]);
+        let mut note = CylheimChartNote::new(0, CylheimChartNoteType::LongHold, 0, 480, 0.5);
+        note.set_hold_tick(1920);
+        chart.set_note_list(vec![note]);
+        let viewer_map = build_cylheim_page_viewer(&chart).unwrap();
+        let handlers: Vec<CylheimChartPageViewerLongHoldHandler> = (0..3)
+            .map(|page_index| {
+                viewer_map[&page_index].note_list[0]
+                    .long_hold_handler
+                    .clone()
+                    .unwrap()
+            })
+            .collect();
+        assert!(handlers[0].with_head);
+        assert_eq!((handlers[0].start_y, handlers[0].end_y), (0.5, 1.0));
+        assert!(!handlers[1].with_head);
+        assert_eq!((handlers[1].start_y, handlers[1].end_y), (0.0, 1.0));
+        assert_eq!((handlers[2].start_y, handlers[2].end_y), (0.0, 0.5));
+        assert!(handlers.iter().all(|handler| handler.check_start_end()));
+        assert_eq!(viewer_map[&0].ghost_note_list.len(), 0);
     }
 }
@@ -1,6 +1,9 @@
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
 
 use super::{
+    chart_directive::{CylheimChartDirectives, DirectiveWarning, ExportTarget},
+    chart_viewer::get_page_y,
     cytus1_chart::{compare_links, Cytus1Chart, Cytus1ChartLink, Cytus1ChartNote},
     utils::CylToolError,
 };
@@ -46,8 +49,22 @@ pub(crate) struct CylheimChartPage {
     position_function: Option<CylheimChartPagePositionFunction>,
 }
 impl CylheimChartPage {
+    pub fn new(start_tick: u32, end_tick: u32, scan_line_direction: i32) -> Self {
+        Self {
+            start_tick,
+            end_tick,
+            scan_line_direction,
+            position_function: None,
+        }
+    }
     pub fn get_page_size(&self) -> i32 {
-        self.end_tick as i32 - self.start_tick as i32
+        (self.end_tick as i64 - self.start_tick as i64).clamp(i32::MIN as i64, i32::MAX as i64)
+            as i32
+    }
+    /// Whether `tick` lies on the page. Both ends count, so a page boundary belongs to the
+    /// pages on either side of it.
+    pub fn contains_tick(&self, tick: u32) -> bool {
+        self.start_tick <= tick && tick <= self.end_tick
     }
 }
 #[derive(Serialize, Deserialize, Debug, Getters, Setters, Clone)]
@@ -68,14 +85,24 @@ pub(crate) struct CylheimChartTempo {
     #[getset(get = "pub", set = "pub")]
     value: u32,
 }
-#[derive(Serialize, Deserialize, Debug, Getters, Setters, Clone)]
+impl CylheimChartTempo {
+    pub fn new(tick: u32, value: u32) -> Self {
+        Self { tick, value }
+    }
+}
+#[derive(Serialize, Deserialize, Debug, Getters, Setters, Clone, MutGetters)]
 #[allow(unused)]
 pub(crate) struct CylheimChartTickEventList {
     #[getset(get = "pub", set = "pub")]
     tick: u32,
-    #[getset(get = "pub", set = "pub")]
+    #[getset(get = "pub", set = "pub", get_mut = "pub")]
     event_list: Vec<CylheimChartEvent>,
 }
+impl CylheimChartTickEventList {
+    pub fn new(tick: u32, event_list: Vec<CylheimChartEvent>) -> Self {
+        Self { tick, event_list }
+    }
+}
 #[derive(Serialize, Deserialize, Debug, Getters, Setters, Clone)]
 #[allow(unused)]
 pub(crate) struct CylheimChartEvent {
@@ -86,6 +113,14 @@ pub(crate) struct CylheimChartEvent {
     #[getset(get = "pub", set = "pub")]
     event_args: String,
 }
+impl CylheimChartEvent {
+    pub fn new(event_type: u32, event_args: &str) -> Self {
+        Self {
+            event_type,
+            event_args: event_args.to_string(),
+        }
+    }
+}
 #[derive(Serialize, Deserialize, Debug, Getters, Setters, Clone)]
 #[allow(unused)]
 pub(crate) struct CylheimChartNote {
@@ -93,7 +128,7 @@ pub(crate) struct CylheimChartNote {
     page_index: u32,
     #[serde(rename = "type")]
     #[getset(get = "pub", set = "pub")]
-    note_type: u32,
+    note_type: CylheimChartNoteType,
     #[getset(get = "pub", set = "pub")]
     id: u32,
     #[getset(get = "pub", set = "pub")]
@@ -116,109 +151,861 @@ pub(crate) struct CylheimChartNote {
     #[serde(skip_serializing_if = "Option::is_none")]
     approach_rate: Option<f64>,
 }
+impl CylheimChartNote {
+    pub fn new(
+        page_index: u32,
+        note_type: CylheimChartNoteType,
+        id: u32,
+        tick: u32,
+        x: f64,
+    ) -> Self {
+        Self {
+            page_index,
+            note_type,
+            id,
+            tick,
+            x,
+            has_sibling: false,
+            hold_tick: 0,
+            next_id: 0,
+            is_forward: false,
+            falling_note_direction: None,
+            approach_rate: None,
+        }
+    }
+}
+/// Note type of a Cylheim chart, stored as its numeric id.
+///
+/// Ids without a known type are kept as `Custom` so they survive a round trip.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[serde(from = "u32", into = "u32")]
+pub enum CylheimChartNoteType {
+    Click,
+    Hold,
+    LongHold,
+    Drag,
+    DragChild,
+    Flick,
+    ClickDrag,
+    ClickDragChild,
+    DropClick,
+    DropDrag,
+    Custom(u32),
+}
+impl CylheimChartNoteType {
+    pub fn get_id(&self) -> u32 {
+        match self {
+            CylheimChartNoteType::Click => 0,
+            CylheimChartNoteType::Hold => 1,
+            CylheimChartNoteType::LongHold => 2,
+            CylheimChartNoteType::Drag => 3,
+            CylheimChartNoteType::DragChild => 4,
+            CylheimChartNoteType::Flick => 5,
+            CylheimChartNoteType::ClickDrag => 6,
+            CylheimChartNoteType::ClickDragChild => 7,
+            CylheimChartNoteType::DropClick => 8,
+            CylheimChartNoteType::DropDrag => 9,
+            CylheimChartNoteType::Custom(id) => *id,
+        }
+    }
+    pub fn from_id(id: u32) -> Self {
+        match id {
+            0 => CylheimChartNoteType::Click,
+            1 => CylheimChartNoteType::Hold,
+            2 => CylheimChartNoteType::LongHold,
+            3 => CylheimChartNoteType::Drag,
+            4 => CylheimChartNoteType::DragChild,
+            5 => CylheimChartNoteType::Flick,
+            6 => CylheimChartNoteType::ClickDrag,
+            7 => CylheimChartNoteType::ClickDragChild,
+            8 => CylheimChartNoteType::DropClick,
+            9 => CylheimChartNoteType::DropDrag,
+            id => CylheimChartNoteType::Custom(id),
+        }
+    }
+    /// Notes linked into drag chains through `next_id`.
+    pub fn is_drag_family(&self) -> bool {
+        self.is_chain_head() || self.is_chain_child()
+    }
+    /// First note of a drag chain.
+    pub fn is_chain_head(&self) -> bool {
+        matches!(
+            self,
+            CylheimChartNoteType::Drag | CylheimChartNoteType::ClickDrag
+        )
+    }
+    /// Note following another one in a drag chain.
+    pub fn is_chain_child(&self) -> bool {
+        matches!(
+            self,
+            CylheimChartNoteType::DragChild | CylheimChartNoteType::ClickDragChild
+        )
+    }
+    /// Notes held for `hold_tick` ticks.
+    pub fn has_duration(&self) -> bool {
+        matches!(
+            self,
+            CylheimChartNoteType::Hold | CylheimChartNoteType::LongHold
+        )
+    }
+}
+impl From<u32> for CylheimChartNoteType {
+    fn from(id: u32) -> Self {
+        CylheimChartNoteType::from_id(id)
+    }
+}
+impl From<CylheimChartNoteType> for u32 {
+    fn from(note_type: CylheimChartNoteType) -> Self {
+        note_type.get_id()
+    }
+}
+impl fmt::Display for CylheimChartNoteType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.get_id())
+    }
+}
 #[allow(dead_code)]
 enum InsertNoteOption {}
+impl Default for CylheimChart {
+    fn default() -> Self {
+        Self {
+            format_version: 1,
+            time_base: 480,
+            start_offset_time: 0.0,
+            end_offset_time: None,
+            is_start_without_ui: None,
+            page_list: Vec::new(),
+            tempo_list: Vec::new(),
+            event_order_list: Vec::new(),
+            note_list: Vec::new(),
+        }
+    }
+}
+/// Reference tempo used when a chart with tempo changes is exported to Cytus 1.
+///
+/// Cytus 1 only has one BPM, so notes are placed exactly on the reference segment and
+/// drift elsewhere.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub enum TempoStrategy {
+    /// The first non-zero tempo of the chart.
+    First,
+    /// The tempo segment lasting longest in real time.
+    Longest,
+    /// The tempo segment holding the most notes.
+    #[default]
+    MostNotes,
+    /// The tempo segment active at the given tick.
+    AtTick(u32),
+}
+/// A note shown at a different place in the exported Cytus 1 chart than in Cylheim.
+#[derive(Serialize, Deserialize, Debug, Clone, Getters)]
+pub struct NotePositionDrift {
+    #[getset(get = "pub")]
+    note_id: u32,
+    #[getset(get = "pub")]
+    tick: u32,
+    #[getset(get = "pub")]
+    page_index: u32,
+    #[getset(get = "pub")]
+    cytus1_page_index: i64,
+    #[getset(get = "pub")]
+    cylheim_y: f64,
+    #[getset(get = "pub")]
+    cytus1_y: f64,
+}
+impl NotePositionDrift {
+    /// Visual y drift within the page, whichever page the note is shown on.
+    pub fn get_drift(&self) -> f64 {
+        self.cytus1_y - self.cylheim_y
+    }
+    /// Pages the note moved by, positive when it is shown later in Cytus 1.
+    pub fn get_page_offset(&self) -> i64 {
+        self.cytus1_page_index - self.page_index as i64
+    }
+}
+/// How pages that differ from the first page's size are exported to Cytus 1.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub enum PageSizeStrategy {
+    /// Refuse charts whose pages are not all the same size.
+    #[default]
+    Strict,
+    /// Keep note timing exact and let notes move to wherever Cytus 1's fixed page puts them.
+    Reflow,
+}
+/// Where the page shift of the exported Cytus 1 chart comes from.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub enum PageShiftSource {
+    /// The `PAGE_SHIFT` directive when present, otherwise computed from the reference tempo.
+    #[default]
+    Auto,
+    /// Computed from the reference tempo, ignoring any directive.
+    Computed,
+    /// The given page shift in seconds.
+    Fixed(f64),
+}
+/// Everything the Cytus 1 export can be told.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct ConversionOptions {
+    /// Require the `#DEFINE CYTUS1` directive in the chart.
+    pub use_flag: bool,
+    pub page_shift_source: PageShiftSource,
+    pub tempo_strategy: TempoStrategy,
+    pub page_size_strategy: PageSizeStrategy,
+    /// None follows the chart's `UNSUPPORTED_NOTE` directive and refuses without one.
+    pub unsupported_note_strategy: Option<UnsupportedNoteStrategy>,
+    pub note_mapping: Cytus1NoteMapping,
+    /// Clamp note x into `[0, 1]` instead of refusing the chart.
+    pub clamp_x: bool,
+    /// Number the exported notes from 0 in (time, x) order instead of keeping their ids.
+    pub renumber_ids: bool,
+}
+/// What the Cytus 1 export does with notes Cytus 1 cannot show.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum UnsupportedNoteStrategy {
+    /// Refuse the chart.
+    #[default]
+    Error,
+    /// Leave the notes out of the exported chart.
+    Drop,
+}
+/// How LongHold notes are exported to Cytus 1.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum LongHoldMapping {
+    /// Handled by the `UnsupportedNoteStrategy`.
+    #[default]
+    Unsupported,
+    /// A hold, cut to one Cytus 1 page when longer.
+    Hold,
+    Click,
+}
+/// How Flick notes are exported to Cytus 1.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum FlickMapping {
+    /// Handled by the `UnsupportedNoteStrategy`.
+    #[default]
+    Unsupported,
+    Click,
+}
+/// How ClickDrag chains are exported to Cytus 1.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ClickDragMapping {
+    /// Handled by the `UnsupportedNoteStrategy`.
+    #[default]
+    Unsupported,
+    /// The whole chain becomes a link, like a Drag chain.
+    Link,
+    /// The head becomes a click and its children a link.
+    ClickAndLink,
+}
+/// How DropClick and DropDrag notes are exported to Cytus 1.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DropNoteMapping {
+    /// Handled by the `UnsupportedNoteStrategy`.
+    #[default]
+    Unsupported,
+    Click,
+}
+macro_rules! impl_mapping_from_str {
+    ($mapping:ident { $($name:literal => $variant:ident),+ $(,)? }) => {
+        impl std::str::FromStr for $mapping {
+            type Err = String;
+            fn from_str(s: &str) -> Result<Self, Self::Err> {
+                match s {
+                    "unsupported" => Ok($mapping::Unsupported),
+                    $($name => Ok($mapping::$variant),)+
+                    _ => Err(format!("unknown mapping '{}'", s)),
+                }
+            }
+        }
+    };
+}
+impl_mapping_from_str!(LongHoldMapping { "hold" => Hold, "click" => Click });
+impl_mapping_from_str!(FlickMapping { "click" => Click });
+impl_mapping_from_str!(ClickDragMapping { "link" => Link, "click-and-link" => ClickAndLink });
+impl_mapping_from_str!(DropNoteMapping { "click" => Click });
+/// Substitutes for the note types Cytus 1 does not have. Unmapped types follow the
+/// `UnsupportedNoteStrategy`.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct Cytus1NoteMapping {
+    pub long_hold: LongHoldMapping,
+    pub flick: FlickMapping,
+    pub click_drag: ClickDragMapping,
+    pub drop_note: DropNoteMapping,
+}
+impl Cytus1NoteMapping {
+    /// A substitute for every note type that has one.
+    pub fn substitute_all() -> Self {
+        Self {
+            long_hold: LongHoldMapping::Hold,
+            flick: FlickMapping::Click,
+            click_drag: ClickDragMapping::Link,
+            drop_note: DropNoteMapping::Click,
+        }
+    }
+    /// What a note of `note_type` becomes in Cytus 1, or None when it has no substitute.
+    fn get_kind(&self, note_type: CylheimChartNoteType) -> Option<Cytus1NoteKind> {
+        let substitute = match note_type {
+            CylheimChartNoteType::Click
+            | CylheimChartNoteType::Hold
+            | CylheimChartNoteType::Drag
+            | CylheimChartNoteType::DragChild => return Some(Cytus1NoteKind::Native),
+            CylheimChartNoteType::LongHold => match self.long_hold {
+                LongHoldMapping::Unsupported => None,
+                LongHoldMapping::Hold => Some(NoteSubstitute::Hold { capped: false }),
+                LongHoldMapping::Click => Some(NoteSubstitute::Click),
+            },
+            CylheimChartNoteType::Flick => match self.flick {
+                FlickMapping::Unsupported => None,
+                FlickMapping::Click => Some(NoteSubstitute::Click),
+            },
+            CylheimChartNoteType::ClickDrag => match self.click_drag {
+                ClickDragMapping::Unsupported => None,
+                ClickDragMapping::Link => Some(NoteSubstitute::LinkNode),
+                ClickDragMapping::ClickAndLink => Some(NoteSubstitute::Click),
+            },
+            CylheimChartNoteType::ClickDragChild => match self.click_drag {
+                ClickDragMapping::Unsupported => None,
+                _ => Some(NoteSubstitute::LinkNode),
+            },
+            CylheimChartNoteType::DropClick | CylheimChartNoteType::DropDrag => {
+                match self.drop_note {
+                    DropNoteMapping::Unsupported => None,
+                    DropNoteMapping::Click => Some(NoteSubstitute::Click),
+                }
+            }
+            CylheimChartNoteType::Custom(_) => None,
+        };
+        substitute.map(Cytus1NoteKind::Substitute)
+    }
+}
+enum Cytus1NoteKind {
+    Native,
+    Substitute(NoteSubstitute),
+}
+/// What a note became in Cytus 1.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(tag = "substitute", rename_all = "snake_case")]
+pub enum NoteSubstitute {
+    Click,
+    /// `capped` is set when the hold was cut to one page.
+    Hold {
+        capped: bool,
+    },
+    /// A node of a link.
+    LinkNode,
+}
+/// A note exported to Cytus 1 as another kind of note.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Getters)]
+pub struct NoteSubstitution {
+    #[getset(get = "pub")]
+    note_id: u32,
+    #[getset(get = "pub")]
+    tick: u32,
+    #[getset(get = "pub")]
+    note_type: CylheimChartNoteType,
+    #[serde(flatten)]
+    #[getset(get = "pub")]
+    substitute: NoteSubstitute,
+}
+impl fmt::Display for NoteSubstitution {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let substitute = match self.substitute {
+            NoteSubstitute::Click => "a click",
+            NoteSubstitute::Hold { capped: false } => "a hold",
+            NoteSubstitute::Hold { capped: true } => "a hold cut to one page",
+            NoteSubstitute::LinkNode => "a link node",
+        };
+        write!(
+            f,
+            "Note {} of type {} is exported as {}.",
+            self.note_id, self.note_type, substitute
+        )
+    }
+}
+/// A note left out of the exported chart.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Getters)]
+pub struct DroppedNote {
+    #[getset(get = "pub")]
+    note_id: u32,
+    #[getset(get = "pub")]
+    tick: u32,
+    #[getset(get = "pub")]
+    note_type: CylheimChartNoteType,
+}
+impl fmt::Display for DroppedNote {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Note {} of type {} at tick {} is dropped.",
+            self.note_id, self.note_type, self.tick
+        )
+    }
+}
+/// A note whose x was moved into `[0, 1]`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Getters)]
+pub struct ClampedNote {
+    #[getset(get = "pub")]
+    note_id: u32,
+    #[getset(get = "pub")]
+    tick: u32,
+    #[getset(get = "pub")]
+    x: f64,
+    #[getset(get = "pub")]
+    clamped_x: f64,
+}
+impl fmt::Display for ClampedNote {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Note {} at tick {} is moved from x {} to {}.",
+            self.note_id, self.tick, self.x, self.clamped_x
+        )
+    }
+}
+/// A note exported under another id.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Getters)]
+pub struct RenumberedNote {
+    #[getset(get = "pub")]
+    note_id: u32,
+    #[getset(get = "pub")]
+    tick: u32,
+    #[getset(get = "pub")]
+    cytus1_id: u32,
+}
+impl fmt::Display for RenumberedNote {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Note {} at tick {} is exported as note {}.",
+            self.note_id, self.tick, self.cytus1_id
+        )
+    }
+}
+/// Everything the Cytus 1 export dropped, approximated or remapped. Notes are given by
+/// their Cylheim id.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, Getters)]
+pub struct ConversionReport {
+    #[getset(get = "pub")]
+    dropped_notes: Vec<DroppedNote>,
+    #[getset(get = "pub")]
+    substitutions: Vec<NoteSubstitution>,
+    #[getset(get = "pub")]
+    clamped_notes: Vec<ClampedNote>,
+    #[getset(get = "pub")]
+    position_drifts: Vec<NotePositionDrift>,
+    #[getset(get = "pub")]
+    renumbered_notes: Vec<RenumberedNote>,
+    #[getset(get = "pub")]
+    directive_warnings: Vec<DirectiveWarning>,
+}
+impl ConversionReport {
+    /// True when every note is shown in Cytus 1 as it is in Cylheim.
+    pub fn is_lossless(&self) -> bool {
+        self.dropped_notes.is_empty()
+            && self.substitutions.is_empty()
+            && self.clamped_notes.is_empty()
+            && self.position_drifts.is_empty()
+    }
+    /// One line per entry, ending with the number of position drifts.
+    pub fn get_messages(&self) -> Vec<String> {
+        let mut messages: Vec<String> = self
+            .directive_warnings
+            .iter()
+            .map(|warning| warning.to_string())
+            .collect();
+        messages.extend(self.dropped_notes.iter().map(|note| note.to_string()));
+        messages.extend(self.substitutions.iter().map(|note| note.to_string()));
+        messages.extend(self.clamped_notes.iter().map(|note| note.to_string()));
+        messages.extend(self.renumbered_notes.iter().map(|note| note.to_string()));
+        messages.push(format!(
+            "{} notes are displayed at a different position.",
+            self.position_drifts.len()
+        ));
+        messages
+    }
+}
+/// Position drifts of the notes in one Cylheim page.
+#[derive(Serialize, Deserialize, Debug, Clone, Getters)]
+pub struct PagePositionDrift {
+    #[getset(get = "pub")]
+    page_index: u32,
+    #[getset(get = "pub")]
+    note_drifts: Vec<NotePositionDrift>,
+}
+impl PagePositionDrift {
+    /// Largest visual y drift of the page, page offsets aside.
+    pub fn get_max_drift(&self) -> f64 {
+        self.note_drifts
+            .iter()
+            .map(|drift| drift.get_drift().abs())
+            .fold(0.0, f64::max)
+    }
+    /// Page offset of largest size among the notes of the page, 0 when every note stays.
+    pub fn get_max_page_offset(&self) -> i64 {
+        self.note_drifts
+            .iter()
+            .map(NotePositionDrift::get_page_offset)
+            .max_by_key(|page_offset| page_offset.abs())
+            .unwrap_or(0)
+    }
+}
+/// Group note drifts by the Cylheim page they belong to, in page order.
+pub fn group_position_drifts_by_page(drifts: &[NotePositionDrift]) -> Vec<PagePositionDrift> {
+    let mut page_map: BTreeMap<u32, Vec<NotePositionDrift>> = BTreeMap::new();
+    for drift in drifts {
+        page_map
+            .entry(drift.page_index)
+            .or_default()
+            .push(drift.clone());
+    }
+    page_map
+        .into_iter()
+        .map(|(page_index, note_drifts)| PagePositionDrift {
+            page_index,
+            note_drifts,
+        })
+        .collect()
+}
+const POSITION_TOLERANCE: f64 = 1e-6;
 impl CylheimChart {
-    #[allow(dead_code)]
+    fn check_cytus1_tempo(&self) -> Result<(), CylToolError> {
+        let first_tempo = self.tempo_list.first().ok_or(CylToolError::MissingTempo)?;
+        if first_tempo.tick != 0 {
+            return Err(CylToolError::TempoMismatch {
+                tick: first_tempo.tick,
+                value: first_tempo.value,
+            });
+        }
+        for (index, tempo) in self.tempo_list.iter().enumerate() {
+            let sorted = index == 0 || tempo.tick > self.tempo_list[index - 1].tick;
+            // 只允许第 0 个 tempo 为 0，用作 page shift 的前导
+            if !sorted || (tempo.value == 0 && index != 0) {
+                return Err(CylToolError::TempoMismatch {
+                    tick: tempo.tick,
+                    value: tempo.value,
+                });
+            }
+        }
+        if first_tempo.value == 0 {
+            let valid_lead_in = match (self.tempo_list.get(1), self.page_list.first()) {
+                (Some(true_tempo), Some(first_page)) => {
+                    (true_tempo.tick as i64) < 2 * first_page.get_page_size() as i64
+                        && first_page.scan_line_direction == 1
+                }
+                _ => false,
+            };
+            if !valid_lead_in {
+                return Err(CylToolError::TempoMismatch {
+                    tick: first_tempo.tick,
+                    value: first_tempo.value,
+                });
+            }
+        }
+        Ok(())
+    }
+    /// Index in `tempo_list` of the segment `strategy` picks as the Cytus 1 tempo.
+    fn get_reference_tempo_index(&self, strategy: &TempoStrategy) -> Result<usize, CylToolError> {
+        let tempo_map = self.get_tempo_map()?;
+        let last_tick = self
+            .note_list
+            .iter()
+            .map(|note| note.tick + note.hold_tick)
+            .chain(self.page_list.last().map(|page| page.end_tick))
+            .max()
+            .unwrap_or(0);
+        let segments: Vec<(usize, u32, u32)> = self
+            .tempo_list
+            .iter()
+            .enumerate()
+            .filter(|(_, tempo)| tempo.value > 0)
+            .map(|(index, tempo)| {
+                let end_tick = self
+                    .tempo_list
+                    .get(index + 1)
+                    .map_or(last_tick.max(tempo.tick), |next| next.tick);
+                (index, tempo.tick, end_tick)
+            })
+            .collect();
+        let longest_by = |key: &dyn Fn(u32, u32) -> f64| {
+            segments
+                .iter()
+                .map(|(index, start_tick, end_tick)| (*index, key(*start_tick, *end_tick)))
+                .fold(None, |best: Option<(usize, f64)>, current| match best {
+                    Some(best) if best.1 >= current.1 => Some(best),
+                    _ => Some(current),
+                })
+                .map(|(index, _)| index)
+        };
+        let index = match strategy {
+            TempoStrategy::First => None,
+            TempoStrategy::Longest => longest_by(&|start_tick, end_tick| {
+                tempo_map.get_duration(start_tick, end_tick).to_f64()
+            }),
+            TempoStrategy::MostNotes => longest_by(&|start_tick, end_tick| {
+                self.note_list
+                    .iter()
+                    .filter(|note| note.tick >= start_tick && note.tick < end_tick)
+                    .count() as f64
+            }),
+            TempoStrategy::AtTick(tick) => segments
+                .iter()
+                .rev()
+                .find(|(_, start_tick, _)| start_tick <= tick)
+                .map(|(index, _, _)| *index),
+        };
+        Ok(index.unwrap_or_else(|| segments.first().map_or(0, |(index, _, _)| *index)))
+    }
     fn to_cytus1_chart_directly(
         &self,
-        page_shift: Option<f64>,
-    ) -> Result<Cytus1Chart, CylToolError> {
-        let check_note_type = |note_type: u32| match note_type {
-            0 | 1 | 3 | 4 => true,
-            _ => false,
-        };
-        let get_tick_time_second = |tempo: u32, time_base: u32, tick: u32| {
-            tick as f64 / time_base as f64 * tempo as f64 / 1000.0 / 1000.0
+        options: &ConversionOptions,
+        directives: &CylheimChartDirectives,
+    ) -> Result<(Cytus1Chart, ConversionReport), CylToolError> {
+        let unsupported_note_strategy = options
+            .unsupported_note_strategy
+            .or(directives.unsupported_note_strategy)
+            .unwrap_or_default();
+        let mut report = ConversionReport {
+            directive_warnings: directives.warnings.clone(),
+            ..Default::default()
         };
+        let mut dropped_note: HashSet<u32> = HashSet::new();
+        let mut substitutes: HashMap<u32, NoteSubstitute> = HashMap::new();
+        let mut clamped_x: HashMap<u32, f64> = HashMap::new();
         for note in &self.note_list {
-            if !check_note_type(note.note_type) {
-                return Err(CylToolError::from(format!(
-                    "Note {} have an invalid type {}.",
-                    note.id, note.note_type
-                )));
+            match options.note_mapping.get_kind(note.note_type) {
+                Some(Cytus1NoteKind::Native) => {}
+                Some(Cytus1NoteKind::Substitute(substitute)) => {
+                    substitutes.insert(note.id, substitute);
+                }
+                None if unsupported_note_strategy == UnsupportedNoteStrategy::Drop => {
+                    dropped_note.insert(note.id);
+                    report.dropped_notes.push(DroppedNote {
+                        note_id: note.id,
+                        tick: note.tick,
+                        note_type: note.note_type,
+                    });
+                    continue;
+                }
+                None => {
+                    return Err(CylToolError::InvalidNoteType {
+                        note_id: note.id,
+                        tick: note.tick,
+                        note_type: note.note_type,
+                    })
+                }
+            }
+            if note.x.is_nan() || (!options.clamp_x && (note.x < 0.0 || note.x > 1.0)) {
+                return Err(CylToolError::NoteXOutOfRange {
+                    note_id: note.id,
+                    tick: note.tick,
+                    x: note.x,
+                });
             }
             if note.x < 0.0 || note.x > 1.0 {
-                return Err(CylToolError::from(format!(
-                    "Note {} have an invalid x {}.",
-                    note.id, note.x
-                )));
+                let x = note.x.clamp(0.0, 1.0);
+                clamped_x.insert(note.id, x);
+                report.clamped_notes.push(ClampedNote {
+                    note_id: note.id,
+                    tick: note.tick,
+                    x: note.x,
+                    clamped_x: x,
+                });
+            }
+            if note.page_index as usize >= self.page_list.len() {
+                return Err(CylToolError::InvalidPageIndex {
+                    note_id: note.id,
+                    tick: note.tick,
+                    page_index: note.page_index,
+                });
             }
         }
         let time_base = self.time_base;
-        let base_page_size = self.page_list.get(0).unwrap().get_page_size();
-        let base_tempo = self.tempo_list.get(0).unwrap();
+        let first_page_size = self
+            .page_list
+            .first()
+            .ok_or(CylToolError::MissingPage)?
+            .get_page_size();
         for (page_index, page) in self.page_list.iter().enumerate() {
-            if page.get_page_size() != base_page_size {
-                return Err(CylToolError::from(format!(
-                    "Page {} have an invalid pagesize {}.",
-                    page_index,
-                    page.get_page_size()
-                )));
-            }
-        }
-        for tempo in &self.tempo_list {
-            if tempo.value != base_tempo.value {
-                return Err(CylToolError::from(format!(
-                    "Tick {} have an invalid tempo {}.",
-                    tempo.tick, tempo.value
-                )));
+            let uniform = page.get_page_size() == first_page_size;
+            if page.get_page_size() <= 0
+                || (!uniform && options.page_size_strategy == PageSizeStrategy::Strict)
+            {
+                return Err(CylToolError::PageSizeMismatch {
+                    page_index: page_index as u32,
+                    page_size: page.get_page_size(),
+                    expected_size: first_page_size,
+                });
             }
         }
+        // 页长检查之后再检查 tempo，前导 tempo 的判断依赖第一页的页长
+        self.check_cytus1_tempo()?;
+        let tempo_map = self.get_tempo_map()?;
+        let base_tempo =
+            &self.tempo_list[self.get_reference_tempo_index(&options.tempo_strategy)?];
+        // 参考 tempo 段起点所在的页决定 Cytus 1 的页长
+        let base_page_index = self
+            .page_list
+            .iter()
+            .rposition(|page| page.start_tick <= base_tempo.tick)
+            .unwrap_or(0);
+        let base_page = &self.page_list[base_page_index];
+        let base_page_size = base_page.get_page_size();
         let mut target_chart = Cytus1Chart::default();
         let note_map: HashMap<u32, CylheimChartNote> = self
             .note_list
-            .clone()
-            .into_iter()
-            .map(|note| (note.id, note))
-            .collect();
-        let _page_map: HashMap<u32, CylheimChartPage> = self
-            .page_list
-            .clone()
-            .into_iter()
-            .enumerate()
-            .map(|(page_index, page)| (page_index as u32, page))
+            .iter()
+            .filter(|note| !dropped_note.contains(&note.id))
+            .map(|note| {
+                let mut note = note.clone();
+                if let Some(x) = clamped_x.get(&note.id) {
+                    note.x = *x;
+                }
+                (note.id, note)
+            })
             .collect();
-        let c1_bpm = 2.0 * 60_000_000.0 / base_tempo.value as f64;
-        let c1_page_size = 240.0 / c1_bpm;
-        let c1_page_shift = page_shift.unwrap_or(0.0);
+        let c1_page_size =
+            base_page_size as f64 / time_base as f64 * base_tempo.value as f64 / 1000.0 / 1000.0;
+        let c1_bpm = 240.0 / c1_page_size;
+        // 以参考 tempo 段对齐页面：该段内的音符在两种谱面中位置完全一致
+        // Cytus 1 的偶数页向上扫描，必要时错开一页以匹配扫描方向
+        let mut page_offset: i64 =
+            match (base_page_index % 2 == 0, base_page.scan_line_direction >= 0) {
+                (true, true) | (false, false) => 0,
+                _ => 1,
+            };
+        let mut c1_page_shift = ((base_page_index as i64 + page_offset) as f64
+            + (base_tempo.tick as i64 - base_page.start_tick as i64) as f64
+                / base_page_size as f64)
+            * c1_page_size
+            - tempo_map.get_tick_time_second(base_tempo.tick);
+        while c1_page_shift < 0.0 {
+            c1_page_shift += 2.0 * c1_page_size;
+            page_offset += 2;
+        }
+        let target_page_shift = match options.page_shift_source {
+            PageShiftSource::Auto => directives.page_shift,
+            PageShiftSource::Computed => None,
+            PageShiftSource::Fixed(page_shift) => Some(page_shift),
+        };
+        if let Some(target_page_shift) = target_page_shift {
+            // 指定的 page shift 使整体偏移若干页，按偏移量修正页号以便计算位置偏差
+            page_offset += ((target_page_shift - c1_page_shift) / c1_page_size).round() as i64;
+            c1_page_shift = target_page_shift;
+        }
+        let get_hold_length = |note: &CylheimChartNote| {
+            tempo_map
+                .get_duration(note.tick, note.tick + note.hold_tick)
+                .to_f64()
+        };
+        // 音符时间包含 start_offset_time，与 page shift 的计算一致
         let mut c1_note_vec: Vec<Cytus1ChartNote> = note_map
             .iter()
             .map(|(note_id, note)| {
                 Cytus1ChartNote::new(
                     *note_id,
-                    get_tick_time_second(base_tempo.value, time_base, note.tick),
+                    tempo_map.get_tick_time_second(note.tick),
                     note.x,
-                    match note.note_type {
-                        1 => get_tick_time_second(base_tempo.value, time_base, note.hold_tick),
+                    match (note.note_type, substitutes.get(note_id)) {
+                        (CylheimChartNoteType::Hold, _) => get_hold_length(note),
+                        (_, Some(NoteSubstitute::Hold { .. })) => {
+                            get_hold_length(note).min(c1_page_size)
+                        }
                         _ => 0.0,
                     },
                 )
             })
             .collect();
+        let mut position_drifts: Vec<NotePositionDrift> = Vec::new();
+        for c1_note in &c1_note_vec {
+            let note = &note_map[c1_note.id()];
+            let mut position = (c1_note.time() + c1_page_shift) / c1_page_size;
+            if (position - position.round()).abs() < 1e-9 {
+                position = position.round();
+            }
+            let c1_page = position.floor();
+            let c1_progress = position - c1_page;
+            let cytus1_y = if c1_page as i64 % 2 == 0 {
+                c1_progress
+            } else {
+                1.0 - c1_progress
+            };
+            let page = &self.page_list[note.page_index as usize];
+            let cylheim_y = get_page_y(page, note.tick);
+            let mut cytus1_page_index = c1_page as i64 - page_offset;
+            // 位于页尾的音符在 Cytus 1 中落在下一页页首，显示位置相同
+            if c1_progress == 0.0
+                && (cytus1_page_index == note.page_index as i64 + 1 || note.tick == page.end_tick)
+            {
+                cytus1_page_index -= 1;
+            }
+            if cytus1_page_index != note.page_index as i64
+                || (cytus1_y - cylheim_y).abs() > POSITION_TOLERANCE
+            {
+                position_drifts.push(NotePositionDrift {
+                    note_id: note.id,
+                    tick: note.tick,
+                    page_index: note.page_index,
+                    cytus1_page_index,
+                    cylheim_y,
+                    cytus1_y,
+                });
+            }
+        }
+        position_drifts.sort_by_key(|drift| (drift.tick, drift.note_id));
         let mut c1_link_vec: Vec<Cytus1ChartLink> = Vec::new();
         let mut visited_note: HashSet<u32> = HashSet::new();
         for (note_id, note) in &note_map {
             if visited_note.contains(note_id) {
                 continue;
             }
-            match note.note_type {
-                3 => {}
+            let mut current_id = match (note.note_type, options.note_mapping.click_drag) {
+                (CylheimChartNoteType::Drag, _)
+                | (CylheimChartNoteType::ClickDrag, ClickDragMapping::Link) => note.id as i32,
+                // 头部作为 click 导出，链从第一个子节点开始
+                (CylheimChartNoteType::ClickDrag, ClickDragMapping::ClickAndLink) => note.next_id,
                 _ => continue,
-            }
-            let mut current_id = note.id as i32;
+            };
             let mut link = Cytus1ChartLink::default();
-            while current_id != -1 {
-                link.link_mut().push(current_id as u32);
-                visited_note.insert(current_id as u32);
-                if let Some(next_note) = note_map.get(&(current_id as u32)) {
-                    current_id = next_note.next_id;
-                } else {
-                    break;
-                }
+            let mut previous_id = note.id;
+            // 被丢弃的音符截断拖拽链
+            while current_id != -1 && !dropped_note.contains(&(current_id as u32)) {
+                let next_note = note_map
+                    .get(&(current_id as u32))
+                    .filter(|_| current_id >= 0 && !link.link().contains(&(current_id as u32)))
+                    .ok_or(CylToolError::BrokenDragChain {
+                        note_id: previous_id,
+                        next_id: current_id,
+                    })?;
+                link.link_mut().push(next_note.id);
+                visited_note.insert(next_note.id);
+                previous_id = next_note.id;
+                current_id = next_note.next_id;
+            }
+            if !link.link().is_empty() {
+                c1_link_vec.push(link);
             }
-            c1_link_vec.push(link);
         }
+        let mut substitutions: Vec<NoteSubstitution> = c1_note_vec
+            .iter()
+            .filter_map(|c1_note| {
+                let note = &note_map[c1_note.id()];
+                let substitute = match substitutes.get(c1_note.id())? {
+                    NoteSubstitute::Hold { .. } => NoteSubstitute::Hold {
+                        capped: *c1_note.hold_length() < get_hold_length(note),
+                    },
+                    substitute => *substitute,
+                };
+                Some(NoteSubstitution {
+                    note_id: note.id,
+                    tick: note.tick,
+                    note_type: note.note_type,
+                    substitute,
+                })
+            })
+            .collect();
+        substitutions.sort_by_key(|substitution| (substitution.tick, substitution.note_id));
+        report.substitutions = substitutions;
+        report.position_drifts = position_drifts;
         c1_note_vec.sort_by_key(|x| *x.id());
         c1_link_vec.sort_by(compare_links);
         target_chart.set_bpm(c1_bpm);
@@ -226,82 +1013,52 @@ impl CylheimChart {
         target_chart.set_page_size(c1_page_size);
         target_chart.set_notes(c1_note_vec);
         target_chart.set_links(c1_link_vec);
-        Ok(target_chart)
-    }
-    #[allow(dead_code)]
-    pub fn to_cytus1_chart_with_pageshift(
-        &self,
-        use_flag: bool,
-    ) -> Result<Cytus1Chart, CylToolError> {
-        let get_tick_time_second = |tempo: u32, time_base: u32, tick: u32| {
-            tick as f64 / time_base as f64 * tempo as f64 / 1000.0 / 1000.0
-        };
-        let mut current_chart = self.clone();
-        let mut cytus1_flag_check = !use_flag;
-        if use_flag {
-            if let Some(cytus1_flag) = current_chart.event_order_list.get(0) {
-                if current_chart.event_order_list.len() == 1
-                    && cytus1_flag.tick == 0
-                    && cytus1_flag.event_list.len() == 1
-                {
-                    if let Some(cytus1_flag_event) = cytus1_flag.event_list.get(0) {
-                        if cytus1_flag_event.event_args.contains("#DEFINE CYTUS1") {
-                            cytus1_flag_check = true;
-                        }
-                    }
+        if options.renumber_ids {
+            for (note_id, cytus1_id) in target_chart.normalize_note_order()? {
+                if note_id != cytus1_id {
+                    report.renumbered_notes.push(RenumberedNote {
+                        note_id,
+                        tick: note_map[&note_id].tick,
+                        cytus1_id,
+                    });
                 }
             }
         }
-        if !cytus1_flag_check {
-            return Err(CylToolError::from(format!("Cannot find cytus1_flag.")));
+        Ok((target_chart, report))
+    }
+    /// Convert to a Cytus 1 chart, reporting everything the conversion dropped, approximated
+    /// or remapped.
+    ///
+    /// A zero tempo at tick 0 is read as the page-shift lead-in. The chart's `PAGE_SHIFT` and
+    /// `UNSUPPORTED_NOTE` directives apply unless `options` overrides them, and a chart whose
+    /// `EXPORT` directive names another format is refused.
+    ///
+    /// Note times are song positions with `start_offset_time` included; the computed
+    /// `PAGE_SHIFT` moves with the offset, so notes stay where they were on screen.
+    pub fn to_cytus1_chart_with_options(
+        &self,
+        options: &ConversionOptions,
+    ) -> Result<(Cytus1Chart, ConversionReport), CylToolError> {
+        let directives = self.get_directive_options();
+        if options.use_flag && !directives.cytus1 {
+            return Err(CylToolError::MissingCytus1Flag);
         }
-        let mut cytus1_tempo_check = false;
-        match current_chart.tempo_list.len() {
-            2 => {
-                if let Some(zero_tempo) = current_chart.tempo_list.get(0) {
-                    if let Some(true_tempo) = current_chart.tempo_list.get(1) {
-                        if let Some(first_page) = current_chart.page_list.get(0) {
-                            if zero_tempo.tick == 0
-                                && zero_tempo.value == 0
-                                && true_tempo.tick - zero_tempo.tick > 0
-                                && true_tempo.tick - zero_tempo.tick
-                                    < 2 * first_page.get_page_size() as u32
-                                && first_page.scan_line_direction == 1
-                            {
-                                cytus1_tempo_check = true;
-                            }
-                        }
-                    }
-                }
+        if let Some(export_target) = directives.export_target {
+            if export_target != ExportTarget::Cytus1 {
+                return Err(CylToolError::ExportTargetMismatch { export_target });
             }
-            1 => {
-                if let Some(true_tempo) = current_chart.tempo_list.get(0) {
-                    cytus1_tempo_check = true_tempo.tick == 0 && true_tempo.value > 0
-                }
-            }
-            _ => (),
-        };
-        if !cytus1_tempo_check {
-            return Err(CylToolError::from(format!("Invalid tempo.")));
-        }
-        let time_base = current_chart.time_base;
-        let true_tempo = current_chart
-            .tempo_list
-            .get(current_chart.tempo_list.len() - 1)
-            .unwrap()
-            .clone();
-        let page_shift = get_tick_time_second(true_tempo.value, time_base, true_tempo.tick);
-        current_chart.event_order_list_mut().clear();
-        current_chart.tempo_list_mut().clear();
-        current_chart.tempo_list_mut().push(CylheimChartTempo {
-            tick: 0,
-            value: true_tempo.value,
-        });
-        for note in current_chart.note_list_mut() {
-            note.set_tick(note.tick - true_tempo.tick);
         }
-        let target = current_chart.to_cytus1_chart_directly(Some(page_shift));
-        target
+        self.to_cytus1_chart_directly(options, &directives)
+    }
+    pub fn to_cytus1_chart_with_pageshift(
+        &self,
+        use_flag: bool,
+    ) -> Result<Cytus1Chart, CylToolError> {
+        self.to_cytus1_chart_with_options(&ConversionOptions {
+            use_flag,
+            ..Default::default()
+        })
+        .map(|(chart, _)| chart)
     }
 }
 #[cfg(test)]
@@ -314,6 +1071,7 @@ mod test {
         TEST_RESOURCE_ROOT.to_owned() + filename
     }
     fn get_output_path(filename: &str) -> String {
+        fs::create_dir_all(TEST_OUTPUT_ROOT).unwrap();
         TEST_OUTPUT_ROOT.to_owned() + filename
     }
     #[test]
@@ -326,7 +1084,15 @@ mod test {
         let f2 = fs::read_to_string(path2).unwrap();
         let chart1: CylheimChart = serde_json::from_str(&f1).unwrap();
         let chart2: CylheimChart = serde_json::from_str(&f2).unwrap();
-        let chart_test1 = chart1.to_cytus1_chart_directly(None).unwrap();
+        let (chart_test1, _) = chart1
+            .to_cytus1_chart_directly(
+                &ConversionOptions {
+                    page_size_strategy: PageSizeStrategy::Strict,
+                    ..Default::default()
+                },
+                &CylheimChartDirectives::default(),
+            )
+            .unwrap();
         // let chart_test2 = chart2.to_cytus1_chart(None).unwrap();
         println!("{}", chart_test1);
         fs::write(path3, chart_test1.to_string()).unwrap();
@@ -340,5 +1106,418 @@ mod test {
         let cytus1chart = cylchart.to_cytus1_chart_with_pageshift(true).unwrap();
         println!("{}", cytus1chart);
         fs::write(path_out, cytus1chart.to_string()).unwrap();
+        assert_eq!(*cytus1chart.page_shift(), 60.0 / 480.0 * 0.375);
+        assert_eq!(*cytus1chart.page_size(), 0.75);
+    }
+    #[test]
+    fn test_cytus2to1_variable_tempo() {
+        let path = get_resource_path("test_valid_c2to1_chart.json");
+        let f = fs::read_to_string(path).unwrap();
+        let mut cylchart: CylheimChart = serde_json::from_str(&f).unwrap();
+        let (_, report) = cylchart
+            .to_cytus1_chart_with_options(&ConversionOptions {
+                page_size_strategy: PageSizeStrategy::Strict,
+                ..Default::default()
+            })
+            .unwrap();
+        assert!(report.position_drifts().is_empty());
+        // 从第 64 页开始速度翻倍
+        cylchart
+            .tempo_list_mut()
+            .push(CylheimChartTempo::new(61440, 187500));
+        let (cytus1chart, report) = cylchart
+            .to_cytus1_chart_with_options(&ConversionOptions {
+                tempo_strategy: TempoStrategy::First,
+                page_size_strategy: PageSizeStrategy::Strict,
+                ..Default::default()
+            })
+            .unwrap();
+        let note = cylchart
+            .note_list()
+            .iter()
+            .find(|note| note.tick > 61440)
+            .unwrap();
+        let c1_note = &cytus1chart.notes()[*note.id() as usize];
+        let expected = 61440.0 / 480.0 * 0.375 + (note.tick() - 61440) as f64 / 480.0 * 0.1875;
+        assert!((c1_note.time() - expected).abs() < 1e-9);
+        assert!(report
+            .position_drifts()
+            .iter()
+            .all(|drift| *drift.tick() > 61440));
+        assert!(!report.position_drifts().is_empty());
+        let (cytus1chart, report) = cylchart
+            .to_cytus1_chart_with_options(&ConversionOptions {
+                tempo_strategy: TempoStrategy::AtTick(61440),
+                page_size_strategy: PageSizeStrategy::Strict,
+                ..Default::default()
+            })
+            .unwrap();
+        assert_eq!(*cytus1chart.page_size(), 0.375);
+        assert!(report
+            .position_drifts()
+            .iter()
+            .all(|drift| *drift.tick() < 61440));
+    }
+    #[test]
+    fn test_cytus2to1_start_offset() {
+        let path = get_resource_path("test_valid_c2to1_chart.json");
+        let f = fs::read_to_string(path).unwrap();
+        let mut cylchart: CylheimChart = serde_json::from_str(&f).unwrap();
+        let cytus1chart = cylchart.to_cytus1_chart_with_pageshift(false).unwrap();
+        cylchart.set_start_offset_time(0.75);
+        let (shifted, report) = cylchart
+            .to_cytus1_chart_with_options(&ConversionOptions::default())
+            .unwrap();
+        assert!(report.position_drifts().is_empty());
+        let page_size = *cytus1chart.page_size();
+        let get_position = |chart: &Cytus1Chart, note: &Cytus1ChartNote| {
+            ((note.time() + chart.page_shift()) / page_size).rem_euclid(2.0)
+        };
+        for (note, shifted_note) in cytus1chart.notes().iter().zip(shifted.notes()) {
+            assert!((shifted_note.time() - note.time() - 0.75).abs() < 1e-9);
+            assert!(
+                (get_position(&shifted, shifted_note) - get_position(&cytus1chart, note)).abs()
+                    < 1e-9
+            );
+        }
+        // 转回 Cylheim 后偏移并入 tick，再次导出得到相同的时间
+        let back = shifted
+            .clone()
+            .into_c2chart(480)
+            .unwrap()
+            .to_cytus1_chart_with_pageshift(true)
+            .unwrap();
+        let tick_second = page_size / 2.0 / 480.0;
+        assert!((back.page_shift() - shifted.page_shift()).abs() <= tick_second);
+        for (note, back_note) in shifted.notes().iter().zip(back.notes()) {
+            assert!((back_note.time() - note.time()).abs() <= tick_second);
+        }
+    }
+    #[test]
+    fn test_cytus2to1_reflow_page_size() {
+        let path = get_resource_path("test_valid_c2to1_chart.json");
+        let f = fs::read_to_string(path).unwrap();
+        let mut cylchart: CylheimChart = serde_json::from_str(&f).unwrap();
+        // 将第 10 页拆成两个半页，后续页面顺延
+        let mut page_list = cylchart.page_list()[..10].to_vec();
+        page_list.push(CylheimChartPage::new(9600, 10080, 1));
+        page_list.push(CylheimChartPage::new(10080, 10560, -1));
+        for page in &cylchart.page_list()[11..] {
+            page_list.push(CylheimChartPage::new(
+                *page.start_tick(),
+                *page.end_tick(),
+                -*page.scan_line_direction(),
+            ));
+        }
+        cylchart.set_page_list(page_list);
+        for note in cylchart.note_list_mut() {
+            if *note.tick() >= 10080 {
+                note.set_page_index(note.page_index() + 1);
+            }
+        }
+        let note_id = cylchart.note_list().len() as u32;
+        cylchart.note_list_mut().push(CylheimChartNote::new(
+            10,
+            CylheimChartNoteType::Click,
+            note_id,
+            9840,
+            0.5,
+        ));
+        assert!(matches!(
+            cylchart.to_cytus1_chart_with_options(&ConversionOptions {
+                tempo_strategy: TempoStrategy::First,
+                page_size_strategy: PageSizeStrategy::Strict,
+                ..Default::default()
+            }),
+            Err(CylToolError::PageSizeMismatch {
+                page_index: 10,
+                page_size: 480,
+                expected_size: 960,
+            })
+        ));
+        let (cytus1chart, report) = cylchart
+            .to_cytus1_chart_with_options(&ConversionOptions {
+                tempo_strategy: TempoStrategy::First,
+                page_size_strategy: PageSizeStrategy::Reflow,
+                ..Default::default()
+            })
+            .unwrap();
+        assert_eq!(*cytus1chart.page_size(), 0.75);
+        assert_eq!(cytus1chart.notes().len(), cylchart.note_list().len());
+        let page_drifts = group_position_drifts_by_page(report.position_drifts());
+        assert!(page_drifts.iter().all(|page| *page.page_index() >= 10));
+        // 半页之后的页面在 Cytus 1 中提前一页显示，扫描方向相反，y 因此翻转
+        for page in page_drifts.iter().filter(|page| *page.page_index() > 11) {
+            assert_eq!(page.get_max_page_offset(), -1);
+            let mut max_drift: f64 = 0.0;
+            for drift in page.note_drifts() {
+                assert_eq!(drift.get_page_offset(), -1);
+                assert!((drift.cytus1_y() + drift.cylheim_y() - 1.0).abs() < 1e-9);
+                max_drift = max_drift.max((1.0 - 2.0 * drift.cylheim_y()).abs());
+            }
+            assert!((page.get_max_drift() - max_drift).abs() < 1e-9);
+        }
+        // 半页中点的音符在 Cytus 1 整页的四分之一处
+        let half_page = &page_drifts[0];
+        assert_eq!(*half_page.page_index(), 10);
+        assert_eq!(half_page.get_max_page_offset(), 0);
+        assert!((half_page.note_drifts()[0].cylheim_y() - 0.5).abs() < 1e-9);
+        assert!((half_page.get_max_drift() - 0.25).abs() < 1e-9);
+    }
+    #[test]
+    fn test_cytus2to1_error_kind() {
+        let path = get_resource_path("test_valid_c2to1_chart.json");
+        let f = fs::read_to_string(path).unwrap();
+        let cylchart: CylheimChart = serde_json::from_str(&f).unwrap();
+        let convert = |chart: &CylheimChart| chart.to_cytus1_chart_with_pageshift(false);
+
+        let mut chart = cylchart.clone();
+        chart.note_list_mut()[0].set_note_type(CylheimChartNoteType::Flick);
+        assert!(matches!(
+            convert(&chart),
+            Err(CylToolError::InvalidNoteType {
+                note_id: 0,
+                tick: 3840,
+                note_type: CylheimChartNoteType::Flick,
+            })
+        ));
+        let mut chart = cylchart.clone();
+        chart.note_list_mut()[0].set_x(1.5);
+        assert!(matches!(
+            convert(&chart),
+            Err(CylToolError::NoteXOutOfRange { note_id: 0, .. })
+        ));
+        let mut chart = cylchart.clone();
+        let head = chart
+            .note_list_mut()
+            .iter_mut()
+            .find(|note| *note.id() == 18)
+            .unwrap();
+        head.set_next_id(100000);
+        assert!(matches!(
+            convert(&chart),
+            Err(CylToolError::BrokenDragChain {
+                note_id: 18,
+                next_id: 100000,
+            })
+        ));
+        assert!(matches!(
+            cylchart.to_cytus1_chart_with_pageshift(true),
+            Err(CylToolError::MissingCytus1Flag)
+        ));
+        // 倒置的第一页在检查前导 tempo 之前就被拒绝
+        let mut chart = cylchart.clone();
+        let mut page_list = chart.page_list().clone();
+        page_list[0] = CylheimChartPage::new(960, 0, 1);
+        chart.set_page_list(page_list);
+        chart.set_tempo_list(vec![
+            CylheimChartTempo::new(0, 0),
+            CylheimChartTempo::new(480, 375000),
+        ]);
+        assert!(matches!(
+            convert(&chart),
+            Err(CylToolError::PageSizeMismatch {
+                page_index: 0,
+                page_size: -960,
+                ..
+            })
+        ));
+        let mut page_list = chart.page_list().clone();
+        page_list[0] = CylheimChartPage::new(0, u32::MAX, 1);
+        chart.set_page_list(page_list);
+        assert!(convert(&chart).is_err());
+    }
+    #[test]
+    fn test_note_type_round_trip() {
+        let json = r#"{"page_index":0,"type":42,"id":1,"tick":0,"x":0.5,"has_sibling":false,"hold_tick":0,"next_id":-1,"is_forward":false}"#;
+        let note: CylheimChartNote = serde_json::from_str(json).unwrap();
+        assert_eq!(*note.note_type(), CylheimChartNoteType::Custom(42));
+        assert!(!note.note_type().is_drag_family());
+        assert_eq!(serde_json::to_string(&note).unwrap(), json);
+        for id in 0..10 {
+            let note_type = CylheimChartNoteType::from_id(id);
+            assert_eq!(note_type.get_id(), id);
+            assert!(!matches!(note_type, CylheimChartNoteType::Custom(_)));
+        }
+        assert!(CylheimChartNoteType::ClickDrag.is_chain_head());
+        assert!(CylheimChartNoteType::LongHold.has_duration());
+    }
+    #[test]
+    fn test_cytus2to1_directives() {
+        use crate::cylheim_tools::CylheimChartEventKind;
+        let path = get_resource_path("cylchart_with_cytus1_flag.json");
+        let f = fs::read_to_string(path).unwrap();
+        let mut cylchart: CylheimChart = serde_json::from_str(&f).unwrap();
+        // 指令事件可以与普通事件共存
+        cylchart.add_event(960, CylheimChartEventKind::new_text("hello"));
+        cylchart.add_event(
+            0,
+            CylheimChartEventKind::new_directive("#DEFINE PAGE_SHIFT 1.546875"),
+        );
+        cylchart.add_event(
+            0,
+            CylheimChartEventKind::new_directive("#DEFINE UNSUPPORTED_NOTE drop"),
+        );
+        cylchart.note_list_mut()[0].set_note_type(CylheimChartNoteType::Flick);
+        let dropped_id = *cylchart.note_list()[0].id();
+        let (cytus1chart, report) = cylchart
+            .to_cytus1_chart_with_options(&ConversionOptions {
+                use_flag: true,
+                page_size_strategy: PageSizeStrategy::Strict,
+                ..Default::default()
+            })
+            .unwrap();
+        assert_eq!(*cytus1chart.page_shift(), 1.546875);
+        assert_eq!(cytus1chart.notes().len(), cylchart.note_list().len() - 1);
+        assert!(cytus1chart
+            .notes()
+            .iter()
+            .all(|note| *note.id() != dropped_id));
+        // 与原本的 page shift 恰好相差两页，音符位置不变
+        assert!(report.position_drifts().is_empty());
+        // 负的 page shift 同样按整页对齐
+        let (cytus1chart, report) = cylchart
+            .to_cytus1_chart_with_options(&ConversionOptions {
+                page_shift_source: PageShiftSource::Fixed(1.546875 - 4.0 * 0.75),
+                page_size_strategy: PageSizeStrategy::Strict,
+                unsupported_note_strategy: Some(UnsupportedNoteStrategy::Drop),
+                ..Default::default()
+            })
+            .unwrap();
+        assert_eq!(*cytus1chart.page_shift(), -1.453125);
+        assert!(report.position_drifts().is_empty());
+        cylchart.add_event(
+            0,
+            CylheimChartEventKind::new_directive("#DEFINE EXPORT cytoid"),
+        );
+        assert!(matches!(
+            cylchart.to_cytus1_chart_with_pageshift(true),
+            Err(CylToolError::ExportTargetMismatch {
+                export_target: ExportTarget::Cytoid
+            })
+        ));
+    }
+    #[test]
+    fn test_cytus2to1_note_mapping() {
+        let path = get_resource_path("test_valid_c2to1_chart.json");
+        let f = fs::read_to_string(path).unwrap();
+        let mut cylchart: CylheimChart = serde_json::from_str(&f).unwrap();
+        let (base_chart, _) = cylchart
+            .to_cytus1_chart_with_options(&ConversionOptions {
+                page_size_strategy: PageSizeStrategy::Strict,
+                ..Default::default()
+            })
+            .unwrap();
+        let head_id = *base_chart.links()[0].link().first().unwrap();
+        let page_ticks = cylchart.page_list()[0].get_page_size() as u32;
+        for note in cylchart.note_list_mut() {
+            match *note.id() {
+                id if id == head_id => {
+                    note.set_note_type(CylheimChartNoteType::ClickDrag);
+                }
+                _ if note.note_type().is_chain_child() => {
+                    note.set_note_type(CylheimChartNoteType::ClickDragChild);
+                }
+                0 => {
+                    note.set_note_type(CylheimChartNoteType::Flick);
+                }
+                1 => {
+                    note.set_note_type(CylheimChartNoteType::LongHold);
+                    note.set_hold_tick(page_ticks * 3);
+                }
+                _ => {}
+            }
+        }
+        let convert = |note_mapping: &Cytus1NoteMapping| {
+            cylchart.to_cytus1_chart_with_options(&ConversionOptions {
+                page_size_strategy: PageSizeStrategy::Strict,
+                note_mapping: note_mapping.clone(),
+                ..Default::default()
+            })
+        };
+        let error = convert(&Cytus1NoteMapping::default()).unwrap_err();
+        assert_eq!(error.kind(), "invalid_note_type");
+
+        let (cytus1chart, report) = convert(&Cytus1NoteMapping::substitute_all()).unwrap();
+        assert_eq!(cytus1chart.notes().len(), base_chart.notes().len());
+        assert_eq!(cytus1chart.links()[0].link(), base_chart.links()[0].link());
+        let long_hold = &cytus1chart.notes()[1];
+        assert_eq!(long_hold.hold_length(), cytus1chart.page_size());
+        let substitution = |note_id: u32| {
+            report
+                .substitutions()
+                .iter()
+                .find(|substitution| *substitution.note_id() == note_id)
+                .map(|substitution| *substitution.substitute())
+        };
+        assert_eq!(substitution(0), Some(NoteSubstitute::Click));
+        assert_eq!(substitution(1), Some(NoteSubstitute::Hold { capped: true }));
+        assert_eq!(substitution(head_id), Some(NoteSubstitute::LinkNode));
+
+        let note_mapping = Cytus1NoteMapping {
+            click_drag: ClickDragMapping::ClickAndLink,
+            ..Cytus1NoteMapping::substitute_all()
+        };
+        let (cytus1chart, report) = convert(&note_mapping).unwrap();
+        assert_eq!(
+            cytus1chart.links()[0].link()[..],
+            base_chart.links()[0].link()[1..]
+        );
+        assert!(report
+            .substitutions()
+            .iter()
+            .any(|substitution| *substitution.note_id() == head_id
+                && *substitution.substitute() == NoteSubstitute::Click));
+    }
+    #[test]
+    fn test_cytus2to1_conversion_options() {
+        let path = get_resource_path("test_valid_c2to1_chart.json");
+        let f = fs::read_to_string(path).unwrap();
+        let mut cylchart: CylheimChart = serde_json::from_str(&f).unwrap();
+        cylchart.note_list_mut()[0].set_x(1.5);
+        cylchart.note_list_mut()[1].set_note_type(CylheimChartNoteType::Flick);
+        let options = ConversionOptions {
+            page_shift_source: PageShiftSource::Fixed(1.5),
+            unsupported_note_strategy: Some(UnsupportedNoteStrategy::Drop),
+            clamp_x: true,
+            renumber_ids: true,
+            ..Default::default()
+        };
+        let (cytus1chart, report) = cylchart.to_cytus1_chart_with_options(&options).unwrap();
+        assert_eq!(*cytus1chart.page_shift(), 1.5);
+        assert!(!report.is_lossless());
+        assert_eq!(report.dropped_notes().len(), 1);
+        assert_eq!(*report.dropped_notes()[0].note_id(), 1);
+        assert_eq!(report.clamped_notes().len(), 1);
+        assert_eq!(*report.clamped_notes()[0].clamped_x(), 1.0);
+        // 编号连续且按时间排列，连线指向新编号
+        let notes = cytus1chart.notes();
+        assert_eq!(notes.len(), cylchart.note_list().len() - 1);
+        assert!(notes
+            .iter()
+            .enumerate()
+            .all(|(i, note)| *note.id() == i as u32));
+        assert!(notes
+            .windows(2)
+            .all(|pair| pair[0].time() <= pair[1].time()));
+        assert!(cytus1chart
+            .links()
+            .iter()
+            .flat_map(|link| link.link())
+            .all(|id| (*id as usize) < notes.len()));
+        let clamped_id = report
+            .renumbered_notes()
+            .iter()
+            .find(|note| *note.note_id() == 0)
+            .map_or(0, |note| *note.cytus1_id());
+        assert_eq!(*notes[clamped_id as usize].x(), 1.0);
+
+        let error = cylchart
+            .to_cytus1_chart_with_options(&ConversionOptions {
+                clamp_x: false,
+                ..options
+            })
+            .unwrap_err();
+        assert_eq!(error.kind(), "note_x_out_of_range");
     }
 }
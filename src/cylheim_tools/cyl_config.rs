@@ -1,6 +1,6 @@
 use super::cytoid_level::CylheimLevelMetaConfig;
 use serde::{Deserialize, Serialize};
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct CylheimProjectConfig {
     #[serde(rename = "Version", default = "default_cyl_version")]
     pub version: i32,
@@ -18,7 +18,7 @@ pub struct CylheimProjectConfig {
 fn default_cyl_version() -> i32 {
     0
 }
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct ChartInfo {
     #[serde(rename = "DiffName")]
     pub diff_name: String,
@@ -50,14 +50,14 @@ pub struct ChartInfo {
     #[serde(rename = "GenerateEventConfig", default)]
     pub generate_event_config: GenerateEventConfig,
 }
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct CylheimBookmark {
     #[serde(rename = "Tick")]
     tick: u32,
     #[serde(rename = "Tag")]
     tag: String,
 }
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct GenerateEventConfig {
     #[serde(rename = "IgnoreFrom")]
     ignore_from: f64,
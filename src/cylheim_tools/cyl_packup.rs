@@ -0,0 +1,321 @@
+use super::{
+    utils::{resolve_cyl_path, CylToolError},
+    ChartInfo, CylheimProjectConfig,
+};
+use getset::Getters;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    fs::{self, File},
+    io::{self, Write},
+    path::{Component, Path, PathBuf},
+};
+use zip::{write::SimpleFileOptions, CompressionMethod, ZipArchive, ZipWriter};
+
+/// Folder inside the archive for assets that lived outside the project folder.
+const EXTERNAL_ASSET_DIR: &str = "assets";
+/// An asset listed in a `ChartInfo` that cannot be found on disk.
+#[derive(Serialize, Deserialize, Debug, Clone, Getters)]
+pub struct MissingAsset {
+    #[getset(get = "pub")]
+    chart_index: usize,
+    /// Name of the `ChartInfo` field, as written in the `.cyl` file.
+    #[getset(get = "pub")]
+    field: String,
+    #[getset(get = "pub")]
+    path: String,
+}
+/// Asset fields of a chart, as (`.cyl` field name, path) pairs. Built-in icons are skipped.
+fn get_chart_assets(chart_info: &mut ChartInfo) -> Vec<(&'static str, &mut String)> {
+    let mut assets = vec![
+        ("FileName", &mut chart_info.chart_path),
+        ("Media", &mut chart_info.song_path),
+        ("Video", &mut chart_info.video_path),
+        ("Bg", &mut chart_info.background_path),
+    ];
+    if !chart_info.icon_path.starts_with("cyl://") {
+        assets.push(("Icon", &mut chart_info.icon_path));
+    }
+    if let Some(storyboard_path) = &mut chart_info.storyboard_path {
+        assets.push(("StoryboardPath", storyboard_path));
+    }
+    assets
+        .into_iter()
+        .filter(|(_, path)| !path.is_empty())
+        .collect()
+}
+/// List every asset of `config` that does not exist relative to `project_dir`.
+pub fn find_missing_assets(config: &CylheimProjectConfig, project_dir: &Path) -> Vec<MissingAsset> {
+    let mut missing_assets = Vec::new();
+    for (chart_index, chart_info) in config.chart_infos.iter().enumerate() {
+        for (field, path) in get_chart_assets(&mut chart_info.clone()) {
+            if !resolve_cyl_path(project_dir, path).is_file() {
+                missing_assets.push(MissingAsset {
+                    chart_index,
+                    field: field.to_string(),
+                    path: path.clone(),
+                });
+            }
+        }
+    }
+    missing_assets
+}
+/// Archive entry name for `path`, or None when it leaves the project folder.
+pub(crate) fn get_entry_name(path: &str) -> Option<String> {
+    let mut components: Vec<String> = Vec::new();
+    for component in Path::new(&path.replace('\\', "/")).components() {
+        match component {
+            // Windows 盘符，例如 `C:`
+            Component::Normal(name) if name.to_string_lossy().ends_with(':') => return None,
+            Component::Normal(name) => components.push(name.to_string_lossy().to_string()),
+            Component::CurDir => {}
+            _ => return None,
+        }
+    }
+    (!components.is_empty()).then(|| components.join("/"))
+}
+/// Bundle the project at `cyl_path` and all assets its charts list into one zip archive.
+///
+/// Assets outside the project folder are stored under `assets/` and their paths in the
+/// packed `.cyl` are rewritten to match. Packing is refused while any asset is missing.
+pub fn pack_cyl_project(cyl_path: &Path, archive_path: &Path) -> Result<(), CylToolError> {
+    let project_dir = cyl_path.parent().unwrap_or(Path::new(""));
+    let f =
+        fs::read_to_string(cyl_path).map_err(|err| CylToolError::from(err).at_path(cyl_path))?;
+    let mut config: CylheimProjectConfig =
+        serde_json::from_str(&f).map_err(|err| CylToolError::from(err).at_path(cyl_path))?;
+    let missing_assets = find_missing_assets(&config, project_dir);
+    if !missing_assets.is_empty() {
+        let mut missing_paths: Vec<PathBuf> = Vec::new();
+        for asset in &missing_assets {
+            let path = PathBuf::from(&asset.path);
+            if !missing_paths.contains(&path) {
+                missing_paths.push(path);
+            }
+        }
+        return Err(CylToolError::MissingAssets(missing_paths));
+    }
+    let cyl_name = cyl_path
+        .file_name()
+        .map_or("project.cyl".into(), |name| name.to_string_lossy());
+    // 同一文件可能被多个谱面引用，只打包一次
+    let mut entries: BTreeMap<String, PathBuf> = BTreeMap::new();
+    entries.insert(cyl_name.to_string(), cyl_path.to_path_buf());
+    // 项目内的文件保持原路径，先占用名称，外部文件再避开这些名称
+    for chart_info in &mut config.chart_infos {
+        for (_, path) in get_chart_assets(chart_info) {
+            let Some(entry_name) = get_entry_name(path) else {
+                continue;
+            };
+            let source = resolve_cyl_path(project_dir, path);
+            match entries.get(&entry_name) {
+                Some(existing) if *existing != source => {
+                    return Err(CylToolError::DuplicateArchiveEntry {
+                        entry_name,
+                        path: existing.clone(),
+                        other_path: source,
+                    })
+                }
+                _ => entries.insert(entry_name, source),
+            };
+        }
+    }
+    for chart_info in &mut config.chart_infos {
+        for (_, path) in get_chart_assets(chart_info) {
+            if get_entry_name(path).is_some() {
+                continue;
+            }
+            let source = resolve_cyl_path(project_dir, path);
+            let file_name = source
+                .file_name()
+                .map_or("asset".into(), |name| name.to_string_lossy());
+            let mut entry_name = format!("{}/{}", EXTERNAL_ASSET_DIR, file_name);
+            let mut index = 1;
+            while entries
+                .get(&entry_name)
+                .is_some_and(|existing| *existing != source)
+            {
+                entry_name = format!("{}/{}_{}", EXTERNAL_ASSET_DIR, index, file_name);
+                index += 1;
+            }
+            *path = entry_name.clone();
+            entries.insert(entry_name, source);
+        }
+    }
+    entries.remove(cyl_name.as_ref());
+    let config_json = serde_json::to_string_pretty(&config)?;
+
+    let options = SimpleFileOptions::default().compression_method(CompressionMethod::Deflated);
+    let archive_error = |err: CylToolError| err.at_path(archive_path);
+    let mut writer =
+        ZipWriter::new(File::create(archive_path).map_err(|err| archive_error(err.into()))?);
+    writer
+        .start_file(cyl_name.as_ref(), options)
+        .map_err(|err| archive_error(err.into()))?;
+    writer
+        .write_all(config_json.as_bytes())
+        .map_err(|err| archive_error(err.into()))?;
+    for (entry_name, source) in &entries {
+        writer
+            .start_file(entry_name.as_str(), options)
+            .map_err(|err| archive_error(err.into()))?;
+        let mut file = File::open(source).map_err(|err| CylToolError::from(err).at_path(source))?;
+        io::copy(&mut file, &mut writer).map_err(|err| archive_error(err.into()))?;
+    }
+    writer.finish().map_err(|err| archive_error(err.into()))?;
+    Ok(())
+}
+/// Extract every entry of the zip archive at `archive_path` into `output_dir`.
+///
+/// Returns the relative paths of the extracted files.
+pub(crate) fn extract_archive(
+    archive_path: &Path,
+    output_dir: &Path,
+) -> Result<Vec<PathBuf>, CylToolError> {
+    let archive_error = |err: CylToolError| err.at_path(archive_path);
+    let file = File::open(archive_path).map_err(|err| archive_error(err.into()))?;
+    let mut archive = ZipArchive::new(file).map_err(|err| archive_error(err.into()))?;
+    let mut entry_paths = Vec::new();
+    for index in 0..archive.len() {
+        let mut entry = archive
+            .by_index(index)
+            .map_err(|err| archive_error(err.into()))?;
+        let entry_path = entry
+            .enclosed_name()
+            .ok_or_else(|| CylToolError::UnsafeArchiveEntry {
+                path: archive_path.to_path_buf(),
+                entry_name: entry.name().to_string(),
+            })?;
+        let target = output_dir.join(&entry_path);
+        if entry.is_dir() {
+            fs::create_dir_all(&target).map_err(|err| CylToolError::from(err).at_path(&target))?;
+            continue;
+        }
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent).map_err(|err| CylToolError::from(err).at_path(parent))?;
+        }
+        let mut file =
+            File::create(&target).map_err(|err| CylToolError::from(err).at_path(&target))?;
+        io::copy(&mut entry, &mut file).map_err(|err| CylToolError::from(err).at_path(&target))?;
+        entry_paths.push(entry_path);
+    }
+    Ok(entry_paths)
+}
+/// Extract an archive made by `pack_cyl_project` into `output_dir`.
+///
+/// Returns the path of the extracted `.cyl` file.
+pub fn unpack_cyl_project(archive_path: &Path, output_dir: &Path) -> Result<PathBuf, CylToolError> {
+    extract_archive(archive_path, output_dir)?
+        .into_iter()
+        .find(|entry_path| {
+            entry_path.parent() == Some(Path::new(""))
+                && entry_path.extension().is_some_and(|ext| ext == "cyl")
+        })
+        .map(|entry_path| output_dir.join(entry_path))
+        .ok_or_else(|| CylToolError::MissingArchiveEntry {
+            path: archive_path.to_path_buf(),
+            entry_name: "*.cyl".to_string(),
+        })
+}
+#[cfg(test)]
+mod test {
+    use super::*;
+    const TEST_RESOURCE_ROOT: &str = "./tests/resources/";
+    const TEST_OUTPUT_ROOT: &str = "./tests/output/";
+    fn build_test_project(project_dir: &Path, external_dir: &Path) -> PathBuf {
+        let _ = fs::remove_dir_all(project_dir);
+        fs::create_dir_all(project_dir.join("song")).unwrap();
+        fs::create_dir_all(project_dir.join("bg")).unwrap();
+        fs::create_dir_all(external_dir).unwrap();
+        let f = fs::read_to_string(TEST_RESOURCE_ROOT.to_owned() + "test_cyl_config.cyl").unwrap();
+        let mut config: CylheimProjectConfig = serde_json::from_str(&f).unwrap();
+        config.chart_infos.truncate(2);
+        let external_video = fs::canonicalize(external_dir).unwrap().join("Video.mp4");
+        fs::write(&external_video, "video").unwrap();
+        for chart_info in &mut config.chart_infos {
+            fs::write(project_dir.join(&chart_info.chart_path), "{}").unwrap();
+            fs::write(resolve_cyl_path(project_dir, &chart_info.song_path), "song").unwrap();
+            fs::write(
+                resolve_cyl_path(project_dir, &chart_info.background_path),
+                "bg",
+            )
+            .unwrap();
+            chart_info.video_path = external_video.to_string_lossy().to_string();
+        }
+        let cyl_path = project_dir.join("project.cyl");
+        fs::write(&cyl_path, serde_json::to_string(&config).unwrap()).unwrap();
+        cyl_path
+    }
+    #[test]
+    fn test_pack_cyl_project() {
+        let root = PathBuf::from(TEST_OUTPUT_ROOT).join("packup");
+        let cyl_path = build_test_project(&root.join("project"), &root.join("external"));
+        let archive_path = root.join("project.zip");
+        pack_cyl_project(&cyl_path, &archive_path).unwrap();
+
+        let unpack_dir = root.join("unpacked");
+        let _ = fs::remove_dir_all(&unpack_dir);
+        let unpacked_cyl = unpack_cyl_project(&archive_path, &unpack_dir).unwrap();
+        assert_eq!(unpacked_cyl, unpack_dir.join("project.cyl"));
+        let f = fs::read_to_string(&unpacked_cyl).unwrap();
+        let config: CylheimProjectConfig = serde_json::from_str(&f).unwrap();
+        assert_eq!(config.chart_infos.len(), 2);
+        assert_eq!(config.chart_infos[0].video_path, "assets/Video.mp4");
+        assert_eq!(
+            config.chart_infos[0].song_path,
+            "song\\Dirge in Magenta.ogg"
+        );
+        assert!(find_missing_assets(&config, &unpack_dir).is_empty());
+        assert_eq!(
+            fs::read_to_string(unpack_dir.join("song/Dirge in Magenta.ogg")).unwrap(),
+            "song"
+        );
+    }
+    #[test]
+    fn test_pack_cyl_project_entry_collision() {
+        let root = PathBuf::from(TEST_OUTPUT_ROOT).join("packup_collision");
+        let project_dir = root.join("project");
+        let cyl_path = build_test_project(&project_dir, &root.join("external"));
+        // 项目内的 assets/Video.mp4 与外部的 Video.mp4 同名
+        fs::create_dir_all(project_dir.join("assets")).unwrap();
+        fs::write(project_dir.join("assets/Video.mp4"), "project video").unwrap();
+        let f = fs::read_to_string(&cyl_path).unwrap();
+        let mut config: CylheimProjectConfig = serde_json::from_str(&f).unwrap();
+        config.chart_infos[1].video_path = "assets\\Video.mp4".to_string();
+        fs::write(&cyl_path, serde_json::to_string(&config).unwrap()).unwrap();
+        let archive_path = root.join("project.zip");
+        pack_cyl_project(&cyl_path, &archive_path).unwrap();
+
+        let unpack_dir = root.join("unpacked");
+        let _ = fs::remove_dir_all(&unpack_dir);
+        let unpacked_cyl = unpack_cyl_project(&archive_path, &unpack_dir).unwrap();
+        let f = fs::read_to_string(&unpacked_cyl).unwrap();
+        let config: CylheimProjectConfig = serde_json::from_str(&f).unwrap();
+        assert_eq!(config.chart_infos[0].video_path, "assets/1_Video.mp4");
+        assert_eq!(config.chart_infos[1].video_path, "assets\\Video.mp4");
+        assert_eq!(
+            fs::read_to_string(unpack_dir.join("assets/Video.mp4")).unwrap(),
+            "project video"
+        );
+        assert_eq!(
+            fs::read_to_string(unpack_dir.join("assets/1_Video.mp4")).unwrap(),
+            "video"
+        );
+    }
+    #[test]
+    fn test_pack_cyl_project_missing_asset() {
+        let root = PathBuf::from(TEST_OUTPUT_ROOT).join("packup_missing");
+        let project_dir = root.join("project");
+        let cyl_path = build_test_project(&project_dir, &root.join("external"));
+        fs::remove_file(project_dir.join("bg/bg_dirge_in_magenta.jpeg")).unwrap();
+        let f = fs::read_to_string(&cyl_path).unwrap();
+        let config: CylheimProjectConfig = serde_json::from_str(&f).unwrap();
+        let missing_assets = find_missing_assets(&config, &project_dir);
+        assert_eq!(missing_assets.len(), 2);
+        assert_eq!(missing_assets[0].field(), "Bg");
+        match pack_cyl_project(&cyl_path, &root.join("project.zip")) {
+            Err(CylToolError::MissingAssets(paths)) => assert_eq!(paths.len(), 1),
+            result => panic!("unexpected result {:?}", result),
+        }
+    }
+}
@@ -1,5 +1,22 @@
+use super::{
+    cyl_config::GenerateEventConfig,
+    cyl_packup::{extract_archive, get_entry_name},
+    utils::{resolve_cyl_path, CylToolError},
+    ChartInfo, CylheimDiffMeta, CylheimDiffOption, CylheimProjectConfig, CylheimThemeColor,
+    CylheimThemeIcon,
+};
 use serde::{Deserialize, Serialize};
-#[derive(Serialize, Deserialize, Debug)]
+use std::{
+    collections::BTreeMap,
+    fs::{self, File},
+    io::{self, Write},
+    path::{Component, Path, PathBuf},
+};
+use zip::{write::SimpleFileOptions, CompressionMethod, ZipWriter};
+
+/// Name of the level meta file inside a `.cytoidlevel` package.
+pub const CYTOID_LEVEL_META_FILE_NAME: &str = "level.json";
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct CylheimLevelMetaConfig {
     schema_version: u32,
     version: u32,
@@ -22,12 +39,12 @@ pub struct CylheimLevelMetaConfig {
     save_path: String,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 struct LevelFilePathConfig {
     #[serde(rename = "path")]
     path: String,
 }
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 struct CylheimLevelChartConfig {
     #[serde(rename = "type")]
     chart_type: String,
@@ -39,7 +56,6 @@ struct CylheimLevelChartConfig {
     #[serde(rename = "VideoPath")]
     video_path: String,
 }
-#[allow(unused)]
 #[derive(Serialize, Deserialize, Debug)]
 pub struct CytoidLevelMetaConfig {
     schema_version: u32,
@@ -66,11 +82,11 @@ pub struct CytoidLevelMetaConfig {
     background: LevelFilePathConfig,
     charts: Vec<CytoidLevelChartConfig>,
 }
-#[allow(unused)]
 #[derive(Debug, Serialize, Deserialize)]
 struct CytoidLevelChartConfig {
     #[serde(rename = "type")]
     chart_type: String,
+    #[serde(default)]
     name: String,
     difficulty: f64,
     path: String,
@@ -79,3 +95,457 @@ struct CytoidLevelChartConfig {
     #[serde(skip_serializing_if = "Option::is_none")]
     storyboard: Option<LevelFilePathConfig>,
 }
+
+fn non_empty(value: &str) -> Option<String> {
+    (!value.is_empty()).then(|| value.to_string())
+}
+fn is_same_cyl_path(a: &str, b: &str) -> bool {
+    a.replace('\\', "/") == b.replace('\\', "/")
+}
+impl CylheimLevelMetaConfig {
+    /// Build the Cytoid `level.json` of this level.
+    ///
+    /// Storyboards and music overrides are taken from the `ChartInfo` with the same chart file.
+    pub fn to_cytoid_level_meta(&self, chart_infos: &[ChartInfo]) -> CytoidLevelMetaConfig {
+        let charts = self
+            .charts
+            .iter()
+            .map(|chart| {
+                let chart_info = chart_infos
+                    .iter()
+                    .find(|chart_info| is_same_cyl_path(&chart_info.chart_path, &chart.path));
+                let music_override = chart_info
+                    .filter(|chart_info| {
+                        !chart_info.song_path.is_empty()
+                            && !is_same_cyl_path(&chart_info.song_path, &self.music.path)
+                    })
+                    .map(|chart_info| LevelFilePathConfig {
+                        path: chart_info.song_path.clone(),
+                    });
+                let storyboard = chart_info
+                    .and_then(|chart_info| chart_info.storyboard_path.as_deref())
+                    .and_then(non_empty)
+                    .map(|path| LevelFilePathConfig { path });
+                CytoidLevelChartConfig {
+                    chart_type: chart.chart_type.clone(),
+                    name: chart.name.clone(),
+                    difficulty: chart.difficulty,
+                    path: chart.path.clone(),
+                    music_override,
+                    storyboard,
+                }
+            })
+            .collect();
+        CytoidLevelMetaConfig {
+            schema_version: self.schema_version,
+            version: self.version,
+            id: self.id.clone(),
+            title: self.title.clone(),
+            title_localized: non_empty(&self.title_localized),
+            artist: self.artist.clone(),
+            artist_localized: non_empty(&self.artist_localized),
+            artist_source: non_empty(&self.artist_source),
+            illustrator: self.illustrator.clone(),
+            illustrator_localized: non_empty(&self.illustrator_localized),
+            illustrator_source: non_empty(&self.illustrator_source),
+            charter: self.charter.clone(),
+            storyboarder: non_empty(&self.storyboarder),
+            music: self.music.clone(),
+            music_preview: self.music_preview.clone(),
+            background: self.background.clone(),
+            charts,
+        }
+    }
+    /// Where the exported package goes: `SavePath`, or `<id>.cytoidlevel` in the project folder.
+    pub fn get_save_path(&self, project_dir: &Path) -> PathBuf {
+        let file_name = format!("{}.cytoidlevel", self.id);
+        if self.save_path.is_empty() {
+            return project_dir.join(file_name);
+        }
+        let save_path = resolve_cyl_path(project_dir, &self.save_path);
+        if save_path.is_dir() {
+            save_path.join(file_name)
+        } else {
+            save_path
+        }
+    }
+}
+impl CytoidLevelChartConfig {
+    fn get_diff_option(&self) -> CylheimDiffOption {
+        match self.chart_type.as_str() {
+            "easy" => CylheimDiffOption::Easy,
+            "hard" => CylheimDiffOption::Hard,
+            "extreme" => CylheimDiffOption::Chaos,
+            chart_type => CylheimDiffOption::Custom(CylheimDiffMeta {
+                diff_name: chart_type.to_uppercase(),
+                ..CylheimDiffOption::Dream.get_meta()
+            }),
+        }
+    }
+}
+impl CytoidLevelMetaConfig {
+    /// Build a Cylheim project with one chart per Cytoid chart and this level as its meta.
+    pub fn to_cylheim_project(&self) -> CylheimProjectConfig {
+        let chart_infos = self
+            .charts
+            .iter()
+            .map(|chart| {
+                let diff_meta = chart.get_diff_option().get_meta();
+                let song_path = chart.music_override.as_ref().unwrap_or(&self.music);
+                ChartInfo {
+                    diff_name: if chart.name.is_empty() {
+                        diff_meta.diff_name
+                    } else {
+                        chart.name.clone()
+                    },
+                    diff_value: chart.difficulty.to_string(),
+                    chart_path: chart.path.clone(),
+                    song_path: song_path.path.clone(),
+                    video_path: String::new(),
+                    background_path: self.background.path.clone(),
+                    icon_path: CylheimThemeIcon::Paff.get_icon_path().to_string(),
+                    song_name: self.title.clone(),
+                    theme_color: CylheimThemeColor::Paff.get_color().to_string(),
+                    diff_text_color: diff_meta.diff_text_color,
+                    diff_background_color: diff_meta.diff_background_color,
+                    storyboard_path: chart
+                        .storyboard
+                        .as_ref()
+                        .map(|storyboard| storyboard.path.clone()),
+                    bookmark: Vec::new(),
+                    generate_event_config: GenerateEventConfig::default(),
+                }
+            })
+            .collect();
+        let level_meta_config = CylheimLevelMetaConfig {
+            schema_version: self.schema_version,
+            version: self.version,
+            id: self.id.clone(),
+            title: self.title.clone(),
+            title_localized: self.title_localized.clone().unwrap_or_default(),
+            artist: self.artist.clone(),
+            artist_localized: self.artist_localized.clone().unwrap_or_default(),
+            artist_source: self.artist_source.clone().unwrap_or_default(),
+            illustrator: self.illustrator.clone(),
+            illustrator_localized: self.illustrator_localized.clone().unwrap_or_default(),
+            illustrator_source: self.illustrator_source.clone().unwrap_or_default(),
+            charter: self.charter.clone(),
+            storyboarder: self.storyboarder.clone().unwrap_or_default(),
+            music: self.music.clone(),
+            music_preview: self.music_preview.clone(),
+            background: self.background.clone(),
+            charts: self
+                .charts
+                .iter()
+                .map(|chart| CylheimLevelChartConfig {
+                    chart_type: chart.chart_type.clone(),
+                    name: chart.name.clone(),
+                    difficulty: chart.difficulty,
+                    path: chart.path.clone(),
+                    generate_new_features_to_storyboard: false,
+                    video_path: String::new(),
+                })
+                .collect(),
+            save_path: String::new(),
+        };
+        CylheimProjectConfig {
+            version: 0,
+            chart_infos,
+            last_open_chart: None,
+            last_edit_time: 0.0,
+            level_meta_config: Some(level_meta_config),
+        }
+    }
+    /// Every file path of the level, as (description, path) pairs.
+    fn get_file_paths(&mut self) -> Vec<(String, &mut String)> {
+        let mut paths = vec![
+            ("music".to_string(), &mut self.music.path),
+            ("music_preview".to_string(), &mut self.music_preview.path),
+            ("background".to_string(), &mut self.background.path),
+        ];
+        for (index, chart) in self.charts.iter_mut().enumerate() {
+            paths.push((format!("charts[{}].path", index), &mut chart.path));
+            if let Some(music_override) = &mut chart.music_override {
+                paths.push((
+                    format!("charts[{}].music_override", index),
+                    &mut music_override.path,
+                ));
+            }
+            if let Some(storyboard) = &mut chart.storyboard {
+                paths.push((
+                    format!("charts[{}].storyboard", index),
+                    &mut storyboard.path,
+                ));
+            }
+        }
+        paths
+    }
+}
+/// Export the project at `cyl_path` as a `.cytoidlevel` package.
+///
+/// The project must have a level meta config. Returns the path of the written package.
+pub fn export_cytoid_level(cyl_path: &Path) -> Result<PathBuf, CylToolError> {
+    let project_dir = cyl_path.parent().unwrap_or(Path::new(""));
+    let f =
+        fs::read_to_string(cyl_path).map_err(|err| CylToolError::from(err).at_path(cyl_path))?;
+    let config: CylheimProjectConfig =
+        serde_json::from_str(&f).map_err(|err| CylToolError::from(err).at_path(cyl_path))?;
+    let level_meta_config = config
+        .level_meta_config
+        .as_ref()
+        .ok_or(CylToolError::MissingLevelMeta)?;
+    let mut level_meta = level_meta_config.to_cytoid_level_meta(&config.chart_infos);
+
+    // level.json 中的路径改写为包内路径
+    let mut entries: BTreeMap<String, PathBuf> = BTreeMap::new();
+    let mut missing_paths: Vec<PathBuf> = Vec::new();
+    for (field, path) in level_meta.get_file_paths() {
+        if path.is_empty() {
+            return Err(CylToolError::EmptyLevelPath { field });
+        }
+        let source = resolve_cyl_path(project_dir, path);
+        if !source.is_file() {
+            if !missing_paths.contains(&PathBuf::from(&*path)) {
+                missing_paths.push(PathBuf::from(&*path));
+            }
+            continue;
+        }
+        let file_name = source
+            .file_name()
+            .map_or("asset".into(), |name| name.to_string_lossy());
+        let mut entry_name = get_entry_name(path).unwrap_or_else(|| file_name.to_string());
+        let mut index = 1;
+        while entries
+            .get(&entry_name)
+            .is_some_and(|existing| *existing != source)
+        {
+            entry_name = format!("{}_{}", index, file_name);
+            index += 1;
+        }
+        *path = entry_name.clone();
+        entries.insert(entry_name, source);
+    }
+    if !missing_paths.is_empty() {
+        return Err(CylToolError::MissingAssets(missing_paths));
+    }
+    let level_json = serde_json::to_string_pretty(&level_meta)?;
+
+    let save_path = level_meta_config.get_save_path(project_dir);
+    if let Some(parent) = save_path.parent() {
+        fs::create_dir_all(parent).map_err(|err| CylToolError::from(err).at_path(parent))?;
+    }
+    let archive_error = |err: CylToolError| err.at_path(&save_path);
+    let options = SimpleFileOptions::default().compression_method(CompressionMethod::Deflated);
+    let mut writer =
+        ZipWriter::new(File::create(&save_path).map_err(|err| archive_error(err.into()))?);
+    writer
+        .start_file(CYTOID_LEVEL_META_FILE_NAME, options)
+        .map_err(|err| archive_error(err.into()))?;
+    writer
+        .write_all(level_json.as_bytes())
+        .map_err(|err| archive_error(err.into()))?;
+    for (entry_name, source) in &entries {
+        writer
+            .start_file(entry_name.as_str(), options)
+            .map_err(|err| archive_error(err.into()))?;
+        let mut file = File::open(source).map_err(|err| CylToolError::from(err).at_path(source))?;
+        io::copy(&mut file, &mut writer).map_err(|err| archive_error(err.into()))?;
+    }
+    writer.finish().map_err(|err| archive_error(err.into()))?;
+    Ok(save_path)
+}
+/// A level id is used as the project file name, so it must be one plain path component.
+fn check_level_id(id: &str) -> Result<(), CylToolError> {
+    let mut components = Path::new(id).components();
+    let is_file_name = matches!(components.next(), Some(Component::Normal(_)))
+        && components.next().is_none()
+        && !id.contains(['/', '\\', ':']);
+    if is_file_name {
+        Ok(())
+    } else {
+        Err(CylToolError::InvalidLevelId { id: id.to_string() })
+    }
+}
+/// Extract the `.cytoidlevel` package at `archive_path` into `output_dir` and write a
+/// Cylheim project for it next to `level.json`.
+///
+/// Returns the path of the written `.cyl` file.
+pub fn import_cytoid_level(
+    archive_path: &Path,
+    output_dir: &Path,
+) -> Result<PathBuf, CylToolError> {
+    // 部分谱面包将文件放在子文件夹中，取最外层的 level.json
+    let level_meta_path = extract_archive(archive_path, output_dir)?
+        .into_iter()
+        .filter(|entry_path| {
+            entry_path
+                .file_name()
+                .is_some_and(|name| name == CYTOID_LEVEL_META_FILE_NAME)
+        })
+        .min_by_key(|entry_path| entry_path.components().count())
+        .map(|entry_path| output_dir.join(entry_path))
+        .ok_or_else(|| CylToolError::MissingArchiveEntry {
+            path: archive_path.to_path_buf(),
+            entry_name: CYTOID_LEVEL_META_FILE_NAME.to_string(),
+        })?;
+    let f = fs::read_to_string(&level_meta_path)
+        .map_err(|err| CylToolError::from(err).at_path(&level_meta_path))?;
+    let level_meta: CytoidLevelMetaConfig = serde_json::from_str(f.trim_start_matches('\u{feff}'))
+        .map_err(|err| CylToolError::from(err).at_path(&level_meta_path))?;
+    check_level_id(&level_meta.id)?;
+    let config = level_meta.to_cylheim_project();
+    let config_json = serde_json::to_string_pretty(&config)?;
+    let cyl_path = level_meta_path.with_file_name(format!("{}.cyl", level_meta.id));
+    fs::write(&cyl_path, config_json).map_err(|err| CylToolError::from(err).at_path(&cyl_path))?;
+    Ok(cyl_path)
+}
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::Read;
+    use zip::ZipArchive;
+    const TEST_RESOURCE_ROOT: &str = "./tests/resources/";
+    const TEST_OUTPUT_ROOT: &str = "./tests/output/";
+    fn build_test_project(project_dir: &Path) -> PathBuf {
+        let _ = fs::remove_dir_all(project_dir);
+        fs::create_dir_all(project_dir.join("song")).unwrap();
+        let f = fs::read_to_string(TEST_RESOURCE_ROOT.to_owned() + "test_cyl_config.cyl").unwrap();
+        let mut config: CylheimProjectConfig = serde_json::from_str(&f).unwrap();
+        let chart_info = config
+            .chart_infos
+            .iter_mut()
+            .find(|chart_info| chart_info.chart_path == "ky_ryuusa_no_arashi2.json")
+            .unwrap();
+        chart_info.song_path = "song\\override.ogg".to_string();
+        chart_info.storyboard_path = Some("storyboard.json".to_string());
+        for path in [
+            "song/流砂の岚.ogg",
+            "song/override.ogg",
+            "bg_ryuusa_no_arashi.png",
+            "ky_ryuusa_no_arashi2.json",
+            "storyboard.json",
+        ] {
+            fs::write(project_dir.join(path), path).unwrap();
+        }
+        let cyl_path = project_dir.join("project.cyl");
+        fs::write(&cyl_path, serde_json::to_string(&config).unwrap()).unwrap();
+        cyl_path
+    }
+    #[test]
+    fn test_export_cytoid_level() {
+        let project_dir = PathBuf::from(TEST_OUTPUT_ROOT).join("cytoid_export");
+        let cyl_path = build_test_project(&project_dir);
+        let save_path = export_cytoid_level(&cyl_path).unwrap();
+        assert_eq!(
+            save_path,
+            project_dir.join("kyr.ryuusa_no_arashi.cytoidlevel")
+        );
+        let mut archive = ZipArchive::new(File::open(&save_path).unwrap()).unwrap();
+        let mut level_json = String::new();
+        archive
+            .by_name(CYTOID_LEVEL_META_FILE_NAME)
+            .unwrap()
+            .read_to_string(&mut level_json)
+            .unwrap();
+        let level_meta: serde_json::Value = serde_json::from_str(&level_json).unwrap();
+        assert!(level_meta.get("title_localized").is_none());
+        assert!(level_meta.get("storyboarder").is_none());
+        assert_eq!(level_meta["music"]["path"], "song/流砂の岚.ogg");
+        assert_eq!(
+            level_meta["charts"][0]["music_override"]["path"],
+            "song/override.ogg"
+        );
+        assert_eq!(
+            level_meta["charts"][0]["storyboard"]["path"],
+            "storyboard.json"
+        );
+        // 音乐与预览共用同一文件
+        assert_eq!(archive.len(), 6);
+    }
+    #[test]
+    fn test_export_cytoid_level_missing_asset() {
+        let project_dir = PathBuf::from(TEST_OUTPUT_ROOT).join("cytoid_export_missing");
+        let _ = fs::remove_dir_all(&project_dir);
+        fs::create_dir_all(&project_dir).unwrap();
+        fs::copy(
+            TEST_RESOURCE_ROOT.to_owned() + "test_cyl_config.cyl",
+            project_dir.join("project.cyl"),
+        )
+        .unwrap();
+        assert_eq!(
+            export_cytoid_level(&project_dir.join("project.cyl"))
+                .unwrap_err()
+                .kind(),
+            "missing_assets"
+        );
+    }
+    #[test]
+    fn test_import_cytoid_level() {
+        let root = PathBuf::from(TEST_OUTPUT_ROOT).join("cytoid_import");
+        let save_path = export_cytoid_level(&build_test_project(&root.join("project"))).unwrap();
+        let import_dir = root.join("imported");
+        let _ = fs::remove_dir_all(&import_dir);
+        let cyl_path = import_cytoid_level(&save_path, &import_dir).unwrap();
+        assert_eq!(cyl_path, import_dir.join("kyr.ryuusa_no_arashi.cyl"));
+
+        let f = fs::read_to_string(&cyl_path).unwrap();
+        let config: CylheimProjectConfig = serde_json::from_str(&f).unwrap();
+        assert_eq!(config.chart_infos.len(), 1);
+        let chart_info = &config.chart_infos[0];
+        assert_eq!(chart_info.diff_name, "CHAOS");
+        assert_eq!(chart_info.diff_value, "12");
+        assert_eq!(
+            chart_info.diff_background_color,
+            CylheimDiffOption::Chaos.get_meta().diff_background_color
+        );
+        assert_eq!(chart_info.song_path, "song/override.ogg");
+        assert_eq!(
+            chart_info.storyboard_path.as_deref(),
+            Some("storyboard.json")
+        );
+        let level_meta_config = config.level_meta_config.as_ref().unwrap();
+        assert_eq!(level_meta_config.id, "kyr.ryuusa_no_arashi");
+        assert_eq!(level_meta_config.title_localized, "");
+        for chart_info in &config.chart_infos {
+            assert!(resolve_cyl_path(&import_dir, &chart_info.chart_path).is_file());
+            assert!(resolve_cyl_path(&import_dir, &chart_info.song_path).is_file());
+        }
+    }
+    #[test]
+    fn test_import_cytoid_level_invalid_id() {
+        let root = PathBuf::from(TEST_OUTPUT_ROOT).join("cytoid_import_invalid_id");
+        let save_path = export_cytoid_level(&build_test_project(&root.join("project"))).unwrap();
+        let mut archive = ZipArchive::new(File::open(&save_path).unwrap()).unwrap();
+        let mut level_json = String::new();
+        archive
+            .by_name(CYTOID_LEVEL_META_FILE_NAME)
+            .unwrap()
+            .read_to_string(&mut level_json)
+            .unwrap();
+        let mut level_meta: serde_json::Value = serde_json::from_str(&level_json).unwrap();
+        for id in [
+            "../../escaped",
+            "nested/level",
+            "..\\escaped",
+            "/tmp/escaped",
+            "..",
+        ] {
+            level_meta["id"] = serde_json::json!(id);
+            let package_path = root.join("malicious.cytoidlevel");
+            let mut zip_writer = ZipWriter::new(File::create(&package_path).unwrap());
+            zip_writer
+                .start_file(CYTOID_LEVEL_META_FILE_NAME, SimpleFileOptions::default())
+                .unwrap();
+            zip_writer
+                .write_all(level_meta.to_string().as_bytes())
+                .unwrap();
+            zip_writer.finish().unwrap();
+            let import_dir = root.join("imported").join("level");
+            let _ = fs::remove_dir_all(&import_dir);
+            let err = import_cytoid_level(&package_path, &import_dir).unwrap_err();
+            assert_eq!(err.kind(), "invalid_level_id", "{}", id);
+        }
+        assert!(!root.join("escaped.cyl").exists());
+        assert!(!root.join("imported").join("escaped.cyl").exists());
+    }
+}
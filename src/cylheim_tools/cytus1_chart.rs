@@ -1,12 +1,17 @@
+use super::chart_event::CylheimChartEventKind;
+use super::cyl_chart::{
+    CylheimChartNote, CylheimChartNoteType, CylheimChartPage, CylheimChartTempo,
+    CylheimChartTickEventList,
+};
 use super::utils::CylToolError;
 use super::CylheimChart;
 use getset::{Getters, MutGetters, Setters};
-use regex::Regex;
 use serde::{Deserialize, Serialize};
 use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::result::Result;
 use std::{fmt, str::FromStr};
-#[derive(Serialize, Deserialize, Debug, Clone, Getters, Setters)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Getters, Setters)]
 
 pub struct Cytus1Chart {
     #[getset(get = "pub", set = "pub")]
@@ -22,7 +27,7 @@ pub struct Cytus1Chart {
     #[getset(get = "pub", set = "pub")]
     links: Vec<Cytus1ChartLink>,
 }
-#[derive(Serialize, Deserialize, Debug, Clone, Getters, Setters)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Getters, Setters)]
 
 pub struct Cytus1ChartNote {
     #[getset(get = "pub", set = "pub")]
@@ -34,7 +39,9 @@ pub struct Cytus1ChartNote {
     #[getset(get = "pub", set = "pub")]
     hold_length: f64,
 }
-#[derive(Serialize, Deserialize, Debug, Clone, Getters, Setters, MutGetters)]
+#[derive(
+    Serialize, Deserialize, Debug, Clone, Default, PartialEq, Getters, Setters, MutGetters,
+)]
 
 pub struct Cytus1ChartLink {
     #[getset(get = "pub", set = "pub", get_mut = "pub")]
@@ -63,17 +70,25 @@ impl Cytus1ChartNote {
         }
     }
 }
-impl Default for Cytus1ChartLink {
-    fn default() -> Self {
-        Self { link: Vec::new() }
+/// A number of the text format: six decimals like other Cytus 1 tools, or the shortest
+/// exact form when six decimals would lose precision.
+struct Cytus1Number(f64);
+impl fmt::Display for Cytus1Number {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let fixed = format!("{:.6}", self.0);
+        if fixed.parse::<f64>() == Ok(self.0) {
+            write!(f, "{}", fixed)
+        } else {
+            write!(f, "{}", self.0)
+        }
     }
 }
 impl fmt::Display for Cytus1Chart {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         writeln!(f, "VERSION {}", self.version)?;
-        writeln!(f, "BPM {:.6}", self.bpm)?;
-        writeln!(f, "PAGE_SHIFT {:.6}", self.page_shift)?;
-        writeln!(f, "PAGE_SIZE {:.6}", self.page_size)?;
+        writeln!(f, "BPM {}", Cytus1Number(self.bpm))?;
+        writeln!(f, "PAGE_SHIFT {}", Cytus1Number(self.page_shift))?;
+        writeln!(f, "PAGE_SIZE {}", Cytus1Number(self.page_size))?;
         for note in &self.notes {
             writeln!(f, "{}", note)?;
         }
@@ -97,93 +112,417 @@ impl fmt::Display for Cytus1ChartNote {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(
             f,
-            "Note\t{}\t{:.6}\t{:.6}\t{:.6}",
-            self.id, self.time, self.x, self.hold_length
+            "NOTE\t{}\t{}\t{}\t{}",
+            self.id,
+            Cytus1Number(self.time),
+            Cytus1Number(self.x),
+            Cytus1Number(self.hold_length)
+        )
+    }
+}
+/// Error raised while parsing a Cytus 1 chart, located by 1-based line and column.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cytus1ParseError {
+    line: usize,
+    column: usize,
+    message: String,
+}
+impl Cytus1ParseError {
+    fn new(line: usize, column: usize, message: String) -> Self {
+        Self {
+            line,
+            column,
+            message,
+        }
+    }
+    pub fn line(&self) -> usize {
+        self.line
+    }
+    pub fn column(&self) -> usize {
+        self.column
+    }
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+}
+impl fmt::Display for Cytus1ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "line {}, column {}: {}",
+            self.line, self.column, self.message
+        )
+    }
+}
+impl std::error::Error for Cytus1ParseError {}
+
+/// A whitespace separated word of a chart line with its 1-based column.
+struct Cytus1Token<'a> {
+    column: usize,
+    text: &'a str,
+}
+fn split_tokens(line: &str) -> Vec<Cytus1Token<'_>> {
+    let mut tokens = Vec::new();
+    let mut start: Option<(usize, usize)> = None;
+    for (column, (offset, c)) in line.char_indices().enumerate() {
+        match (c.is_whitespace(), start) {
+            (false, None) => start = Some((column + 1, offset)),
+            (true, Some((token_column, token_offset))) => {
+                tokens.push(Cytus1Token {
+                    column: token_column,
+                    text: &line[token_offset..offset],
+                });
+                start = None;
+            }
+            _ => {}
+        }
+    }
+    if let Some((column, offset)) = start {
+        tokens.push(Cytus1Token {
+            column,
+            text: &line[offset..],
+        });
+    }
+    tokens
+}
+fn parse_token<T: FromStr>(
+    line: usize,
+    token: &Cytus1Token,
+    name: &str,
+) -> Result<T, Cytus1ParseError> {
+    token.text.parse().map_err(|_| {
+        Cytus1ParseError::new(
+            line,
+            token.column,
+            format!("Invalid {} '{}'.", name, token.text),
         )
+    })
+}
+fn parse_number(line: usize, token: &Cytus1Token, name: &str) -> Result<f64, Cytus1ParseError> {
+    let value: f64 = parse_token(line, token, name)?;
+    if !value.is_finite() {
+        return Err(Cytus1ParseError::new(
+            line,
+            token.column,
+            format!("Invalid {} '{}'.", name, token.text),
+        ));
     }
+    Ok(value)
 }
 impl FromStr for Cytus1Chart {
-    type Err = String;
+    type Err = Cytus1ParseError;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let version_re = Regex::new(r"VERSION (\d+)").map_err(|e| e.to_string())?;
-        let bpm_re = Regex::new(r"BPM ([\d\.]+)").map_err(|e| e.to_string())?;
-        let page_shift_re = Regex::new(r"PAGE_SHIFT ([\d\.]+)").map_err(|e| e.to_string())?;
-        let page_size_re = Regex::new(r"PAGE_SIZE ([\d\.]+)").map_err(|e| e.to_string())?;
-        let note_re = Regex::new(r"NOTE\s+(\d+)\s+([\d\.]+)\s+([\d\.]+)\s+([\d\.]+)")
-            .map_err(|e| e.to_string())?;
-        let link_re = Regex::new(r"LINK((?:\s+\d+)+)").map_err(|e| e.to_string())?;
-
-        let version = version_re
-            .captures(s)
-            .and_then(|cap| cap.get(1))
-            .ok_or("Failed to parse VERSION")?
-            .as_str()
-            .parse::<u32>()
-            .map_err(|e| e.to_string())?;
+        let s = s.strip_prefix('\u{feff}').unwrap_or(s);
+        let mut version: Option<u32> = None;
+        let mut bpm: Option<f64> = None;
+        let mut page_shift: Option<f64> = None;
+        let mut page_size: Option<f64> = None;
+        let mut notes: Vec<Cytus1ChartNote> = Vec::new();
+        let mut note_ids: HashSet<u32> = HashSet::new();
+        let mut links: Vec<(usize, Vec<usize>, Cytus1ChartLink)> = Vec::new();
+        let line_count = s.lines().count();
+        for (line_index, line) in s.lines().enumerate() {
+            let line_number = line_index + 1;
+            let tokens = split_tokens(line);
+            let Some(directive) = tokens.first() else {
+                continue;
+            };
+            let args = &tokens[1..];
+            let expect_args = |count: usize| {
+                if args.len() == count {
+                    return Ok(());
+                }
+                let column = args
+                    .get(count)
+                    .map_or(line.chars().count() + 1, |t| t.column);
+                Err(Cytus1ParseError::new(
+                    line_number,
+                    column,
+                    format!(
+                        "{} expects {} values but got {}.",
+                        directive.text.to_uppercase(),
+                        count,
+                        args.len()
+                    ),
+                ))
+            };
+            let set_header = |value: &mut Option<f64>, name: &str| {
+                expect_args(1)?;
+                if value.is_some() {
+                    return Err(Cytus1ParseError::new(
+                        line_number,
+                        directive.column,
+                        format!("Duplicated {}.", name),
+                    ));
+                }
+                *value = Some(parse_number(line_number, &args[0], name)?);
+                Ok(())
+            };
+            match directive.text.to_uppercase().as_str() {
+                "VERSION" => {
+                    expect_args(1)?;
+                    if version.is_some() {
+                        return Err(Cytus1ParseError::new(
+                            line_number,
+                            directive.column,
+                            "Duplicated VERSION.".to_string(),
+                        ));
+                    }
+                    version = Some(parse_token(line_number, &args[0], "VERSION")?);
+                }
+                "BPM" => set_header(&mut bpm, "BPM")?,
+                "PAGE_SHIFT" => set_header(&mut page_shift, "PAGE_SHIFT")?,
+                "PAGE_SIZE" => set_header(&mut page_size, "PAGE_SIZE")?,
+                "NOTE" => {
+                    expect_args(4)?;
+                    let note = Cytus1ChartNote {
+                        id: parse_token(line_number, &args[0], "note id")?,
+                        time: parse_number(line_number, &args[1], "note time")?,
+                        x: parse_number(line_number, &args[2], "note x")?,
+                        hold_length: parse_number(line_number, &args[3], "hold length")?,
+                    };
+                    if !note_ids.insert(note.id) {
+                        return Err(Cytus1ParseError::new(
+                            line_number,
+                            args[0].column,
+                            format!("Duplicated note id {}.", note.id),
+                        ));
+                    }
+                    notes.push(note);
+                }
+                "LINK" => {
+                    if args.is_empty() {
+                        return Err(Cytus1ParseError::new(
+                            line_number,
+                            line.chars().count() + 1,
+                            "LINK expects at least one note id.".to_string(),
+                        ));
+                    }
+                    let link = Cytus1ChartLink {
+                        link: args
+                            .iter()
+                            .map(|arg| parse_token(line_number, arg, "note id"))
+                            .collect::<Result<_, _>>()?,
+                    };
+                    links.push((
+                        line_number,
+                        args.iter().map(|arg| arg.column).collect(),
+                        link,
+                    ));
+                }
+                _ => {
+                    return Err(Cytus1ParseError::new(
+                        line_number,
+                        directive.column,
+                        format!("Unknown directive '{}'.", directive.text),
+                    ))
+                }
+            }
+        }
+        // 音符可能写在 LINK 之后，因此最后再检查链接
+        for (line_number, columns, link) in &links {
+            for (column, note_id) in columns.iter().zip(link.link.iter()) {
+                if !note_ids.contains(note_id) {
+                    return Err(Cytus1ParseError::new(
+                        *line_number,
+                        *column,
+                        format!("Link refers to an unknown note {}.", note_id),
+                    ));
+                }
+            }
+        }
+        let missing =
+            |name: &str| Cytus1ParseError::new(line_count + 1, 1, format!("Missing {}.", name));
+        Ok(Cytus1Chart {
+            version: version.ok_or_else(|| missing("VERSION"))?,
+            bpm: bpm.ok_or_else(|| missing("BPM"))?,
+            page_shift: page_shift.ok_or_else(|| missing("PAGE_SHIFT"))?,
+            page_size: page_size.ok_or_else(|| missing("PAGE_SIZE"))?,
+            notes,
+            links: links.into_iter().map(|(_, _, link)| link).collect(),
+        })
+    }
+}
 
-        let bpm = bpm_re
-            .captures(s)
-            .and_then(|cap| cap.get(1))
-            .ok_or("Failed to parse BPM")?
-            .as_str()
-            .parse::<f64>()
-            .map_err(|e| e.to_string())?;
+impl Cytus1Chart {
+    /// Tempo of the rebuilt Cylheim chart in whole microseconds per beat.
+    fn get_c2_tempo(&self) -> u32 {
+        // 一页为两拍，因此每拍的微秒数为页长的一半
+        (self.page_size * 1_000_000.0 / 2.0).round() as u32
+    }
+    /// Seconds a page of the rebuilt Cylheim chart is longer than PAGE_SIZE.
+    ///
+    /// Cylheim stores tempos in whole microseconds per beat, so a page size like the
+    /// 0.857143 of 140 BPM cannot be carried exactly. Note times are kept; their page
+    /// positions move by this much per page.
+    pub fn get_page_size_rounding(&self) -> f64 {
+        2.0 * self.get_c2_tempo() as f64 / 1_000_000.0 - self.page_size
+    }
+    /// Rebuild a Cylheim chart from this Cytus 1 chart with `time_base` ticks per beat.
+    ///
+    /// One Cytus 1 page becomes two beats (`2 * time_base` ticks) with alternating scan
+    /// directions. A non-zero PAGE_SHIFT is written as a zero-tempo lead-in plus a
+    /// `#DEFINE CYTUS1` event, which `to_cytus1_chart_with_pageshift(true)` reads back.
+    /// An offset folded into the note times on export comes back as ticks, so exporting again
+    /// gives the same times. Notes before 0 seconds are kept by a negative `start_offset_time`
+    /// of whole page pairs, which leaves every note where it was on screen. The tempo is
+    /// rounded to whole microseconds, see `get_page_size_rounding`.
+    pub fn into_c2chart(self, time_base: u32) -> Result<CylheimChart, CylToolError> {
+        if time_base == 0 {
+            return Err(CylToolError::InvalidTimeBase { time_base });
+        }
+        if self.page_size <= 0.0 || self.page_size.is_nan() {
+            return Err(CylToolError::InvalidPageSize {
+                page_size: self.page_size,
+            });
+        }
+        let tempo = self.get_c2_tempo();
+        let page_ticks = 2 * time_base;
+        let get_second_tick =
+            |second: f64| (second * 1_000_000.0 * time_base as f64 / tempo as f64).round();
+        let shift_tick =
+            (get_second_tick(self.page_shift) as i64).rem_euclid(2 * page_ticks as i64) as u32;
+        // 负时间的音符整体后移若干个两页周期，并以 start_offset_time 抵消
+        let pair_second = 4.0 * tempo as f64 / 1_000_000.0;
+        let first_time = self.notes.iter().map(|note| note.time).fold(0.0, f64::min);
+        let start_offset_time = (first_time / pair_second).floor() * pair_second;
 
-        let page_shift = page_shift_re
-            .captures(s)
-            .and_then(|cap| cap.get(1))
-            .ok_or("Failed to parse PAGE_SHIFT")?
-            .as_str()
-            .parse::<f64>()
-            .map_err(|e| e.to_string())?;
+        let mut note_map: HashMap<u32, CylheimChartNote> = HashMap::new();
+        for note in &self.notes {
+            if note.hold_length < 0.0 {
+                return Err(CylToolError::InvalidNoteTime {
+                    note_id: note.id,
+                    time: note.time,
+                    hold_length: note.hold_length,
+                });
+            }
+            let tick = shift_tick + get_second_tick(note.time - start_offset_time) as u32;
+            if note.x < 0.0 || note.x > 1.0 {
+                return Err(CylToolError::NoteXOutOfRange {
+                    note_id: note.id,
+                    tick,
+                    x: note.x,
+                });
+            }
+            let page_index = tick / page_ticks;
+            let mut c2_note = CylheimChartNote::new(
+                page_index,
+                CylheimChartNoteType::Click,
+                note.id,
+                tick,
+                note.x,
+            );
+            let hold_tick = get_second_tick(note.hold_length) as u32;
+            if hold_tick > 0 {
+                let page_end = (page_index + 1) * page_ticks;
+                c2_note.set_note_type(if tick + hold_tick <= page_end {
+                    CylheimChartNoteType::Hold
+                } else {
+                    CylheimChartNoteType::LongHold
+                });
+                c2_note.set_hold_tick(hold_tick);
+            }
+            if note_map.insert(note.id, c2_note).is_some() {
+                return Err(CylToolError::DuplicateNoteId { note_id: note.id });
+            }
+        }
 
-        let page_size = page_size_re
-            .captures(s)
-            .and_then(|cap| cap.get(1))
-            .ok_or("Failed to parse PAGE_SIZE")?
-            .as_str()
-            .parse::<f64>()
-            .map_err(|e| e.to_string())?;
+        let mut linked_note: HashSet<u32> = HashSet::new();
+        for link in &self.links {
+            for (index, note_id) in link.link.iter().enumerate() {
+                if !linked_note.insert(*note_id) {
+                    return Err(CylToolError::DuplicateLinkNote { note_id: *note_id });
+                }
+                let next_id = link.link.get(index + 1).map_or(-1, |id| *id as i32);
+                let note = note_map
+                    .get_mut(note_id)
+                    .ok_or(CylToolError::UnknownLinkNote { note_id: *note_id })?;
+                note.set_note_type(if index == 0 {
+                    CylheimChartNoteType::Drag
+                } else {
+                    CylheimChartNoteType::DragChild
+                });
+                note.set_hold_tick(0);
+                note.set_next_id(next_id);
+            }
+        }
 
-        let notes = note_re
-            .captures_iter(s)
-            .map(|cap| Cytus1ChartNote {
-                id: cap.get(1).unwrap().as_str().parse().unwrap(),
-                time: cap.get(2).unwrap().as_str().parse().unwrap(),
-                x: cap.get(3).unwrap().as_str().parse().unwrap(),
-                hold_length: cap.get(4).unwrap().as_str().parse().unwrap(),
-            })
-            .collect();
+        let mut note_list: Vec<CylheimChartNote> = note_map.into_values().collect();
+        note_list.sort_by_key(|note| *note.id());
+        let mut tick_count: HashMap<u32, u32> = HashMap::new();
+        for note in &note_list {
+            *tick_count.entry(*note.tick()).or_default() += 1;
+        }
+        for note in &mut note_list {
+            note.set_has_sibling(tick_count[note.tick()] > 1);
+        }
 
-        let links = link_re
-            .captures_iter(s)
-            .map(|cap| Cytus1ChartLink {
-                link: cap
-                    .get(1)
-                    .unwrap()
-                    .as_str()
-                    .split_whitespace()
-                    .map(|x| x.parse().unwrap())
-                    .collect(),
+        let last_tick = note_list
+            .iter()
+            .map(|note| note.tick() + note.hold_tick())
+            .max()
+            .unwrap_or(0);
+        let page_list: Vec<CylheimChartPage> = (0..last_tick / page_ticks + 1)
+            .map(|page_index| {
+                CylheimChartPage::new(
+                    page_index * page_ticks,
+                    (page_index + 1) * page_ticks,
+                    if page_index % 2 == 0 { 1 } else { -1 },
+                )
             })
             .collect();
+        let tempo_list = if shift_tick > 0 {
+            vec![
+                CylheimChartTempo::new(0, 0),
+                CylheimChartTempo::new(shift_tick, tempo),
+            ]
+        } else {
+            vec![CylheimChartTempo::new(0, tempo)]
+        };
+        let event_order_list = vec![CylheimChartTickEventList::new(
+            0,
+            vec![CylheimChartEventKind::new_directive("#DEFINE CYTUS1").into_event()],
+        )];
 
-        Ok(Cytus1Chart {
-            version,
-            bpm,
-            page_shift,
-            page_size,
-            notes,
-            links,
-        })
+        let mut target_chart = CylheimChart::default();
+        target_chart.set_time_base(time_base);
+        target_chart.set_start_offset_time(start_offset_time);
+        target_chart.set_page_list(page_list);
+        target_chart.set_tempo_list(tempo_list);
+        target_chart.set_event_order_list(event_order_list);
+        target_chart.set_note_list(note_list);
+        Ok(target_chart)
     }
-}
-
-impl Cytus1Chart {
-    #[allow(dead_code)]
-    fn into_c2chart(&self) -> Result<CylheimChart, CylToolError> {
-        Err(CylToolError::from("error"))
+    /// Sort notes by (time, x) and renumber them from 0 in that order, remapping every link.
+    /// Returns the old → new id of every note.
+    ///
+    /// Fails without changing the chart on a duplicate id or a link to a missing note.
+    pub fn normalize_note_order(&mut self) -> Result<BTreeMap<u32, u32>, CylToolError> {
+        let mut notes = self.notes.clone();
+        notes.sort_by(|a, b| {
+            a.time
+                .total_cmp(&b.time)
+                .then(a.x.total_cmp(&b.x))
+                .then(a.id.cmp(&b.id))
+        });
+        let mut id_map: BTreeMap<u32, u32> = BTreeMap::new();
+        for (new_id, note) in notes.iter_mut().enumerate() {
+            if id_map.insert(note.id, new_id as u32).is_some() {
+                return Err(CylToolError::DuplicateNoteId { note_id: note.id });
+            }
+            note.id = new_id as u32;
+        }
+        let mut links = self.links.clone();
+        for note_id in links.iter_mut().flat_map(|link| link.link.iter_mut()) {
+            *note_id = *id_map
+                .get(note_id)
+                .ok_or(CylToolError::UnknownLinkNote { note_id: *note_id })?;
+        }
+        links.sort_by(compare_links);
+        self.notes = notes;
+        self.links = links;
+        Ok(id_map)
     }
 }
 pub fn compare_links(a: &Cytus1ChartLink, b: &Cytus1ChartLink) -> Ordering {
@@ -200,6 +539,7 @@ pub fn compare_links(a: &Cytus1ChartLink, b: &Cytus1ChartLink) -> Ordering {
 #[cfg(test)]
 mod test {
     use super::*;
+    use proptest::prelude::*;
     use std::{fs, vec};
     #[test]
     fn test_cytus1_chart_to_string() {
@@ -227,6 +567,18 @@ mod test {
             links: vec![link.clone(), link.clone()],
         };
         println!("{}", chart);
+        // 6 位小数无法表示的值以完整精度写出
+        let note = Cytus1ChartNote::new(0, 1.0 / 3.0, 0.5, 0.1 + 0.2);
+        assert_eq!(
+            note.to_string(),
+            "NOTE\t0\t0.3333333333333333\t0.500000\t0.30000000000000004"
+        );
+        let chart = Cytus1Chart {
+            page_size: 2.0 / 3.0,
+            notes: vec![note],
+            ..Default::default()
+        };
+        assert_eq!(chart.to_string().parse::<Cytus1Chart>().unwrap(), chart);
     }
     #[test]
     fn test_cytus1_chart_from_str() {
@@ -235,4 +587,234 @@ mod test {
         let config: Cytus1Chart = f.parse().expect("Failed to parse config");
         println!("{:#?}", config);
     }
+    #[test]
+    fn test_cytus1_chart_into_c2chart() {
+        let path = "./tests/resources/test_cytus1_chart.txt";
+        let f = fs::read_to_string(path).unwrap();
+        let chart: Cytus1Chart = f.parse().unwrap();
+        let cylchart = chart.clone().into_c2chart(480).unwrap();
+        assert_eq!(cylchart.tempo_list().len(), 2);
+        for page in cylchart.page_list() {
+            assert_eq!(page.get_page_size(), 960);
+        }
+        let back = cylchart.to_cytus1_chart_with_pageshift(true).unwrap();
+        // 往返转换的误差不超过一个 tick
+        let tick_second = chart.page_size / 2.0 / 480.0;
+        assert_eq!(back.notes.len(), chart.notes.len());
+        assert_eq!(back.links.len(), chart.links.len());
+        assert!((back.page_shift - chart.page_shift).abs() <= tick_second);
+        assert!((back.page_size - chart.page_size - chart.get_page_size_rounding()).abs() < 1e-12);
+        for (a, b) in back.notes.iter().zip(chart.notes.iter()) {
+            assert_eq!(a.id, b.id);
+            assert!((a.time - b.time).abs() <= tick_second);
+            assert_eq!(a.x, b.x);
+        }
+    }
+    #[test]
+    fn test_cytus1_chart_into_c2chart_lead_in() {
+        let chart = Cytus1Chart {
+            page_shift: -0.5,
+            page_size: 2.0,
+            notes: vec![
+                Cytus1ChartNote::new(0, -1.0, 0.5, 0.0),
+                Cytus1ChartNote::new(1, 1.0, 0.5, 0.25),
+            ],
+            ..Default::default()
+        };
+        let cylchart = chart.clone().into_c2chart(480).unwrap();
+        assert_eq!(*cylchart.start_offset_time(), -4.0);
+        let back = cylchart.to_cytus1_chart_with_pageshift(true).unwrap();
+        let get_position = |chart: &Cytus1Chart, note: &Cytus1ChartNote| {
+            ((note.time + chart.page_shift) / chart.page_size).rem_euclid(2.0)
+        };
+        for (a, b) in back.notes.iter().zip(chart.notes.iter()) {
+            assert!((a.time - b.time).abs() < 1e-9);
+            assert!((a.hold_length - b.hold_length).abs() < 1e-9);
+            assert!((get_position(&back, a) - get_position(&chart, b)).abs() < 1e-9);
+        }
+    }
+    #[test]
+    fn test_cytus1_chart_into_c2chart_rounded_tempo() {
+        // 这些页长没有整微秒的 tempo，往返后的页长差值由 get_page_size_rounding 给出
+        for (bpm, page_size) in [(140.0, 0.857143), (175.0, 1.371429)] {
+            let chart = Cytus1Chart {
+                bpm,
+                page_size,
+                notes: vec![Cytus1ChartNote::new(0, 60.0, 0.5, 0.0)],
+                ..Default::default()
+            };
+            let rounding = chart.get_page_size_rounding();
+            assert!(rounding != 0.0 && rounding.abs() < 1.000001e-6);
+            let back = chart
+                .into_c2chart(480)
+                .unwrap()
+                .to_cytus1_chart_with_pageshift(false)
+                .unwrap();
+            assert!((back.page_size - (page_size + rounding)).abs() < 1e-12);
+            assert!((back.notes[0].time - 60.0).abs() <= page_size / 2.0 / 480.0);
+        }
+    }
+    #[test]
+    fn test_cytus1_chart_parse_exported() {
+        let path = "./tests/resources/test_c1.txt";
+        let f = fs::read_to_string(path).unwrap();
+        let chart: Cytus1Chart = f.parse().unwrap();
+        assert_eq!(chart.notes.len(), 707);
+        assert_eq!(chart.to_string().parse::<Cytus1Chart>().unwrap(), chart);
+        let crlf = "version 2\r\nbpm\t-120\r\nPage_Shift -0.5\r\n\r\nPAGE_SIZE 2\r\n\
+                    note\t0\t-1.0\t0.5\t0\r\nNote 1 1 0.5 0.25\r\nlink 0\t1\r\n";
+        let chart: Cytus1Chart = crlf.parse().unwrap();
+        assert_eq!(chart.bpm, -120.0);
+        assert_eq!(chart.page_shift, -0.5);
+        assert_eq!(chart.notes[0].time, -1.0);
+        assert_eq!(chart.links[0].link, vec![0, 1]);
+    }
+    #[test]
+    fn test_cytus1_chart_parse_error() {
+        let header = "VERSION 2\nBPM 120\nPAGE_SHIFT 0\nPAGE_SIZE 2\n";
+        let cases = [
+            ("NOTE 0 1.0 0.5 0\nSPEED 2\n", 6, 1),
+            ("NOTE 0 1.0 0.5\n", 5, 15),
+            ("NOTE 0 1.0 abc 0\n", 5, 12),
+            ("NOTE 0 1.0 0.5 0\nNOTE 0 2.0 0.5 0\n", 6, 6),
+            ("NOTE 0 1.0 0.5 0\nLINK 0  7\n", 6, 9),
+        ];
+        for (body, line, column) in cases {
+            let err = format!("{}{}", header, body)
+                .parse::<Cytus1Chart>()
+                .unwrap_err();
+            assert_eq!((err.line(), err.column()), (line, column), "{}", err);
+        }
+        let err = "VERSION 2\nBPM 120\n".parse::<Cytus1Chart>().unwrap_err();
+        assert_eq!(err.line(), 3);
+        match CylToolError::from(err).at_path("chart.txt") {
+            CylToolError::Cytus1Parse {
+                path, line, column, ..
+            } => assert_eq!((path, line, column), (Some("chart.txt".into()), 3, 1)),
+            err => panic!("unexpected error {:?}", err),
+        }
+    }
+    #[test]
+    fn test_cytus1_chart_into_c2chart_error() {
+        let chart = Cytus1Chart {
+            page_size: 2.0,
+            notes: vec![
+                Cytus1ChartNote::new(0, 1.0, 0.5, 0.0),
+                Cytus1ChartNote::new(1, 2.0, 0.5, 0.0),
+            ],
+            ..Default::default()
+        };
+        let into_error_kind =
+            |chart: &Cytus1Chart| chart.clone().into_c2chart(480).unwrap_err().kind();
+        let mut invalid = chart.clone();
+        invalid.page_size = 0.0;
+        assert_eq!(into_error_kind(&invalid), "invalid_page_size");
+        let mut invalid = chart.clone();
+        invalid.notes[1].hold_length = -1.0;
+        assert_eq!(into_error_kind(&invalid), "invalid_note_time");
+        let mut invalid = chart.clone();
+        invalid.notes[1].id = 0;
+        assert_eq!(into_error_kind(&invalid), "duplicate_note_id");
+        assert_eq!(
+            invalid.normalize_note_order().unwrap_err().kind(),
+            "duplicate_note_id"
+        );
+        let mut invalid = chart.clone();
+        invalid.links = vec![Cytus1ChartLink { link: vec![0, 7] }];
+        assert_eq!(into_error_kind(&invalid), "unknown_link_note");
+        assert_eq!(
+            invalid.normalize_note_order().unwrap_err().kind(),
+            "unknown_link_note"
+        );
+        let mut invalid = chart.clone();
+        invalid.links = vec![Cytus1ChartLink {
+            link: vec![0, 1, 0],
+        }];
+        assert_eq!(into_error_kind(&invalid), "duplicate_link_note");
+    }
+    fn cytus1_chart_strategy() -> impl Strategy<Value = Cytus1Chart> {
+        // 混合 6 位小数与任意有限值，两种写法都应精确往返
+        let micro = |range: std::ops::Range<i64>| {
+            prop_oneof![
+                range.prop_map(|value| value as f64 / 1e6),
+                any::<f64>().prop_filter("finite", |value| value.is_finite()),
+            ]
+        };
+        let note = (
+            micro(-1_000_000..600_000_000),
+            micro(0..1_000_001),
+            micro(0..10_000_000),
+        );
+        (
+            0u32..10,
+            micro(1..500_000_000),
+            micro(-5_000_000..5_000_000),
+            micro(1..5_000_000),
+            prop::collection::vec(note, 0..40),
+            prop::collection::vec(
+                prop::collection::vec(any::<prop::sample::Index>(), 1..6),
+                0..6,
+            ),
+        )
+            .prop_map(|(version, bpm, page_shift, page_size, notes, links)| {
+                let notes: Vec<Cytus1ChartNote> = notes
+                    .into_iter()
+                    .enumerate()
+                    .map(|(id, (time, x, hold_length))| {
+                        Cytus1ChartNote::new(id as u32 * 3, time, x, hold_length)
+                    })
+                    .collect();
+                let links = if notes.is_empty() {
+                    Vec::new()
+                } else {
+                    links
+                        .into_iter()
+                        .map(|link| Cytus1ChartLink {
+                            link: link.iter().map(|index| index.get(&notes).id).collect(),
+                        })
+                        .collect()
+                };
+                Cytus1Chart {
+                    version,
+                    bpm,
+                    page_shift,
+                    page_size,
+                    notes,
+                    links,
+                }
+            })
+    }
+    proptest! {
+        #[test]
+        fn test_cytus1_chart_round_trip(chart in cytus1_chart_strategy()) {
+            let parsed: Cytus1Chart = chart.to_string().parse().unwrap();
+            prop_assert_eq!(parsed, chart);
+        }
+        #[test]
+        fn test_cytus1_chart_normalize_note_order(chart in cytus1_chart_strategy()) {
+            let mut normalized = chart.clone();
+            let id_map = normalized.normalize_note_order().unwrap();
+            prop_assert!(normalized.notes.iter().enumerate().all(|(i, note)| note.id == i as u32));
+            prop_assert!(normalized.notes.windows(2).all(|pair| pair[0].time <= pair[1].time));
+            // 每条连线仍连接原来的音符
+            let get_note = |chart: &Cytus1Chart, id: u32| {
+                chart.notes.iter().find(|note| note.id == id).cloned().map(|mut note| {
+                    note.id = 0;
+                    note
+                })
+            };
+            let mut old_links: Vec<Vec<u32>> = chart
+                .links
+                .iter()
+                .map(|link| link.link.iter().map(|id| id_map[id]).collect())
+                .collect();
+            old_links.sort();
+            let new_links: Vec<Vec<u32>> =
+                normalized.links.iter().map(|link| link.link.clone()).collect();
+            prop_assert_eq!(old_links, new_links);
+            for (old_id, new_id) in id_map {
+                prop_assert_eq!(get_note(&chart, old_id), get_note(&normalized, new_id));
+            }
+        }
+    }
 }
@@ -1,15 +1,53 @@
+pub mod batch_convert;
+pub mod chart_directive;
+pub mod chart_event;
+pub mod chart_id_map;
+pub mod chart_lint;
+pub mod chart_page;
+pub mod chart_repair;
+pub mod chart_retime;
+pub mod chart_stats;
+pub mod chart_transform;
 pub mod chart_viewer;
 pub mod cyl_chart;
 pub mod cyl_config;
+pub mod cyl_packup;
 pub mod cyl_theme;
 pub mod cytoid_level;
 pub mod cytus1_chart;
+pub mod tempo_map;
 pub mod utils;
 #[allow(unused)]
-pub use cyl_chart::CylheimChart;
+pub use chart_directive::{
+    CylheimChartDirective, CylheimChartDirectives, DirectiveWarning, ExportTarget,
+};
+#[allow(unused)]
+pub use chart_event::CylheimChartEventKind;
+#[allow(unused)]
+pub use chart_id_map::{NoteIdMap, NoteIdPair, SyncedX};
+#[allow(unused)]
+pub use chart_lint::{CylheimChartLintReport, LintIssue, LintIssueKind, LintSeverity};
+#[allow(unused)]
+pub use chart_page::ScanDirectionMode;
+#[allow(unused)]
+pub use chart_repair::{CylheimChartRepairOptions, RepairChange};
+#[allow(unused)]
+pub use chart_retime::{InexactTickStrategy, QuantizedTick, TickLocation};
+#[allow(unused)]
+pub use chart_stats::{CylheimChartStats, Cytus1ChartStats};
+#[allow(unused)]
+pub use cyl_chart::{
+    group_position_drifts_by_page, ClampedNote, ClickDragMapping, ConversionOptions,
+    ConversionReport, CylheimChart, CylheimChartNoteType, Cytus1NoteMapping, DropNoteMapping,
+    DroppedNote, FlickMapping, LongHoldMapping, NotePositionDrift, NoteSubstitute,
+    NoteSubstitution, PagePositionDrift, PageShiftSource, PageSizeStrategy, RenumberedNote,
+    TempoStrategy, UnsupportedNoteStrategy,
+};
 #[allow(unused)]
 pub use cyl_config::{ChartInfo, CylheimProjectConfig, GenerateEventConfig};
 #[allow(unused)]
 pub use cyl_theme::{CylheimDiffMeta, CylheimDiffOption, CylheimThemeColor, CylheimThemeIcon};
 #[allow(unused)]
 pub use cytoid_level::{CylheimLevelMetaConfig, CytoidLevelMetaConfig};
+#[allow(unused)]
+pub use tempo_map::{Rational, TempoMap};
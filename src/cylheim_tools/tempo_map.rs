@@ -0,0 +1,348 @@
+use super::{cyl_chart::CylheimChartTempo, utils::CylToolError, CylheimChart};
+use std::{
+    cmp::Ordering,
+    fmt,
+    ops::{Add, Div, Mul, Neg, Sub},
+};
+
+/// An exact fraction, always stored in lowest terms with a positive denominator.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Rational {
+    numerator: i128,
+    denominator: i128,
+}
+fn gcd(mut a: i128, mut b: i128) -> i128 {
+    while b != 0 {
+        (a, b) = (b, a % b);
+    }
+    a.abs()
+}
+impl Rational {
+    pub const ZERO: Rational = Rational {
+        numerator: 0,
+        denominator: 1,
+    };
+    /// Panics when `denominator` is zero.
+    pub fn new(numerator: i128, denominator: i128) -> Self {
+        assert!(denominator != 0, "Rational with zero denominator.");
+        let sign = denominator.signum();
+        let divisor = gcd(numerator, denominator).max(1);
+        Self {
+            numerator: sign * numerator / divisor,
+            denominator: sign * denominator / divisor,
+        }
+    }
+    pub fn numerator(&self) -> i128 {
+        self.numerator
+    }
+    pub fn denominator(&self) -> i128 {
+        self.denominator
+    }
+    pub fn is_integer(&self) -> bool {
+        self.denominator == 1
+    }
+    pub fn floor(&self) -> i128 {
+        self.numerator.div_euclid(self.denominator)
+    }
+    /// Nearest integer, rounding halves away from zero.
+    pub fn round(&self) -> i128 {
+        let doubled = Rational::new(
+            2 * self.numerator.abs() + self.denominator,
+            2 * self.denominator,
+        );
+        self.numerator.signum() * doubled.floor()
+    }
+    pub fn to_f64(&self) -> f64 {
+        self.numerator as f64 / self.denominator as f64
+    }
+}
+impl From<i128> for Rational {
+    fn from(value: i128) -> Self {
+        Rational::new(value, 1)
+    }
+}
+impl From<u32> for Rational {
+    fn from(value: u32) -> Self {
+        Rational::new(value as i128, 1)
+    }
+}
+impl Add for Rational {
+    type Output = Rational;
+    fn add(self, rhs: Rational) -> Rational {
+        Rational::new(
+            self.numerator * rhs.denominator + rhs.numerator * self.denominator,
+            self.denominator * rhs.denominator,
+        )
+    }
+}
+impl Sub for Rational {
+    type Output = Rational;
+    fn sub(self, rhs: Rational) -> Rational {
+        self + -rhs
+    }
+}
+impl Neg for Rational {
+    type Output = Rational;
+    fn neg(self) -> Rational {
+        Rational {
+            numerator: -self.numerator,
+            denominator: self.denominator,
+        }
+    }
+}
+impl Mul for Rational {
+    type Output = Rational;
+    fn mul(self, rhs: Rational) -> Rational {
+        Rational::new(
+            self.numerator * rhs.numerator,
+            self.denominator * rhs.denominator,
+        )
+    }
+}
+impl Div for Rational {
+    type Output = Rational;
+    /// Panics when `rhs` is zero.
+    fn div(self, rhs: Rational) -> Rational {
+        Rational::new(
+            self.numerator * rhs.denominator,
+            self.denominator * rhs.numerator,
+        )
+    }
+}
+impl Ord for Rational {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (self.numerator * other.denominator).cmp(&(other.numerator * self.denominator))
+    }
+}
+impl PartialOrd for Rational {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+impl fmt::Display for Rational {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.is_integer() {
+            write!(f, "{}", self.numerator)
+        } else {
+            write!(f, "{}/{}", self.numerator, self.denominator)
+        }
+    }
+}
+
+const MICROSECONDS_PER_SECOND: i128 = 1_000_000;
+/// One tempo segment, starting at `tick` and lasting until the next one.
+#[derive(Debug, Clone, PartialEq)]
+struct TempoSegment {
+    tick: u32,
+    /// Microseconds per beat. Zero makes the segment take no time.
+    value: u32,
+    /// Seconds from tick 0 to `tick`.
+    start_time: Rational,
+}
+/// Exact tick ↔ time conversion over every tempo change of a chart.
+///
+/// Times are kept as `Rational` seconds, so converting back and forth never drifts.
+/// Ticks before the first tempo take no time, the same as a zero tempo.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TempoMap {
+    time_base: u32,
+    start_offset_time: f64,
+    segments: Vec<TempoSegment>,
+}
+impl TempoMap {
+    /// `tempo_list` does not need to be sorted. Fails when `time_base` is zero.
+    pub(crate) fn new(
+        time_base: u32,
+        tempo_list: &[CylheimChartTempo],
+        start_offset_time: f64,
+    ) -> Result<Self, CylToolError> {
+        if time_base == 0 {
+            return Err(CylToolError::InvalidTimeBase { time_base });
+        }
+        let mut tempo_list = tempo_list.to_vec();
+        tempo_list.sort_by_key(|tempo| *tempo.tick());
+        let mut segments: Vec<TempoSegment> = Vec::new();
+        for tempo in tempo_list {
+            let start_time = match segments.last() {
+                Some(previous) => {
+                    previous.start_time
+                        + Self::get_span_time(
+                            time_base,
+                            previous.value,
+                            *tempo.tick() - previous.tick,
+                        )
+                }
+                None => Rational::ZERO,
+            };
+            segments.push(TempoSegment {
+                tick: *tempo.tick(),
+                value: *tempo.value(),
+                start_time,
+            });
+        }
+        Ok(Self {
+            time_base,
+            start_offset_time,
+            segments,
+        })
+    }
+    fn get_span_time(time_base: u32, value: u32, ticks: u32) -> Rational {
+        Rational::new(
+            ticks as i128 * value as i128,
+            time_base as i128 * MICROSECONDS_PER_SECOND,
+        )
+    }
+    pub fn time_base(&self) -> u32 {
+        self.time_base
+    }
+    pub fn start_offset_time(&self) -> f64 {
+        self.start_offset_time
+    }
+    /// Tempo segment active at `tick`, if any tempo starts at or before it.
+    fn get_segment(&self, tick: Rational) -> Option<&TempoSegment> {
+        self.segments
+            .iter()
+            .rev()
+            .find(|segment| Rational::from(segment.tick) <= tick)
+    }
+    /// Exact seconds from tick 0 to `tick`, without `start_offset_time`.
+    pub fn get_tick_time(&self, tick: u32) -> Rational {
+        self.get_fractional_tick_time(tick.into())
+    }
+    /// Same as `get_tick_time`, for a tick between two integer ticks.
+    pub fn get_fractional_tick_time(&self, tick: Rational) -> Rational {
+        match self.get_segment(tick) {
+            Some(segment) => {
+                segment.start_time
+                    + (tick - segment.tick.into())
+                        * Rational::new(
+                            segment.value as i128,
+                            self.time_base as i128 * MICROSECONDS_PER_SECOND,
+                        )
+            }
+            None => Rational::ZERO,
+        }
+    }
+    /// Exact tick reached `time` seconds after tick 0, without `start_offset_time`.
+    ///
+    /// Inside a zero tempo every tick shares one time; the last of them is returned.
+    /// Returns None when the chart has no non-zero tempo.
+    pub fn get_time_tick(&self, time: Rational) -> Option<Rational> {
+        let mut playing = self.segments.iter().filter(|segment| segment.value > 0);
+        let first = playing.clone().next()?;
+        // 早于第一个非零 tempo 的时间按该 tempo 向前外推
+        let segment = playing
+            .rfind(|segment| segment.start_time <= time)
+            .unwrap_or(first);
+        Some(
+            Rational::from(segment.tick)
+                + (time - segment.start_time)
+                    * Rational::new(
+                        self.time_base as i128 * MICROSECONDS_PER_SECOND,
+                        segment.value as i128,
+                    ),
+        )
+    }
+    /// Song position of `tick` in seconds, `start_offset_time` included.
+    pub fn get_tick_time_second(&self, tick: u32) -> f64 {
+        self.start_offset_time + self.get_tick_time(tick).to_f64()
+    }
+    /// Tick at song position `second`, `start_offset_time` included.
+    pub fn get_second_tick(&self, second: f64) -> Option<f64> {
+        let mut playing = self.segments.iter().filter(|segment| segment.value > 0);
+        let first = playing.clone().next()?;
+        let time = second - self.start_offset_time;
+        let segment = playing
+            .rfind(|segment| segment.start_time.to_f64() <= time)
+            .unwrap_or(first);
+        Some(
+            segment.tick as f64
+                + (time - segment.start_time.to_f64()) * self.time_base as f64 * 1_000_000.0
+                    / segment.value as f64,
+        )
+    }
+    /// Exact seconds between `start_tick` and `end_tick`.
+    pub fn get_duration(&self, start_tick: u32, end_tick: u32) -> Rational {
+        self.get_tick_time(end_tick) - self.get_tick_time(start_tick)
+    }
+    /// Microseconds per beat at `tick`, or None before the first tempo.
+    pub fn get_tempo_value_at_tick(&self, tick: u32) -> Option<u32> {
+        self.get_segment(tick.into()).map(|segment| segment.value)
+    }
+    /// Beats per minute at `tick`, or None before the first tempo or inside a zero tempo.
+    pub fn get_bpm_at_tick(&self, tick: u32) -> Option<f64> {
+        self.get_tempo_value_at_tick(tick)
+            .filter(|value| *value > 0)
+            .map(|value| 60_000_000.0 / value as f64)
+    }
+}
+impl CylheimChart {
+    pub fn get_tempo_map(&self) -> Result<TempoMap, CylToolError> {
+        TempoMap::new(
+            *self.time_base(),
+            self.tempo_list(),
+            *self.start_offset_time(),
+        )
+    }
+}
+#[cfg(test)]
+mod test {
+    use super::*;
+    fn build_tempo_map() -> TempoMap {
+        // 120 BPM，第 960 tick 起 90 BPM，第 1920 tick 起 150 BPM
+        TempoMap::new(
+            480,
+            &[
+                CylheimChartTempo::new(1920, 400_000),
+                CylheimChartTempo::new(0, 500_000),
+                CylheimChartTempo::new(960, 666_667),
+            ],
+            1.5,
+        )
+        .unwrap()
+    }
+    #[test]
+    fn test_tempo_map_tick_time() {
+        let tempo_map = build_tempo_map();
+        assert_eq!(tempo_map.get_tick_time(960), Rational::from(1u32));
+        assert_eq!(
+            tempo_map.get_tick_time(1920),
+            Rational::new(1_333_334, 1_000_000) + Rational::from(1u32)
+        );
+        assert_eq!(tempo_map.get_tick_time_second(0), 1.5);
+        assert_eq!(tempo_map.get_bpm_at_tick(100), Some(120.0));
+        assert_eq!(tempo_map.get_bpm_at_tick(2000), Some(150.0));
+        let tick = tempo_map.get_second_tick(3.5).unwrap();
+        assert!((tempo_map.get_tick_time_second(tick.round() as u32) - 3.5).abs() < 1e-3);
+    }
+    #[test]
+    fn test_tempo_map_round_trip() {
+        let tempo_map = build_tempo_map();
+        for tick in (0..4000).step_by(7) {
+            let mut current = Rational::from(tick as u32);
+            for _ in 0..100 {
+                let time = tempo_map.get_fractional_tick_time(current);
+                current = tempo_map.get_time_tick(time).unwrap();
+            }
+            assert_eq!(current, Rational::from(tick as u32));
+        }
+    }
+    #[test]
+    fn test_tempo_map_zero_tempo() {
+        let tempo_map = TempoMap::new(
+            480,
+            &[
+                CylheimChartTempo::new(0, 0),
+                CylheimChartTempo::new(240, 500_000),
+            ],
+            0.0,
+        )
+        .unwrap();
+        assert_eq!(tempo_map.get_tick_time(120), Rational::ZERO);
+        assert_eq!(tempo_map.get_bpm_at_tick(120), None);
+        assert_eq!(
+            tempo_map.get_time_tick(Rational::ZERO),
+            Some(Rational::from(240u32))
+        );
+    }
+}
@@ -1,35 +1,459 @@
+use super::{
+    chart_directive::ExportTarget, chart_retime::QuantizedTick, cyl_chart::CylheimChartNoteType,
+    cytus1_chart::Cytus1ParseError,
+};
 use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
 
+/// Errors of the Cylheim tools.
+///
+/// Variants carry the note, tick and page they refer to, so callers can match on them and
+/// build their own messages.
 #[derive(Debug)]
-pub struct CylToolError {
-    message: String,
+pub enum CylToolError {
+    /// A note type the target format cannot express.
+    InvalidNoteType {
+        note_id: u32,
+        tick: u32,
+        note_type: CylheimChartNoteType,
+    },
+    /// A note x outside `[0, 1]`.
+    NoteXOutOfRange { note_id: u32, tick: u32, x: f64 },
+    /// A note on a page that does not exist.
+    InvalidPageIndex {
+        note_id: u32,
+        tick: u32,
+        page_index: u32,
+    },
+    /// A page with a non-positive size, or one that differs from `expected_size`.
+    PageSizeMismatch {
+        page_index: u32,
+        page_size: i32,
+        expected_size: i32,
+    },
+    /// The chart has no page.
+    MissingPage,
+    /// The chart has no tempo.
+    MissingTempo,
+    /// A tempo the target format cannot express.
+    TempoMismatch { tick: u32, value: u32 },
+    /// The `#DEFINE CYTUS1` event is required but missing.
+    MissingCytus1Flag,
+    /// A drag note whose `next_id` points at a missing note or back into its own chain.
+    BrokenDragChain { note_id: u32, next_id: i32 },
+    /// Ticks with no exact position at the target time base, each with where it would snap.
+    InexactTick {
+        time_base: u32,
+        quantized_ticks: Vec<QuantizedTick>,
+    },
+    /// Two tempos that would fall on the same tick.
+    TempoCollision { tick: u32, other_tick: u32 },
+    /// A note listed in a note id map but missing from one of the charts.
+    MissingMappedNote { cylheim_id: u32, cytus1_id: u32 },
+    /// A Cytus 1 page size that is not a positive number.
+    InvalidPageSize { page_size: f64 },
+    /// A Cytus 1 note with a negative time or hold length.
+    InvalidNoteTime {
+        note_id: u32,
+        time: f64,
+        hold_length: f64,
+    },
+    /// Two notes sharing one id.
+    DuplicateNoteId { note_id: u32 },
+    /// A Cytus 1 link referring to a note that does not exist.
+    UnknownLinkNote { note_id: u32 },
+    /// A Cytus 1 note appearing in more than one link, or twice in one.
+    DuplicateLinkNote { note_id: u32 },
+    /// A Cytus 1 chart that cannot be parsed, located by 1-based line and column.
+    Cytus1Parse {
+        path: Option<PathBuf>,
+        line: usize,
+        column: usize,
+        message: String,
+    },
+    /// Project assets that do not exist, as written in the project.
+    MissingAssets(Vec<PathBuf>),
+    Io {
+        path: Option<PathBuf>,
+        source: io::Error,
+    },
+    Json {
+        path: Option<PathBuf>,
+        source: serde_json::Error,
+    },
+    Archive {
+        path: Option<PathBuf>,
+        source: zip::result::ZipError,
+    },
+    /// Two different files that would be stored under one archive entry.
+    DuplicateArchiveEntry {
+        entry_name: String,
+        path: PathBuf,
+        other_path: PathBuf,
+    },
+    /// A time base of zero ticks per beat.
+    InvalidTimeBase { time_base: u32 },
+    /// A level id that cannot be used as a file name.
+    InvalidLevelId { id: String },
+    /// An archive without an entry it must contain.
+    MissingArchiveEntry { path: PathBuf, entry_name: String },
+    /// An archive entry whose name would be extracted outside the output folder.
+    UnsafeArchiveEntry { path: PathBuf, entry_name: String },
+    /// A page index past the last page.
+    PageNotFound { page_index: u32 },
+    /// A tick that would move past `u32::MAX`.
+    TickOverflow { tick: u32, delta: u32 },
+    /// A deleted drag head whose chain goes on outside the deleted page.
+    DragHeadDeletion { note_id: u32, child_id: u32 },
+    /// A tick that is not the start or end of a page.
+    NotPageBoundary { tick: u32 },
+    /// A tick that is not strictly inside the page it should split.
+    TickOutsidePage { tick: u32, page_index: u32 },
+    /// The last page, which has no next page to merge with.
+    LastPage { page_index: u32 },
+    /// A note in the part of a page that would be cut off.
+    NoteAfterPageEnd { note_id: u32, page_index: u32 },
+    /// A tick without a tempo.
+    UnknownTempo { tick: u32 },
+    /// A tick that already has a tempo.
+    DuplicateTempo { tick: u32 },
+    /// The first tempo, which cannot be removed.
+    FirstTempoRemoval { tick: u32 },
+    /// An x range that is not inside `[0, 1]` or runs backwards.
+    InvalidXRange { low: f64, high: f64 },
+    /// A playback rate that is not a positive number.
+    InvalidRate { rate: f64 },
+    /// A note that would move outside the pages.
+    NoteOutsidePages { note_id: u32 },
+    /// A note that would start before 0 seconds.
+    NoteBeforeStart { note_id: u32, time: f64 },
+    /// A chart whose export directive names another format.
+    ExportTargetMismatch { export_target: ExportTarget },
+    /// A path that should be a directory but is not.
+    NotADirectory { path: PathBuf },
+    /// A tick shift asked of a Cytus 1 chart, which has no ticks.
+    Cytus1TickShift,
+    /// A Cylheim project without a level meta config.
+    MissingLevelMeta,
+    /// A level meta field with an empty path.
+    EmptyLevelPath { field: String },
 }
 impl CylToolError {
-    fn new(msg: &str) -> Self {
-        CylToolError {
-            message: msg.to_string(),
+    /// Attach the file an I/O, JSON, archive or Cytus 1 parse error happened on.
+    pub fn at_path(mut self, file_path: impl Into<PathBuf>) -> Self {
+        match &mut self {
+            CylToolError::Io { path, .. }
+            | CylToolError::Json { path, .. }
+            | CylToolError::Archive { path, .. }
+            | CylToolError::Cytus1Parse { path, .. } => *path = Some(file_path.into()),
+            _ => {}
+        }
+        self
+    }
+    /// Stable snake_case name of the variant, for scripts.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            CylToolError::InvalidNoteType { .. } => "invalid_note_type",
+            CylToolError::NoteXOutOfRange { .. } => "note_x_out_of_range",
+            CylToolError::InvalidPageIndex { .. } => "invalid_page_index",
+            CylToolError::PageSizeMismatch { .. } => "page_size_mismatch",
+            CylToolError::MissingPage => "missing_page",
+            CylToolError::MissingTempo => "missing_tempo",
+            CylToolError::TempoMismatch { .. } => "tempo_mismatch",
+            CylToolError::MissingCytus1Flag => "missing_cytus1_flag",
+            CylToolError::BrokenDragChain { .. } => "broken_drag_chain",
+            CylToolError::InexactTick { .. } => "inexact_tick",
+            CylToolError::TempoCollision { .. } => "tempo_collision",
+            CylToolError::MissingMappedNote { .. } => "missing_mapped_note",
+            CylToolError::InvalidPageSize { .. } => "invalid_page_size",
+            CylToolError::InvalidNoteTime { .. } => "invalid_note_time",
+            CylToolError::DuplicateNoteId { .. } => "duplicate_note_id",
+            CylToolError::UnknownLinkNote { .. } => "unknown_link_note",
+            CylToolError::DuplicateLinkNote { .. } => "duplicate_link_note",
+            CylToolError::Cytus1Parse { .. } => "cytus1_parse",
+            CylToolError::MissingAssets(_) => "missing_assets",
+            CylToolError::DuplicateArchiveEntry { .. } => "duplicate_archive_entry",
+            CylToolError::InvalidTimeBase { .. } => "invalid_time_base",
+            CylToolError::InvalidLevelId { .. } => "invalid_level_id",
+            CylToolError::MissingArchiveEntry { .. } => "missing_archive_entry",
+            CylToolError::UnsafeArchiveEntry { .. } => "unsafe_archive_entry",
+            CylToolError::Io { .. } => "io",
+            CylToolError::Json { .. } => "json",
+            CylToolError::Archive { .. } => "archive",
+            CylToolError::PageNotFound { .. } => "page_not_found",
+            CylToolError::TickOverflow { .. } => "tick_overflow",
+            CylToolError::DragHeadDeletion { .. } => "drag_head_deletion",
+            CylToolError::NotPageBoundary { .. } => "not_page_boundary",
+            CylToolError::TickOutsidePage { .. } => "tick_outside_page",
+            CylToolError::LastPage { .. } => "last_page",
+            CylToolError::NoteAfterPageEnd { .. } => "note_after_page_end",
+            CylToolError::UnknownTempo { .. } => "unknown_tempo",
+            CylToolError::DuplicateTempo { .. } => "duplicate_tempo",
+            CylToolError::FirstTempoRemoval { .. } => "first_tempo_removal",
+            CylToolError::InvalidXRange { .. } => "invalid_x_range",
+            CylToolError::InvalidRate { .. } => "invalid_rate",
+            CylToolError::NoteOutsidePages { .. } => "note_outside_pages",
+            CylToolError::NoteBeforeStart { .. } => "note_before_start",
+            CylToolError::ExportTargetMismatch { .. } => "export_target_mismatch",
+            CylToolError::NotADirectory { .. } => "not_a_directory",
+            CylToolError::Cytus1TickShift => "cytus1_tick_shift",
+            CylToolError::MissingLevelMeta => "missing_level_meta",
+            CylToolError::EmptyLevelPath { .. } => "empty_level_path",
         }
     }
 }
 
 impl fmt::Display for CylToolError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{}", self.message)
+        let write_path = |f: &mut fmt::Formatter, path: &Option<PathBuf>| match path {
+            Some(path) => write!(f, "{}: ", path.display()),
+            None => Ok(()),
+        };
+        match self {
+            CylToolError::InvalidNoteType {
+                note_id, note_type, ..
+            } => write!(f, "Note {} have an invalid type {}.", note_id, note_type),
+            CylToolError::NoteXOutOfRange { note_id, x, .. } => {
+                write!(f, "Note {} have an invalid x {}.", note_id, x)
+            }
+            CylToolError::InvalidPageIndex {
+                note_id,
+                page_index,
+                ..
+            } => write!(
+                f,
+                "Note {} have an invalid page index {}.",
+                note_id, page_index
+            ),
+            CylToolError::PageSizeMismatch {
+                page_index,
+                page_size,
+                ..
+            } => write!(
+                f,
+                "Page {} have an invalid pagesize {}.",
+                page_index, page_size
+            ),
+            CylToolError::MissingPage => write!(f, "Chart has no page."),
+            CylToolError::MissingTempo => write!(f, "Chart has no tempo."),
+            CylToolError::TempoMismatch { tick, value } => {
+                write!(f, "Tick {} have an invalid tempo {}.", tick, value)
+            }
+            CylToolError::MissingCytus1Flag => write!(f, "Cannot find cytus1_flag."),
+            CylToolError::BrokenDragChain { note_id, next_id } => {
+                write!(f, "Note {} have an invalid next note {}.", note_id, next_id)
+            }
+            CylToolError::InexactTick {
+                time_base,
+                quantized_ticks,
+            } => {
+                let ticks: Vec<String> = quantized_ticks
+                    .iter()
+                    .map(|quantized_tick| quantized_tick.old_tick().to_string())
+                    .collect();
+                write!(
+                    f,
+                    "Ticks {} cannot be placed exactly at time base {}.",
+                    ticks.join(", "),
+                    time_base
+                )
+            }
+            CylToolError::TempoCollision { tick, other_tick } => write!(
+                f,
+                "Tempos at tick {} and {} would fall on the same tick.",
+                tick, other_tick
+            ),
+            CylToolError::MissingMappedNote {
+                cylheim_id,
+                cytus1_id,
+            } => write!(
+                f,
+                "Note {} mapped to Cytus 1 note {} is missing.",
+                cylheim_id, cytus1_id
+            ),
+            CylToolError::InvalidPageSize { page_size } => {
+                write!(f, "Invalid page size {}.", page_size)
+            }
+            CylToolError::InvalidNoteTime {
+                note_id,
+                time,
+                hold_length,
+            } => write!(
+                f,
+                "Note {} have an invalid time {} or hold length {}.",
+                note_id, time, hold_length
+            ),
+            CylToolError::DuplicateNoteId { note_id } => {
+                write!(f, "Note {} is duplicated.", note_id)
+            }
+            CylToolError::UnknownLinkNote { note_id } => {
+                write!(f, "Link refers to an unknown note {}.", note_id)
+            }
+            CylToolError::DuplicateLinkNote { note_id } => {
+                write!(f, "Note {} is linked more than once.", note_id)
+            }
+            CylToolError::Cytus1Parse {
+                path,
+                line,
+                column,
+                message,
+            } => {
+                write_path(f, path)?;
+                write!(f, "line {}, column {}: {}", line, column, message)
+            }
+            CylToolError::MissingAssets(paths) => {
+                let paths: Vec<String> = paths
+                    .iter()
+                    .map(|path| path.display().to_string())
+                    .collect();
+                write!(f, "Missing assets: {}.", paths.join(", "))
+            }
+            CylToolError::DuplicateArchiveEntry {
+                entry_name,
+                path,
+                other_path,
+            } => write!(
+                f,
+                "{} and {} would both be packed as {}.",
+                path.display(),
+                other_path.display(),
+                entry_name
+            ),
+            CylToolError::InvalidTimeBase { time_base } => {
+                write!(f, "Invalid time base {}.", time_base)
+            }
+            CylToolError::InvalidLevelId { id } => {
+                write!(f, "Level id {:?} cannot be used as a file name.", id)
+            }
+            CylToolError::MissingArchiveEntry { path, entry_name } => {
+                write!(f, "{} does not contain {}.", path.display(), entry_name)
+            }
+            CylToolError::UnsafeArchiveEntry { path, entry_name } => write!(
+                f,
+                "{} have an entry {} with an unsafe path.",
+                path.display(),
+                entry_name
+            ),
+            CylToolError::Io { path, source } => {
+                write_path(f, path)?;
+                write!(f, "{}", source)
+            }
+            CylToolError::Json { path, source } => {
+                write_path(f, path)?;
+                write!(f, "{}", source)
+            }
+            CylToolError::Archive { path, source } => {
+                write_path(f, path)?;
+                write!(f, "{}", source)
+            }
+            CylToolError::PageNotFound { page_index } => {
+                write!(f, "Page {} does not exist.", page_index)
+            }
+            CylToolError::TickOverflow { tick, delta } => {
+                write!(f, "Tick {} cannot move {} ticks later.", tick, delta)
+            }
+            CylToolError::DragHeadDeletion { note_id, child_id } => write!(
+                f,
+                "Note {} cannot be deleted without its drag child {}.",
+                note_id, child_id
+            ),
+            CylToolError::NotPageBoundary { tick } => {
+                write!(f, "Tick {} is not a page boundary.", tick)
+            }
+            CylToolError::TickOutsidePage { tick, page_index } => {
+                write!(f, "Tick {} is not inside page {}.", tick, page_index)
+            }
+            CylToolError::LastPage { page_index } => {
+                write!(f, "Page {} is the last page.", page_index)
+            }
+            CylToolError::NoteAfterPageEnd {
+                note_id,
+                page_index,
+            } => write!(
+                f,
+                "Note {} lies after the new end of page {}.",
+                note_id, page_index
+            ),
+            CylToolError::UnknownTempo { tick } => write!(f, "No tempo at tick {}.", tick),
+            CylToolError::DuplicateTempo { tick } => {
+                write!(f, "Tick {} already has a tempo.", tick)
+            }
+            CylToolError::FirstTempoRemoval { tick } => {
+                write!(f, "The first tempo at tick {} cannot be removed.", tick)
+            }
+            CylToolError::InvalidXRange { low, high } => {
+                write!(f, "Invalid x range {} to {}.", low, high)
+            }
+            CylToolError::InvalidRate { rate } => write!(f, "Invalid playback rate {}.", rate),
+            CylToolError::NoteOutsidePages { note_id } => {
+                write!(f, "Note {} would move outside the pages.", note_id)
+            }
+            CylToolError::NoteBeforeStart { note_id, time } => write!(
+                f,
+                "Note {} would start before 0 seconds, at {}.",
+                note_id, time
+            ),
+            CylToolError::ExportTargetMismatch { export_target } => {
+                write!(f, "Chart is marked for export to {:?}.", export_target)
+            }
+            CylToolError::NotADirectory { path } => {
+                write!(f, "{} is not a directory.", path.display())
+            }
+            CylToolError::Cytus1TickShift => {
+                write!(f, "Cytus 1 charts cannot be shifted by ticks.")
+            }
+            CylToolError::MissingLevelMeta => {
+                write!(f, "Project does not have a level meta config.")
+            }
+            CylToolError::EmptyLevelPath { field } => {
+                write!(f, "Level field {} has an empty path.", field)
+            }
+        }
     }
 }
 
-impl std::error::Error for CylToolError {}
+impl std::error::Error for CylToolError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            CylToolError::Io { source, .. } => Some(source),
+            CylToolError::Json { source, .. } => Some(source),
+            CylToolError::Archive { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
 
-// 实现从 String 类型转换为 CylToolError 类型
-impl From<String> for CylToolError {
-    fn from(msg: String) -> Self {
-        CylToolError { message: msg }
+impl From<io::Error> for CylToolError {
+    fn from(source: io::Error) -> Self {
+        CylToolError::Io { path: None, source }
     }
 }
 
-// 实现从 &str 类型转换为 CylToolError 类型
-impl From<&str> for CylToolError {
-    fn from(msg: &str) -> Self {
-        CylToolError::new(msg)
+impl From<serde_json::Error> for CylToolError {
+    fn from(source: serde_json::Error) -> Self {
+        CylToolError::Json { path: None, source }
     }
 }
+
+impl From<zip::result::ZipError> for CylToolError {
+    fn from(source: zip::result::ZipError) -> Self {
+        CylToolError::Archive { path: None, source }
+    }
+}
+
+impl From<Cytus1ParseError> for CylToolError {
+    fn from(err: Cytus1ParseError) -> Self {
+        CylToolError::Cytus1Parse {
+            path: None,
+            line: err.line(),
+            column: err.column(),
+            message: err.message().to_string(),
+        }
+    }
+}
+
+/// Resolve a path stored in a Cylheim project, which uses `\` as separator on Windows.
+pub fn resolve_cyl_path(project_dir: &Path, path: &str) -> PathBuf {
+    project_dir.join(path.replace('\\', "/"))
+}
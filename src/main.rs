@@ -1,2 +1,776 @@
-mod cylheim_tools;
-fn main() {}
+use clap::{Parser, Subcommand, ValueEnum};
+use cyl_package_tool::cylheim_tools::{
+    batch_convert::batch_convert_directory,
+    chart_id_map::{get_note_id_map_path, NoteIdMap},
+    chart_repair::CylheimChartRepairOptions,
+    cyl_packup::{pack_cyl_project, unpack_cyl_project},
+    cytoid_level::{export_cytoid_level, import_cytoid_level},
+    cytus1_chart::{Cytus1Chart, Cytus1ParseError},
+    utils::{resolve_cyl_path, CylToolError},
+    ClickDragMapping, ConversionOptions, ConversionReport, CylheimChart, CylheimProjectConfig,
+    Cytus1NoteMapping, DropNoteMapping, FlickMapping, LongHoldMapping, PageShiftSource,
+    PageSizeStrategy, ScanDirectionMode, TempoStrategy, UnsupportedNoteStrategy,
+};
+use serde_json::json;
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    process::ExitCode,
+};
+
+/// Headless access to the Cytus toolkit.
+#[derive(Parser)]
+#[command(version, about)]
+struct Cli {
+    /// Output format of the command result.
+    #[arg(long, value_enum, global = true, default_value_t = OutputFormat::Text)]
+    format: OutputFormat,
+    #[command(subcommand)]
+    command: Command,
+}
+#[derive(Copy, Clone, PartialEq, Eq, ValueEnum)]
+enum OutputFormat {
+    Text,
+    Json,
+}
+#[derive(Subcommand)]
+enum Command {
+    /// Convert a Cylheim chart to a Cytus 1 chart.
+    ///
+    /// The note id map is written next to the output as `<name>.ids.json`.
+    Convert {
+        #[arg(short, long)]
+        input: PathBuf,
+        #[arg(short, long)]
+        output: PathBuf,
+        /// Also write the conversion report as JSON to this file.
+        #[arg(long)]
+        report: Option<PathBuf>,
+        #[command(flatten)]
+        options: ConvertArgs,
+    },
+    /// Convert every Cylheim chart under a folder, mirroring it into the output folder.
+    Batch {
+        #[arg(short, long)]
+        input: PathBuf,
+        #[arg(short, long)]
+        output: PathBuf,
+        #[command(flatten)]
+        options: ConvertArgs,
+    },
+    /// Bundle a Cylheim project (.cyl) and its assets into one archive.
+    Pack {
+        #[arg(short, long)]
+        input: PathBuf,
+        #[arg(short, long)]
+        output: PathBuf,
+    },
+    /// Extract a project archive into a folder.
+    Unpack {
+        #[arg(short, long)]
+        input: PathBuf,
+        #[arg(short, long)]
+        output: PathBuf,
+    },
+    /// Export a Cylheim project (.cyl) with a level meta config as a .cytoidlevel package.
+    ExportLevel {
+        #[arg(short, long)]
+        input: PathBuf,
+    },
+    /// Extract a .cytoidlevel package into a folder and create a Cylheim project for it.
+    ImportLevel {
+        #[arg(short, long)]
+        input: PathBuf,
+        #[arg(short, long)]
+        output: PathBuf,
+    },
+    /// Check that a Cylheim chart (.json) or a Cytus 1 chart (.txt) is well formed.
+    ///
+    /// Cylheim charts are linted and test-converted to Cytus 1.
+    Validate {
+        #[arg(short, long)]
+        input: PathBuf,
+        #[command(flatten)]
+        options: ConvertArgs,
+    },
+    /// Fix common structural problems of a Cylheim chart and list every change.
+    Repair {
+        #[arg(short, long)]
+        input: PathBuf,
+        #[arg(short, long)]
+        output: PathBuf,
+        #[command(flatten)]
+        options: RepairArgs,
+    },
+    /// Sort the notes of a Cylheim chart (.json) or a Cytus 1 chart (.txt) by time and
+    /// renumber them from 0.
+    Normalize {
+        #[arg(short, long)]
+        input: PathBuf,
+        #[arg(short, long)]
+        output: PathBuf,
+    },
+    /// Mirror, compress, speed up or shift a Cylheim chart (.json) or a Cytus 1 chart (.txt).
+    ///
+    /// Transforms run in the order mirror, compress, rate, shift.
+    Transform {
+        #[arg(short, long)]
+        input: PathBuf,
+        #[arg(short, long)]
+        output: PathBuf,
+        #[command(flatten)]
+        options: TransformArgs,
+    },
+    /// Insert, delete, split, merge or resize pages of a Cylheim chart.
+    Page {
+        #[arg(short, long)]
+        input: PathBuf,
+        #[arg(short, long)]
+        output: PathBuf,
+        /// Leave the scan direction of existing pages unchanged.
+        #[arg(long)]
+        keep_direction: bool,
+        #[command(subcommand)]
+        action: PageAction,
+    },
+    /// Copy note x between a Cylheim chart and its Cytus 1 export through their note id map.
+    SyncX {
+        /// The Cylheim chart (.json).
+        #[arg(long)]
+        cylheim: PathBuf,
+        /// The Cytus 1 chart (.txt).
+        #[arg(long)]
+        cytus1: PathBuf,
+        /// The note id map, `<cytus1 name>.ids.json` by default.
+        #[arg(long)]
+        id_map: Option<PathBuf>,
+        /// The chart receiving the x values.
+        #[arg(long, value_enum)]
+        to: SyncTarget,
+        /// Where to write the updated chart.
+        #[arg(short, long)]
+        output: PathBuf,
+    },
+    /// Print statistics of a Cylheim chart (.json) or a Cytus 1 chart (.txt).
+    Stats {
+        #[arg(short, long)]
+        input: PathBuf,
+    },
+    /// Show the charts and assets of a Cylheim project (.cyl).
+    Project {
+        #[arg(short, long)]
+        input: PathBuf,
+    },
+}
+#[derive(Copy, Clone, PartialEq, Eq, ValueEnum)]
+enum SyncTarget {
+    Cylheim,
+    Cytus1,
+}
+#[derive(Subcommand)]
+enum PageAction {
+    /// Insert an empty page at a page boundary, moving later pages back.
+    Insert {
+        #[arg(long)]
+        tick: u32,
+        #[arg(long)]
+        size: u32,
+    },
+    /// Delete a page and its notes, moving later pages forward.
+    Delete {
+        #[arg(long)]
+        page: u32,
+    },
+    /// Split a page in two at a tick inside it.
+    Split {
+        #[arg(long)]
+        page: u32,
+        #[arg(long)]
+        tick: u32,
+    },
+    /// Merge a page with the page after it.
+    Merge {
+        #[arg(long)]
+        page: u32,
+    },
+    /// Change the size of a page, moving later pages by the difference.
+    Resize {
+        #[arg(long)]
+        page: u32,
+        #[arg(long)]
+        size: u32,
+    },
+}
+#[derive(clap::Args)]
+struct ConvertArgs {
+    /// Require the `#DEFINE CYTUS1` event in the chart.
+    #[arg(long)]
+    use_flag: bool,
+    /// Reference tempo: first, longest, most-notes or a tick number.
+    #[arg(long, value_parser = parse_tempo_strategy, default_value = "most-notes")]
+    tempo_strategy: TempoStrategy,
+    /// Accept pages of different sizes and move notes onto Cytus 1's fixed page.
+    #[arg(long)]
+    reflow: bool,
+    /// Page shift: auto (from the chart's directive), computed, or a number of seconds.
+    #[arg(long, value_parser = parse_page_shift_source, default_value = "auto")]
+    page_shift: PageShiftSource,
+    /// Notes Cytus 1 cannot show: error or drop. Defaults to the chart's directive.
+    #[arg(long, value_parser = parse_unsupported_note_strategy)]
+    unsupported_note: Option<UnsupportedNoteStrategy>,
+    /// Clamp note x into [0, 1] instead of refusing the chart.
+    #[arg(long)]
+    clamp_x: bool,
+    /// Number the exported notes from 0 in time order.
+    #[arg(long)]
+    renumber_ids: bool,
+    /// Export LongHold notes as: unsupported, hold or click.
+    #[arg(long, default_value = "unsupported")]
+    long_hold: LongHoldMapping,
+    /// Export Flick notes as: unsupported or click.
+    #[arg(long, default_value = "unsupported")]
+    flick: FlickMapping,
+    /// Export ClickDrag chains as: unsupported, link or click-and-link.
+    #[arg(long, default_value = "unsupported")]
+    click_drag: ClickDragMapping,
+    /// Export DropClick and DropDrag notes as: unsupported or click.
+    #[arg(long, default_value = "unsupported")]
+    drop_note: DropNoteMapping,
+}
+impl ConvertArgs {
+    fn get_page_size_strategy(&self) -> PageSizeStrategy {
+        if self.reflow {
+            PageSizeStrategy::Reflow
+        } else {
+            PageSizeStrategy::Strict
+        }
+    }
+    fn get_conversion_options(&self) -> ConversionOptions {
+        ConversionOptions {
+            use_flag: self.use_flag,
+            page_shift_source: self.page_shift.clone(),
+            tempo_strategy: self.tempo_strategy.clone(),
+            page_size_strategy: self.get_page_size_strategy(),
+            unsupported_note_strategy: self.unsupported_note,
+            note_mapping: Cytus1NoteMapping {
+                long_hold: self.long_hold,
+                flick: self.flick,
+                click_drag: self.click_drag,
+                drop_note: self.drop_note,
+            },
+            clamp_x: self.clamp_x,
+            renumber_ids: self.renumber_ids,
+        }
+    }
+    fn convert(
+        &self,
+        chart: &CylheimChart,
+    ) -> Result<(Cytus1Chart, ConversionReport), CylToolError> {
+        chart.to_cytus1_chart_with_options(&self.get_conversion_options())
+    }
+}
+#[derive(clap::Args)]
+struct TransformArgs {
+    /// Flip notes horizontally.
+    #[arg(long)]
+    mirror: bool,
+    /// Squeeze x into the range from LOW to HIGH.
+    #[arg(long, num_args = 2, value_names = ["LOW", "HIGH"])]
+    compress_x: Option<Vec<f64>>,
+    /// Playback rate, 2 plays twice as fast.
+    #[arg(long)]
+    rate: Option<f64>,
+    /// Move notes later by this many ticks, keeping the pages. Cylheim charts only.
+    #[arg(long, allow_hyphen_values = true)]
+    shift_ticks: Option<i64>,
+    /// Move the chart later by this many seconds.
+    #[arg(long, allow_hyphen_values = true)]
+    shift_seconds: Option<f64>,
+}
+#[derive(clap::Args)]
+struct RepairArgs {
+    /// Apply every fix below.
+    #[arg(long)]
+    all: bool,
+    /// Sort notes, tempos and events by tick.
+    #[arg(long)]
+    sort_lists: bool,
+    /// Move notes outside their page to the page containing their tick.
+    #[arg(long)]
+    recompute_page_index: bool,
+    /// Unlink drag notes pointing to missing notes.
+    #[arg(long)]
+    drop_dangling_next_id: bool,
+    /// Recompute is_forward of drag children.
+    #[arg(long)]
+    fix_is_forward: bool,
+    /// Clamp note x into [0, 1].
+    #[arg(long)]
+    clamp_x: bool,
+    /// Recompute has_sibling from notes sharing a tick.
+    #[arg(long)]
+    recompute_has_sibling: bool,
+}
+impl RepairArgs {
+    fn get_repair_options(&self) -> CylheimChartRepairOptions {
+        if self.all {
+            return CylheimChartRepairOptions::all();
+        }
+        CylheimChartRepairOptions {
+            sort_lists: self.sort_lists,
+            recompute_page_index: self.recompute_page_index,
+            drop_dangling_next_id: self.drop_dangling_next_id,
+            fix_is_forward: self.fix_is_forward,
+            clamp_x: self.clamp_x,
+            recompute_has_sibling: self.recompute_has_sibling,
+        }
+    }
+}
+fn parse_page_shift_source(arg: &str) -> Result<PageShiftSource, String> {
+    match arg {
+        "auto" => Ok(PageShiftSource::Auto),
+        "computed" => Ok(PageShiftSource::Computed),
+        seconds => match seconds.parse::<f64>() {
+            Ok(page_shift) if page_shift.is_finite() => Ok(PageShiftSource::Fixed(page_shift)),
+            _ => Err(format!("invalid page shift '{}'", seconds)),
+        },
+    }
+}
+fn parse_unsupported_note_strategy(arg: &str) -> Result<UnsupportedNoteStrategy, String> {
+    match arg {
+        "error" => Ok(UnsupportedNoteStrategy::Error),
+        "drop" => Ok(UnsupportedNoteStrategy::Drop),
+        strategy => Err(format!("unknown unsupported note strategy '{}'", strategy)),
+    }
+}
+fn parse_tempo_strategy(arg: &str) -> Result<TempoStrategy, String> {
+    match arg {
+        "first" => Ok(TempoStrategy::First),
+        "longest" => Ok(TempoStrategy::Longest),
+        "most-notes" => Ok(TempoStrategy::MostNotes),
+        tick => tick
+            .parse()
+            .map(TempoStrategy::AtTick)
+            .map_err(|_| format!("unknown tempo strategy '{}'", tick)),
+    }
+}
+
+fn read_file(path: &Path) -> Result<String, CylToolError> {
+    fs::read_to_string(path).map_err(|err| CylToolError::from(err).at_path(path))
+}
+fn read_cylheim_chart(path: &Path) -> Result<CylheimChart, CylToolError> {
+    serde_json::from_str(&read_file(path)?).map_err(|err| CylToolError::from(err).at_path(path))
+}
+fn read_cytus1_chart(path: &Path) -> Result<Cytus1Chart, CylToolError> {
+    read_file(path)?
+        .parse()
+        .map_err(|err: Cytus1ParseError| CylToolError::from(err).at_path(path))
+}
+fn is_cytus1_chart(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("txt"))
+}
+
+/// Machine readable result of a command with its text rendering.
+struct CommandOutput {
+    value: serde_json::Value,
+    text: String,
+    /// False when the command finished but some of its work failed.
+    success: bool,
+}
+impl CommandOutput {
+    fn new(value: serde_json::Value, text: String) -> Self {
+        Self {
+            value,
+            text,
+            success: true,
+        }
+    }
+}
+fn run(command: &Command) -> Result<CommandOutput, CylToolError> {
+    match command {
+        Command::Convert {
+            input,
+            output,
+            report: report_path,
+            options,
+        } => {
+            let chart = read_cylheim_chart(input)?;
+            let (cytus1_chart, report) = options.convert(&chart)?;
+            fs::write(output, cytus1_chart.to_string())
+                .map_err(|err| CylToolError::from(err).at_path(output))?;
+            let id_map_path = get_note_id_map_path(output);
+            let id_map = NoteIdMap::from_conversion(&chart, &report);
+            fs::write(&id_map_path, serde_json::to_string_pretty(&id_map)?)
+                .map_err(|err| CylToolError::from(err).at_path(&id_map_path))?;
+            if let Some(report_path) = report_path {
+                fs::write(report_path, serde_json::to_string_pretty(&report)?)
+                    .map_err(|err| CylToolError::from(err).at_path(report_path))?;
+            }
+            let mut lines = report.get_messages();
+            lines.push(format!(
+                "Converted {} notes to {}.",
+                cytus1_chart.notes().len(),
+                output.display(),
+            ));
+            let value = json!({
+                "input": input,
+                "output": output,
+                "note_count": cytus1_chart.notes().len(),
+                "note_id_map": id_map_path,
+                "lossless": report.is_lossless(),
+                "report": report,
+            });
+            let text = lines.join("\n");
+            Ok(CommandOutput::new(value, text))
+        }
+        Command::Batch {
+            input,
+            output,
+            options,
+        } => {
+            let summary =
+                batch_convert_directory(input, output, &options.get_conversion_options())?;
+            let mut lines: Vec<String> = summary
+                .entries()
+                .iter()
+                .filter_map(|entry| {
+                    let error = entry.error().as_ref()?;
+                    Some(format!("{}: {}", entry.input().display(), error))
+                })
+                .collect();
+            lines.push(format!(
+                "{} charts converted, {} failed.",
+                summary.success_count(),
+                summary.failure_count()
+            ));
+            let value = serde_json::to_value(&summary)?;
+            Ok(CommandOutput {
+                success: summary.failure_count() == 0,
+                ..CommandOutput::new(value, lines.join("\n"))
+            })
+        }
+        Command::Pack { input, output } => {
+            pack_cyl_project(input, output)?;
+            let text = format!("Packed {} into {}.", input.display(), output.display());
+            Ok(CommandOutput::new(
+                json!({ "input": input, "output": output }),
+                text,
+            ))
+        }
+        Command::Unpack { input, output } => {
+            let cyl_path = unpack_cyl_project(input, output)?;
+            let text = format!("Unpacked {} to {}.", input.display(), cyl_path.display());
+            Ok(CommandOutput::new(
+                json!({ "input": input, "project": cyl_path }),
+                text,
+            ))
+        }
+        Command::ExportLevel { input } => {
+            let save_path = export_cytoid_level(input)?;
+            let text = format!("Exported {} to {}.", input.display(), save_path.display());
+            Ok(CommandOutput::new(
+                json!({ "input": input, "output": save_path }),
+                text,
+            ))
+        }
+        Command::ImportLevel { input, output } => {
+            let cyl_path = import_cytoid_level(input, output)?;
+            let text = format!("Imported {} as {}.", input.display(), cyl_path.display());
+            Ok(CommandOutput::new(
+                json!({ "input": input, "project": cyl_path }),
+                text,
+            ))
+        }
+        Command::Validate { input, options } => {
+            if is_cytus1_chart(input) {
+                let cytus1_chart = read_cytus1_chart(input)?;
+                cytus1_chart.clone().into_c2chart(480)?;
+                let page_size_rounding = cytus1_chart.get_page_size_rounding();
+                let mut text = format!("{} is a valid Cytus 1 chart.", input.display());
+                if page_size_rounding != 0.0 {
+                    text += &format!(
+                        "\nPAGE_SIZE moves by {:.1} microseconds per page in Cylheim, which \
+                         stores whole microseconds per beat.",
+                        page_size_rounding * 1_000_000.0
+                    );
+                }
+                let value = json!({
+                    "input": input,
+                    "valid": true,
+                    "page_size_rounding": page_size_rounding,
+                });
+                return Ok(CommandOutput::new(value, text));
+            }
+            let chart = read_cylheim_chart(input)?;
+            let lint_report = chart.lint();
+            let mut lines: Vec<String> = lint_report
+                .issues()
+                .iter()
+                .map(|issue| issue.to_string())
+                .collect();
+            let mut value = json!({ "input": input, "issues": lint_report.issues() });
+            let converted = match options.convert(&chart) {
+                Ok((_, report)) => {
+                    lines.extend(report.get_messages());
+                    lines.push(format!("{} can be converted to Cytus 1.", input.display()));
+                    value["report"] = json!(report);
+                    true
+                }
+                Err(err) => {
+                    lines.push(format!(
+                        "{} cannot be converted to Cytus 1: {}",
+                        input.display(),
+                        err
+                    ));
+                    value["error"] = json!(err.to_string());
+                    value["kind"] = json!(err.kind());
+                    false
+                }
+            };
+            let valid = converted && !lint_report.has_errors();
+            value["valid"] = json!(valid);
+            Ok(CommandOutput {
+                success: valid,
+                ..CommandOutput::new(value, lines.join("\n"))
+            })
+        }
+        Command::Repair {
+            input,
+            output,
+            options,
+        } => {
+            let mut chart = read_cylheim_chart(input)?;
+            let changes = chart.repair(&options.get_repair_options());
+            fs::write(output, serde_json::to_string(&chart)?)
+                .map_err(|err| CylToolError::from(err).at_path(output))?;
+            let mut lines: Vec<String> = changes.iter().map(|change| change.to_string()).collect();
+            lines.push(format!(
+                "{} changes written to {}.",
+                changes.len(),
+                output.display()
+            ));
+            let value = json!({ "input": input, "output": output, "changes": changes });
+            Ok(CommandOutput::new(value, lines.join("\n")))
+        }
+        Command::Normalize { input, output } => {
+            let (id_map, content) = if is_cytus1_chart(input) {
+                let mut cytus1_chart = read_cytus1_chart(input)?;
+                (
+                    cytus1_chart.normalize_note_order()?,
+                    cytus1_chart.to_string(),
+                )
+            } else {
+                let mut chart = read_cylheim_chart(input)?;
+                (
+                    chart.normalize_note_order()?,
+                    serde_json::to_string(&chart)?,
+                )
+            };
+            fs::write(output, content).map_err(|err| CylToolError::from(err).at_path(output))?;
+            let renumbered_count = id_map
+                .iter()
+                .filter(|(old_id, new_id)| old_id != new_id)
+                .count();
+            let text = format!(
+                "Renumbered {} of {} notes, written to {}.",
+                renumbered_count,
+                id_map.len(),
+                output.display()
+            );
+            let value = json!({ "input": input, "output": output, "id_map": id_map });
+            Ok(CommandOutput::new(value, text))
+        }
+        Command::Transform {
+            input,
+            output,
+            options,
+        } => {
+            let content = if is_cytus1_chart(input) {
+                if options.shift_ticks.is_some() {
+                    return Err(CylToolError::Cytus1TickShift);
+                }
+                let mut cytus1_chart = read_cytus1_chart(input)?;
+                if options.mirror {
+                    cytus1_chart.mirror_x();
+                }
+                if let Some(range) = &options.compress_x {
+                    cytus1_chart.compress_x(range[0], range[1])?;
+                }
+                if let Some(rate) = options.rate {
+                    cytus1_chart.change_rate(rate)?;
+                }
+                if let Some(seconds) = options.shift_seconds {
+                    cytus1_chart.shift_seconds(seconds)?;
+                }
+                cytus1_chart.to_string()
+            } else {
+                let mut chart = read_cylheim_chart(input)?;
+                if options.mirror {
+                    chart.mirror_x();
+                }
+                if let Some(range) = &options.compress_x {
+                    chart.compress_x(range[0], range[1])?;
+                }
+                if let Some(rate) = options.rate {
+                    chart.change_rate(rate)?;
+                }
+                if let Some(ticks) = options.shift_ticks {
+                    chart.shift_ticks(ticks)?;
+                }
+                if let Some(seconds) = options.shift_seconds {
+                    chart.shift_seconds(seconds)?;
+                }
+                serde_json::to_string(&chart)?
+            };
+            fs::write(output, content).map_err(|err| CylToolError::from(err).at_path(output))?;
+            let text = format!("Transformed {} into {}.", input.display(), output.display());
+            Ok(CommandOutput::new(
+                json!({ "input": input, "output": output }),
+                text,
+            ))
+        }
+        Command::Page {
+            input,
+            output,
+            keep_direction,
+            action,
+        } => {
+            let mut chart = read_cylheim_chart(input)?;
+            let mode = if *keep_direction {
+                ScanDirectionMode::Keep
+            } else {
+                ScanDirectionMode::Alternate
+            };
+            let mut deleted_notes: Vec<u32> = Vec::new();
+            match action {
+                PageAction::Insert { tick, size } => chart.insert_page(*tick, *size, mode)?,
+                PageAction::Delete { page } => deleted_notes = chart.delete_page(*page, mode)?,
+                PageAction::Split { page, tick } => chart.split_page(*page, *tick, mode)?,
+                PageAction::Merge { page } => chart.merge_pages(*page, mode)?,
+                PageAction::Resize { page, size } => chart.set_page_size(*page, *size)?,
+            }
+            fs::write(output, serde_json::to_string(&chart)?)
+                .map_err(|err| CylToolError::from(err).at_path(output))?;
+            let page_count = *chart.get_stats()?.page_count();
+            let mut text = format!("Wrote {} pages to {}.", page_count, output.display());
+            if !deleted_notes.is_empty() {
+                text += &format!("\n{} notes deleted.", deleted_notes.len());
+            }
+            let value = json!({
+                "input": input,
+                "output": output,
+                "page_count": page_count,
+                "deleted_notes": deleted_notes,
+            });
+            Ok(CommandOutput::new(value, text))
+        }
+        Command::SyncX {
+            cylheim,
+            cytus1,
+            id_map,
+            to,
+            output,
+        } => {
+            let id_map_path = id_map
+                .clone()
+                .unwrap_or_else(|| get_note_id_map_path(cytus1));
+            let id_map: NoteIdMap = serde_json::from_str(&read_file(&id_map_path)?)
+                .map_err(|err| CylToolError::from(err).at_path(&id_map_path))?;
+            let mut chart = read_cylheim_chart(cylheim)?;
+            let mut cytus1_chart = read_cytus1_chart(cytus1)?;
+            let (changes, content) = match to {
+                SyncTarget::Cylheim => {
+                    let changes = id_map.sync_x_to_cylheim(&cytus1_chart, &mut chart)?;
+                    (changes, serde_json::to_string(&chart)?)
+                }
+                SyncTarget::Cytus1 => {
+                    let changes = id_map.sync_x_to_cytus1(&chart, &mut cytus1_chart)?;
+                    (changes, cytus1_chart.to_string())
+                }
+            };
+            fs::write(output, content).map_err(|err| CylToolError::from(err).at_path(output))?;
+            let mut lines: Vec<String> = changes.iter().map(|change| change.to_string()).collect();
+            lines.push(format!(
+                "{} notes moved, written to {}.",
+                changes.len(),
+                output.display()
+            ));
+            let value = json!({ "output": output, "changes": changes });
+            Ok(CommandOutput::new(value, lines.join("\n")))
+        }
+        Command::Stats { input } => {
+            let value = if is_cytus1_chart(input) {
+                serde_json::to_value(read_cytus1_chart(input)?.get_stats())
+            } else {
+                serde_json::to_value(read_cylheim_chart(input)?.get_stats()?)
+            }?;
+            let text = value
+                .as_object()
+                .into_iter()
+                .flatten()
+                .map(|(key, value)| format!("{}: {}", key, value))
+                .collect::<Vec<String>>()
+                .join("\n");
+            Ok(CommandOutput::new(value, text))
+        }
+        Command::Project { input } => {
+            let config: CylheimProjectConfig = serde_json::from_str(&read_file(input)?)
+                .map_err(|err| CylToolError::from(err).at_path(input))?;
+            let project_dir = input.parent().unwrap_or(Path::new(""));
+            let mut lines = vec![format!("{} charts", config.chart_infos.len())];
+            let mut missing_files: Vec<String> = Vec::new();
+            for chart_info in &config.chart_infos {
+                lines.push(format!(
+                    "[{} {}] {} ({})",
+                    chart_info.diff_name,
+                    chart_info.diff_value,
+                    chart_info.chart_path,
+                    chart_info.song_path
+                ));
+                let assets = [
+                    &chart_info.chart_path,
+                    &chart_info.song_path,
+                    &chart_info.video_path,
+                    &chart_info.background_path,
+                ];
+                for asset in assets.into_iter().chain(&chart_info.storyboard_path) {
+                    let asset_path = resolve_cyl_path(project_dir, asset);
+                    if !asset.is_empty() && !asset_path.exists() {
+                        lines.push(format!("  missing {}", asset));
+                        missing_files.push(asset.clone());
+                    }
+                }
+            }
+            let value = json!({ "project": config, "missing_files": missing_files });
+            Ok(CommandOutput::new(value, lines.join("\n")))
+        }
+    }
+}
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+    match run(&cli.command) {
+        Ok(output) => {
+            match cli.format {
+                OutputFormat::Text => println!("{}", output.text),
+                OutputFormat::Json => println!("{}", output.value),
+            }
+            if output.success {
+                ExitCode::SUCCESS
+            } else {
+                ExitCode::FAILURE
+            }
+        }
+        Err(err) => {
+            match cli.format {
+                OutputFormat::Text => eprintln!("error: {}", err),
+                OutputFormat::Json => println!(
+                    "{}",
+                    json!({ "error": err.to_string(), "kind": err.kind() })
+                ),
+            }
+            ExitCode::FAILURE
+        }
+    }
+}
@@ -0,0 +1,90 @@
+use std::{fs, process::Command};
+const TEST_OUTPUT_ROOT: &str = "./tests/output/";
+fn toolkit() -> Command {
+    Command::new(env!("CARGO_BIN_EXE_cyl_package_tool"))
+}
+#[test]
+fn test_cli_convert() {
+    fs::create_dir_all(TEST_OUTPUT_ROOT).unwrap();
+    let output_path = TEST_OUTPUT_ROOT.to_owned() + "cli_converted.txt";
+    let report_path = TEST_OUTPUT_ROOT.to_owned() + "cli_converted_report.json";
+    let output = toolkit()
+        .args(["--format", "json", "convert", "--use-flag"])
+        .args(["-i", "./tests/resources/cylchart_with_cytus1_flag.json"])
+        .args(["-o", &output_path])
+        .args(["--report", &report_path])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let result: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(result["note_count"], 707);
+    let report: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(report_path).unwrap()).unwrap();
+    assert_eq!(report, result["report"]);
+    let synced_path = TEST_OUTPUT_ROOT.to_owned() + "cli_synced.json";
+    let output = toolkit()
+        .args(["--format", "json", "sync-x", "--to", "cylheim"])
+        .args([
+            "--cylheim",
+            "./tests/resources/cylchart_with_cytus1_flag.json",
+        ])
+        .args(["--cytus1", &output_path])
+        .args(["-o", &synced_path])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let result: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(result["changes"], serde_json::json!([]));
+    assert!(fs::read_to_string(output_path)
+        .unwrap()
+        .starts_with("VERSION 2"));
+}
+#[test]
+fn test_cli_error_exit_code() {
+    let output = toolkit()
+        .args(["--format", "json", "validate"])
+        .args(["-i", "./tests/resources/test_cyl_chart.json"])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    let result: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert!(result["error"].as_str().unwrap().contains("pagesize"));
+    assert_eq!(result["kind"], "page_size_mismatch");
+}
+#[test]
+fn test_cli_cytus1_parse_error() {
+    fs::create_dir_all(TEST_OUTPUT_ROOT).unwrap();
+    let input_path = TEST_OUTPUT_ROOT.to_owned() + "cli_invalid_cytus1.txt";
+    fs::write(&input_path, "VERSION 2\nBPM 120\nSPEED 2\n").unwrap();
+    let output = toolkit()
+        .args(["--format", "json", "validate"])
+        .args(["-i", &input_path])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    let result: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(result["kind"], "cytus1_parse");
+    assert!(result["error"]
+        .as_str()
+        .unwrap()
+        .contains("line 3, column 1"));
+}
+#[test]
+fn test_cli_zero_time_base() {
+    fs::create_dir_all(TEST_OUTPUT_ROOT).unwrap();
+    let f = fs::read_to_string("./tests/resources/test_valid_c2to1_chart.json").unwrap();
+    let mut chart: serde_json::Value = serde_json::from_str(&f).unwrap();
+    chart["time_base"] = serde_json::json!(0);
+    let input_path = TEST_OUTPUT_ROOT.to_owned() + "cli_zero_time_base.json";
+    fs::write(&input_path, chart.to_string()).unwrap();
+    for command in ["stats", "validate"] {
+        let output = toolkit()
+            .args(["--format", "json", command])
+            .args(["-i", &input_path])
+            .output()
+            .unwrap();
+        assert!(!output.status.success());
+        let result: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+        assert_eq!(result["kind"], "invalid_time_base", "{}", command);
+    }
+}